@@ -1,19 +1,40 @@
 use std::fs;
 use tauri::{AppHandle, Manager};
-use crate::{diary, config, tags, sync};
+use crate::{diary, config, tags, sync, vault, search, history, notebooks, relocate, conflicts};
 use config::Config;
 use diary::{DiaryEntry, ScheduleEvent};
 use tags::{Tag, TagStat};
-use sync::{SyncStatus, SyncReport, SyncSettings};
+use sync::{SyncStatus, SyncReport, SyncSettings, PlannedAction, SyncHistoryPage, Device, SyncedDevice};
+use vault::{Vault, VaultStatus};
+use search::{SearchQuery, SearchHit};
+use history::{Revision, DiffLine};
+use conflicts::ConflictCopy;
+use notebooks::NotebookInfo;
+use relocate::RelocationReport;
 
 #[tauri::command]
 pub async fn save_diary(date: String, content: String, app: AppHandle) -> Result<(), String> {
     let diary_dir = config::get_diary_dir(&app).await?;
-    let file_path = diary_dir.join(format!("{}.txt", date));
+    let file_name = format!("{}.txt", date);
+    let file_path = diary_dir.join(&file_name);
+    let vault = Vault::open(&app, &diary_dir);
+
+    // History is a safety net; a failed snapshot must not lose the save itself
+    if let Err(e) = history::record(&vault, &diary_dir, &file_name, content.as_bytes(), history::SOURCE_SAVE) {
+        println!("[History] Failed to keep revision of {}: {}", file_name, e);
+    }
 
-    fs::write(&file_path, content)
+    vault.write(&file_path, content.as_bytes())
         .map_err(|e| format!("Failed to save diary: {}", e))?;
 
+    // The entry is saved; a stale index is picked up again on the next search
+    if let Err(e) = search::index_entry(&app, &diary_dir, &file_name, &content) {
+        println!("[Search] Failed to update index: {}", e);
+    }
+
+    sync::dirty::mark(&app, &format!("entries/{}", file_name));
+    sync::scheduler::notify_saved(&app);
+
     Ok(())
 }
 
@@ -23,7 +44,7 @@ pub async fn load_diary(date: String, app: AppHandle) -> Result<Option<String>,
     let file_path = diary_dir.join(format!("{}.txt", date));
 
     if file_path.exists() {
-        let content = fs::read_to_string(&file_path)
+        let content = Vault::open(&app, &diary_dir).read_to_string(&file_path)
             .map_err(|e| format!("Failed to read diary: {}", e))?;
         Ok(Some(content))
     } else {
@@ -45,6 +66,11 @@ pub async fn save_image(
     diary::save_image(filename, data, &app).await
 }
 
+#[tauri::command]
+pub async fn read_image(path: String, app: AppHandle) -> Result<Vec<u8>, String> {
+    diary::read_image(&path, &app).await
+}
+
 #[tauri::command]
 pub async fn export_json(app: AppHandle) -> Result<(), String> {
     use tauri_plugin_dialog::{DialogExt, FilePath};
@@ -119,21 +145,146 @@ pub async fn import_json(app: AppHandle) -> Result<String, String> {
 
         // Import each diary entry
         let diary_dir = config::get_diary_dir(&app).await?;
+        let vault = Vault::open(&app, &diary_dir);
         let mut imported = 0;
 
         for entry in diaries {
-            let file_path = diary_dir.join(format!("{}.txt", entry.date));
-            if fs::write(&file_path, &entry.content).is_ok() {
+            let file_name = format!("{}.txt", entry.date);
+            if let Err(e) = history::record(&vault, &diary_dir, &file_name, entry.content.as_bytes(), history::SOURCE_IMPORT) {
+                println!("[History] Failed to keep revision of {}: {}", file_name, e);
+            }
+            if vault.write(&diary_dir.join(&file_name), entry.content.as_bytes()).is_ok() {
+                sync::dirty::mark(&app, &format!("entries/{}", file_name));
                 imported += 1;
             }
         }
 
+        if let Err(e) = search::refresh(&app, &diary_dir) {
+            println!("[Search] Failed to update index: {}", e);
+        }
+
         Ok(format!("Successfully imported {} diaries", imported))
     } else {
         Err("No file selected".to_string())
     }
 }
 
+#[tauri::command]
+pub async fn search_diaries(query: SearchQuery, app: AppHandle) -> Result<Vec<SearchHit>, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    search::search(&app, &diary_dir, query).await
+}
+
+#[tauri::command]
+pub async fn list_revisions(date: String, app: AppHandle) -> Result<Vec<Revision>, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    history::list(&diary_dir, &format!("{}.txt", date))
+}
+
+/// Diff revision `from` against revision `to`, or against the current entry if `to` is omitted
+#[tauri::command]
+pub async fn diff_revisions(date: String, from: String, to: Option<String>, app: AppHandle) -> Result<Vec<DiffLine>, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    let file_name = format!("{}.txt", date);
+    let vault = Vault::open(&app, &diary_dir);
+
+    let from_content = history::read(&vault, &diary_dir, &file_name, &from)?;
+    let to_content = match to {
+        Some(to) => history::read(&vault, &diary_dir, &file_name, &to)?,
+        None if diary_dir.join(&file_name).exists() => vault.read(&diary_dir.join(&file_name))?,
+        None => Vec::new(),
+    };
+
+    Ok(history::diff(
+        &String::from_utf8_lossy(&from_content),
+        &String::from_utf8_lossy(&to_content),
+    ))
+}
+
+/// Bring back an earlier revision, returning the restored content
+#[tauri::command]
+pub async fn restore_revision(date: String, revision_id: String, app: AppHandle) -> Result<String, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    let file_name = format!("{}.txt", date);
+    let vault = Vault::open(&app, &diary_dir);
+
+    let content = String::from_utf8(history::read(&vault, &diary_dir, &file_name, &revision_id)?)
+        .map_err(|e| format!("Invalid UTF-8 content: {}", e))?;
+
+    // Unlike a save, the restore can't proceed without a snapshot of what it replaces
+    history::record(&vault, &diary_dir, &file_name, content.as_bytes(), history::SOURCE_RESTORE)?;
+    vault.write(&diary_dir.join(&file_name), content.as_bytes())
+        .map_err(|e| format!("Failed to restore diary: {}", e))?;
+
+    if let Err(e) = search::index_entry(&app, &diary_dir, &file_name, &content) {
+        println!("[Search] Failed to update index: {}", e);
+    }
+    sync::dirty::mark(&app, &format!("entries/{}", file_name));
+    sync::scheduler::notify_saved(&app);
+
+    Ok(content)
+}
+
+#[tauri::command]
+pub async fn list_conflicts(app: AppHandle) -> Result<Vec<ConflictCopy>, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    conflicts::list(&diary_dir)
+}
+
+/// Diff an entry's conflict copy against the current entry
+#[tauri::command]
+pub async fn diff_conflict(name: String, app: AppHandle) -> Result<Vec<DiffLine>, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    let vault = Vault::open(&app, &diary_dir);
+    let (copy, path) = conflicts::get(&diary_dir, &name)?;
+    if !copy.is_entry {
+        return Err("Only entries can be compared".to_string());
+    }
+
+    let copy_content = vault.read(&path)?;
+    let current_path = diary_dir.join(&copy.original);
+    let current = if current_path.exists() { vault.read(&current_path)? } else { Vec::new() };
+
+    Ok(history::diff(
+        &String::from_utf8_lossy(&current),
+        &String::from_utf8_lossy(&copy_content),
+    ))
+}
+
+/// Settle a conflict: with `use_copy` the copy replaces the current version, otherwise
+/// it is dropped. Either way the copy is removed.
+#[tauri::command]
+pub async fn resolve_conflict(name: String, use_copy: bool, app: AppHandle) -> Result<(), String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    let vault = Vault::open(&app, &diary_dir);
+    let (copy, path) = conflicts::get(&diary_dir, &name)?;
+
+    if use_copy {
+        let content = vault.read(&path)?;
+        let target = diary_dir.join(&copy.original);
+        if copy.is_entry {
+            history::record(&vault, &diary_dir, &copy.original, &content, history::SOURCE_RESTORE)?;
+        } else if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create images directory: {}", e))?;
+        }
+        vault.write(&target, &content)
+            .map_err(|e| format!("Failed to restore conflict copy: {}", e))?;
+
+        if copy.is_entry {
+            if let Err(e) = search::index_entry(&app, &diary_dir, &copy.original, &String::from_utf8_lossy(&content)) {
+                println!("[Search] Failed to update index: {}", e);
+            }
+            sync::dirty::mark(&app, &format!("entries/{}", copy.original));
+        } else {
+            sync::dirty::mark(&app, &copy.original);
+        }
+        sync::scheduler::notify_saved(&app);
+    }
+
+    conflicts::discard(&diary_dir, &name)
+}
+
 #[tauri::command]
 pub async fn export_images(app: AppHandle) -> Result<String, String> {
     diary::export_images(&app).await
@@ -150,9 +301,11 @@ pub async fn get_storage_path(app: AppHandle) -> Result<String, String> {
     Ok(diary_dir.to_string_lossy().to_string())
 }
 
+/// `mode` is "move", "copy" or "point"; pointing is what older versions did
 #[tauri::command]
-pub async fn change_storage_path(app: AppHandle) -> Result<String, String> {
-    config::change_storage_path(&app).await
+pub async fn change_storage_path(mode: Option<String>, app: AppHandle) -> Result<RelocationReport, String> {
+    let mode = mode.unwrap_or_else(|| relocate::MODE_POINT.to_string());
+    config::change_storage_path(&app, &mode).await
 }
 
 #[tauri::command]
@@ -277,7 +430,9 @@ pub async fn export_pdf_range(start_date: String, end_date: String, app: AppHand
 
 #[tauri::command]
 pub fn get_config(app: AppHandle) -> Result<Config, String> {
-    config::read_config(&app)
+    let mut cfg = config::read_config(&app)?;
+    cfg.diary_dir = cfg.active().diary_dir.clone();
+    Ok(cfg)
 }
 
 #[tauri::command]
@@ -289,7 +444,7 @@ pub fn save_config(
     app: AppHandle
 ) -> Result<(), String> {
     let mut cfg = config::read_config(&app)?;
-    cfg.diary_dir = diary_dir;
+    cfg.active_mut().diary_dir = diary_dir;
     cfg.bg_type = bg_type;
     cfg.bg_value = bg_value;
     cfg.language = language;
@@ -297,43 +452,111 @@ pub fn save_config(
 }
 
 #[tauri::command]
-pub fn set_password(password: String, app: AppHandle) -> Result<(), String> {
+pub async fn set_password(password: String, app: AppHandle) -> Result<(), String> {
     use bcrypt::{hash, DEFAULT_COST};
 
+    // Re-wrap the vault key so encrypted entries stay readable with the new password
+    let diary_dir = config::get_diary_dir(&app).await?;
+    if vault::is_enabled(&diary_dir) {
+        vault::change_password(&app, &diary_dir, &password)?;
+    }
+
     let hashed = hash(password, DEFAULT_COST)
         .map_err(|e| format!("Failed to hash password: {}", e))?;
 
     let mut cfg = config::read_config(&app)?;
-    cfg.password_hash = Some(hashed);
+    cfg.active_mut().password_hash = Some(hashed);
     config::write_config(&app, &cfg)
 }
 
 #[tauri::command]
-pub fn verify_password(password: String, app: AppHandle) -> Result<bool, String> {
+pub async fn verify_password(password: String, app: AppHandle) -> Result<bool, String> {
     use bcrypt::verify;
 
     let cfg = config::read_config(&app)?;
 
-    match cfg.password_hash {
+    let valid = match &cfg.active().password_hash {
         Some(hash) => {
-            verify(password, &hash)
-                .map_err(|e| format!("Failed to verify password: {}", e))
+            verify(&password, hash)
+                .map_err(|e| format!("Failed to verify password: {}", e))?
         }
-        None => Ok(true) // No password set, always pass
+        None => true // No password set, always pass
+    };
+
+    // Unlock encrypted entries with the same password
+    let diary_dir = config::get_diary_dir(&app).await?;
+    if valid && vault::is_enabled(&diary_dir) {
+        vault::unlock(&app, &diary_dir, &password)?;
     }
+
+    Ok(valid)
 }
 
 #[tauri::command]
-pub fn clear_password(app: AppHandle) -> Result<(), String> {
+pub async fn clear_password(app: AppHandle) -> Result<(), String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    if vault::is_enabled(&diary_dir) {
+        return Err("Disable diary encryption before removing the password".to_string());
+    }
+
     let mut cfg = config::read_config(&app)?;
-    cfg.password_hash = None;
+    cfg.active_mut().password_hash = None;
     config::write_config(&app, &cfg)
 }
 
 #[tauri::command]
 pub fn has_password(app: AppHandle) -> Result<bool, String> {
     let cfg = config::read_config(&app)?;
-    Ok(cfg.password_hash.is_some())
+    Ok(cfg.active().password_hash.is_some())
+}
+
+#[tauri::command]
+pub async fn get_vault_status(app: AppHandle) -> Result<VaultStatus, String> {
+    let diary_dir = config::get_diary_dir(&app).await?;
+    Ok(vault::get_status(&app, &diary_dir))
+}
+
+#[tauri::command]
+pub async fn lock_vault(app: AppHandle) -> Result<(), String> {
+    vault::lock(&app);
+    Ok(())
+}
+
+/// Encrypt (or decrypt) the whole diary directory with the app password
+#[tauri::command]
+pub async fn migrate_vault(encrypt: bool, password: String, app: AppHandle) -> Result<String, String> {
+    let cfg = config::read_config(&app)?;
+    let hash = cfg.active().password_hash.as_ref()
+        .ok_or_else(|| "Set a password before enabling encryption".to_string())?;
+    let valid = bcrypt::verify(&password, hash)
+        .map_err(|e| format!("Failed to verify password: {}", e))?;
+    if !valid {
+        return Err("Incorrect password".to_string());
+    }
+
+    // A sync would read entries mid-rewrite and put back synced versions about to be cleared
+    let scheduler = app.state::<sync::scheduler::SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let diary_dir = config::get_diary_dir(&app).await?;
+
+    // Key derivation and rewriting every file are slow, keep them off the async runtime
+    let task_app = app.clone();
+    let migrated = tokio::task::spawn_blocking(move || {
+        vault::migrate(&task_app, &diary_dir, &password, encrypt)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))??;
+
+    // The search index and synced versions hold entry text too; rebuild them in the new format
+    search::reset(&app)?;
+    sync::shadow::clear(&app)?;
+
+    if encrypt {
+        Ok(format!("Encrypted {} files", migrated))
+    } else {
+        Ok(format!("Decrypted {} files", migrated))
+    }
 }
 
 #[tauri::command]
@@ -345,6 +568,38 @@ pub fn save_background_image(
     config::save_bg_image(&app, &data, &ext)
 }
 
+// ============== Notebook Commands ==============
+
+#[tauri::command]
+pub fn list_notebooks(app: AppHandle) -> Result<Vec<NotebookInfo>, String> {
+    notebooks::list(&app)
+}
+
+#[tauri::command]
+pub fn create_notebook(
+    name: String,
+    diary_dir: Option<String>,
+    remote_folder: Option<String>,
+    app: AppHandle,
+) -> Result<NotebookInfo, String> {
+    notebooks::create(&app, &name, diary_dir, remote_folder)
+}
+
+#[tauri::command]
+pub fn rename_notebook(id: String, name: String, app: AppHandle) -> Result<NotebookInfo, String> {
+    notebooks::rename(&app, &id, &name)
+}
+
+#[tauri::command]
+pub fn switch_notebook(id: String, app: AppHandle) -> Result<NotebookInfo, String> {
+    notebooks::switch(&app, &id)
+}
+
+#[tauri::command]
+pub fn delete_notebook(id: String, delete_entries: bool, app: AppHandle) -> Result<(), String> {
+    notebooks::delete(&app, &id, delete_entries)
+}
+
 // ============== Tag Commands ==============
 
 #[tauri::command]
@@ -412,51 +667,82 @@ pub async fn get_sync_status(app: AppHandle) -> Result<SyncStatus, String> {
 }
 
 #[tauri::command]
-pub async fn start_sync(app: AppHandle) -> Result<SyncReport, String> {
-    let mut auth = sync::GoogleAuth::load(&app)?;
+pub async fn start_sync(
+    excluded: Option<Vec<String>>,
+    previewed: Option<Vec<PlannedAction>>,
+    app: AppHandle,
+) -> Result<SyncReport, String> {
+    let excluded = excluded.unwrap_or_default().into_iter().collect();
+    sync::scheduler::sync_now(&app, previewed, excluded).await
+}
 
-    if !auth.is_authenticated() {
-        return Err("Not authenticated with Google".to_string());
-    }
+#[tauri::command]
+pub async fn preview_sync(app: AppHandle) -> Result<Vec<PlannedAction>, String> {
+    sync::scheduler::preview(&app).await
+}
 
-    let access_token = auth.get_valid_access_token().await?;
-    let diary_dir = config::get_diary_dir(&app).await?;
+#[tauri::command]
+pub async fn force_upload_sync(app: AppHandle) -> Result<SyncReport, String> {
+    sync::scheduler::force_upload_now(&app).await
+}
 
-    let engine = sync::SyncEngine::new(app.clone(), diary_dir);
-    engine.sync(&access_token).await
+/// Past sync runs, newest first; `offset` and `limit` page through them
+#[tauri::command]
+pub fn get_sync_history(offset: Option<usize>, limit: Option<usize>, app: AppHandle) -> Result<SyncHistoryPage, String> {
+    sync::journal::page(&app, offset.unwrap_or(0), limit)
 }
 
 #[tauri::command]
-pub async fn force_upload_sync(app: AppHandle) -> Result<SyncReport, String> {
-    let mut auth = sync::GoogleAuth::load(&app)?;
+pub fn get_device_info(app: AppHandle) -> Result<Device, String> {
+    sync::device::load(&app)
+}
 
-    if !auth.is_authenticated() {
-        return Err("Not authenticated with Google".to_string());
-    }
+#[tauri::command]
+pub fn set_device_name(name: String, app: AppHandle) -> Result<Device, String> {
+    sync::device::rename(&app, &name)
+}
 
-    let access_token = auth.get_valid_access_token().await?;
-    let diary_dir = config::get_diary_dir(&app).await?;
+#[tauri::command]
+pub async fn list_sync_devices(app: AppHandle) -> Result<Vec<SyncedDevice>, String> {
+    sync::list_devices(&app).await
+}
+
+#[tauri::command]
+pub async fn set_sync_passphrase(passphrase: String, app: AppHandle) -> Result<(), String> {
+    sync::enable_encryption(&app, &passphrase).await
+}
 
-    let engine = sync::SyncEngine::new(app.clone(), diary_dir);
-    engine.force_upload_sync(&access_token).await
+#[tauri::command]
+pub async fn disable_sync_encryption(app: AppHandle) -> Result<(), String> {
+    sync::disable_encryption(&app).await
 }
 
 #[tauri::command]
 pub async fn get_google_auth_url(is_mobile: bool, app: AppHandle) -> Result<String, String> {
-    let auth = sync::GoogleAuth::load(&app)?;
-    auth.get_auth_url(is_mobile).await
+    let mut auth = sync::GoogleAuth::load(&app)?;
+    let redirect_uri = sync::auth::prepare_redirect(is_mobile).await?;
+    auth.get_auth_url(&redirect_uri).await
 }
 
+/// `code` and `state` come from the redirect URL, caught by the callback server or pasted by hand
 #[tauri::command]
-pub async fn handle_oauth_callback(code: String, is_mobile: bool, app: AppHandle) -> Result<(), String> {
+pub async fn handle_oauth_callback(code: String, state: String, app: AppHandle) -> Result<(), String> {
     let mut auth = sync::GoogleAuth::load(&app)?;
-    auth.exchange_code(&code, is_mobile).await
+    auth.exchange_code(&code, &state).await
 }
 
+/// Pass a token from prepare_remote_deletion as delete_remote to also delete the Drive folder
 #[tauri::command]
-pub async fn disconnect_google(app: AppHandle) -> Result<(), String> {
-    let mut auth = sync::GoogleAuth::load(&app)?;
-    auth.disconnect()
+pub async fn disconnect_google(
+    delete_remote: Option<String>,
+    app: AppHandle,
+) -> Result<sync::DisconnectReport, String> {
+    sync::disconnect::disconnect(&app, delete_remote.as_deref()).await
+}
+
+#[tauri::command]
+pub fn prepare_remote_deletion(app: AppHandle) -> Result<sync::RemoteDeletion, String> {
+    sync::disconnect::prepare_remote_deletion(&app)
 }
 
 #[tauri::command]
@@ -493,19 +779,28 @@ pub async fn get_google_client_id(app: AppHandle) -> Result<Option<String>, Stri
 }
 
 #[tauri::command]
-pub async fn clear_sync_credentials(app: AppHandle) -> Result<(), String> {
+pub async fn save_webdav_password(password: String, app: AppHandle) -> Result<(), String> {
+    sync::webdav::save_password(&app, &password)
+}
+
+#[tauri::command]
+pub async fn clear_sync_credentials(app: AppHandle) -> Result<Option<String>, String> {
     let mut auth = sync::GoogleAuth::load(&app)?;
-    auth.clear_all_credentials()
+    let revoke_error = auth.clear_all_credentials().await?;
+    sync::webdav::clear_password(&app)?;
+    sync::encryption::clear_key(&app)?;
+    Ok(revoke_error)
 }
 
-// Desktop-only: Start OAuth callback server
+// Desktop-only: Wait on the OAuth callback server started by get_google_auth_url
 #[cfg(desktop)]
 #[tauri::command]
-pub async fn start_oauth_callback_server() -> Result<String, String> {
-    // Run in blocking thread pool since it uses sync I/O
-    tokio::task::spawn_blocking(|| {
-        sync::auth::callback_server::start_callback_server()
-    })
-    .await
-    .map_err(|e| format!("Task failed: {}", e))?
+pub async fn start_oauth_callback_server(app: AppHandle) -> Result<sync::auth::callback_server::AuthorizationResponse, String> {
+    sync::auth::callback_server::wait_for_code(&app).await
+}
+
+#[cfg(desktop)]
+#[tauri::command]
+pub fn cancel_oauth_callback_server() {
+    sync::auth::callback_server::cancel()
 }
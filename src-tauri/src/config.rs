@@ -2,9 +2,59 @@ use std::fs;
 use std::path::PathBuf;
 use tauri::{AppHandle, Manager};
 use serde::{Deserialize, Serialize};
+use crate::relocate::{self, RelocationReport};
+use crate::storage;
+use crate::sync::drive::APP_FOLDER_NAME;
+
+/// Notebook of the diaries saved before notebooks existed; its state stays in the app data root
+pub const DEFAULT_NOTEBOOK_ID: &str = "default";
+const DEFAULT_NOTEBOOK_NAME: &str = "Diary";
+
+/// Sync metadata, search index and sync credentials of the other notebooks
+const NOTEBOOK_STATE_DIR: &str = "notebooks";
+/// Default entry directories of the other notebooks
+const NOTEBOOK_DIARY_DIR: &str = "diaries";
+
+/// A diary with its own directory, password and sync target
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notebook {
+    pub id: String,
+    pub name: String,
+
+    /// None for the default location in app data
+    #[serde(rename = "diaryDir", default)]
+    pub diary_dir: Option<String>,
+
+    #[serde(rename = "passwordHash", default)]
+    pub password_hash: Option<String>, // bcrypt hash
+
+    /// Folder on the sync target, chosen when the notebook is created so that other
+    /// devices can attach to it. None for the default notebook and older notebooks.
+    #[serde(rename = "remoteFolder", default)]
+    pub remote_folder: Option<String>,
+}
+
+impl Notebook {
+    pub fn is_default(&self) -> bool {
+        self.id == DEFAULT_NOTEBOOK_ID
+    }
+
+    /// Name of the notebook's folder on the sync target
+    pub fn remote_folder(&self) -> String {
+        if let Some(folder) = &self.remote_folder {
+            folder.clone()
+        } else if self.is_default() {
+            APP_FOLDER_NAME.to_string()
+        } else {
+            format!("{}-{}", APP_FOLDER_NAME, self.id)
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Config {
+    /// Directory of the active notebook as reported by get_config; in the file
+    /// it is only read to migrate configs from before notebooks
     #[serde(rename = "diaryDir")]
     pub diary_dir: Option<String>,
 
@@ -31,19 +81,84 @@ pub struct Config {
     #[serde(rename = "windowHeight", default)]
     pub window_height: Option<u32>,
 
-    // Password settings
-    #[serde(rename = "passwordHash", default)]
+    // Password settings, migrated to the default notebook like diary_dir
+    #[serde(rename = "passwordHash", default, skip_serializing)]
     pub password_hash: Option<String>, // bcrypt hash
 
     // Language settings
     #[serde(rename = "language", default)]
     pub language: Option<String>,      // "en" | "zh"
+
+    // Notebook settings
+    #[serde(rename = "notebooks", default)]
+    pub notebooks: Vec<Notebook>,
+
+    #[serde(rename = "activeNotebook", default)]
+    pub active_notebook: Option<String>,
 }
 
-pub async fn get_diary_dir(app: &AppHandle) -> Result<PathBuf, String> {
-    let config = read_config(app)?;
+impl Config {
+    /// Move the single diary of older configs into the default notebook
+    fn migrate_notebooks(&mut self) {
+        if self.notebooks.is_empty() {
+            self.notebooks.push(Notebook {
+                id: DEFAULT_NOTEBOOK_ID.to_string(),
+                name: DEFAULT_NOTEBOOK_NAME.to_string(),
+                diary_dir: self.diary_dir.take(),
+                password_hash: self.password_hash.take(),
+                remote_folder: None,
+            });
+        }
+        self.diary_dir = None;
+        self.password_hash = None;
+    }
+
+    pub fn notebook(&self, id: &str) -> Option<&Notebook> {
+        self.notebooks.iter().find(|n| n.id == id)
+    }
+
+    pub fn notebook_mut(&mut self, id: &str) -> Option<&mut Notebook> {
+        self.notebooks.iter_mut().find(|n| n.id == id)
+    }
 
-    if let Some(dir) = config.diary_dir {
+    /// The notebook commands operate on; the first one if the active id is unknown
+    pub fn active(&self) -> &Notebook {
+        self.active_notebook.as_deref()
+            .and_then(|id| self.notebook(id))
+            .or(self.notebooks.first())
+            .expect("config has at least one notebook")
+    }
+
+    pub fn active_mut(&mut self) -> &mut Notebook {
+        let id = self.active().id.clone();
+        self.notebook_mut(&id).expect("active notebook exists")
+    }
+}
+
+/// Directory of the notebook's state kept outside its diary directory
+pub fn get_notebook_state_dir(app: &AppHandle, notebook: &Notebook) -> Result<PathBuf, String> {
+    let app_data = app.path().app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+    let dir = if notebook.is_default() {
+        app_data
+    } else {
+        app_data.join(NOTEBOOK_STATE_DIR).join(&notebook.id)
+    };
+
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create notebook state dir: {}", e))?;
+    Ok(dir)
+}
+
+/// State directory of the active notebook: sync metadata and credentials, sync
+/// history and the search index
+pub fn get_state_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    get_notebook_state_dir(app, read_config(app)?.active())
+}
+
+/// Entry directory of a notebook, created if it is the default location
+pub fn get_notebook_diary_dir(app: &AppHandle, notebook: &Notebook) -> Result<PathBuf, String> {
+    if let Some(dir) = &notebook.diary_dir {
         let path = PathBuf::from(dir);
         if path.exists() {
             return Ok(path);
@@ -53,7 +168,11 @@ pub async fn get_diary_dir(app: &AppHandle) -> Result<PathBuf, String> {
     // Default directory
     let app_data = app.path().app_data_dir()
         .map_err(|e| format!("Failed to get app data dir: {}", e))?;
-    let default_dir = app_data.join("diary");
+    let default_dir = if notebook.is_default() {
+        app_data.join("diary")
+    } else {
+        app_data.join(NOTEBOOK_DIARY_DIR).join(&notebook.id)
+    };
 
     if !default_dir.exists() {
         fs::create_dir_all(&default_dir)
@@ -63,8 +182,15 @@ pub async fn get_diary_dir(app: &AppHandle) -> Result<PathBuf, String> {
     Ok(default_dir)
 }
 
+/// Entry directory of the active notebook
+pub async fn get_diary_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    get_notebook_diary_dir(app, read_config(app)?.active())
+}
+
+/// Let the user pick a new folder for the active notebook and relocate it there,
+/// see `relocate::relocate` for the modes
 #[cfg(not(target_os = "android"))]
-pub async fn change_storage_path(app: &AppHandle) -> Result<String, String> {
+pub async fn change_storage_path(app: &AppHandle, mode: &str) -> Result<RelocationReport, String> {
     use tauri_plugin_dialog::{DialogExt, FilePath};
 
     let folder = app.dialog()
@@ -73,32 +199,35 @@ pub async fn change_storage_path(app: &AppHandle) -> Result<String, String> {
         .blocking_pick_folder();
 
     if let Some(FilePath::Path(path)) = folder {
-        let mut config = read_config(app)?;
-        config.diary_dir = Some(path.to_string_lossy().to_string());
-        write_config(app, &config)?;
-
-        Ok(path.to_string_lossy().to_string())
+        // Copying and hashing every file is slow, keep it off the async runtime
+        let task_app = app.clone();
+        let mode = mode.to_string();
+        tokio::task::spawn_blocking(move || relocate::relocate(&task_app, &path, &mode))
+            .await
+            .map_err(|e| format!("Task failed: {}", e))?
     } else {
         Err("No folder selected".to_string())
     }
 }
 
 #[cfg(target_os = "android")]
-pub async fn change_storage_path(_app: &AppHandle) -> Result<String, String> {
+pub async fn change_storage_path(_app: &AppHandle, _mode: &str) -> Result<RelocationReport, String> {
     Err("Changing storage path is not supported on Android".to_string())
 }
 
 pub fn read_config(app: &AppHandle) -> Result<Config, String> {
     let config_path = get_config_path(app)?;
 
-    if config_path.exists() {
-        let content = fs::read_to_string(&config_path)
-            .map_err(|e| format!("Failed to read config: {}", e))?;
-        serde_json::from_str(&content)
-            .map_err(|e| format!("Invalid config format: {}", e))
+    let mut config = if config_path.exists() {
+        storage::read_with_recovery(&config_path, |data| {
+            serde_json::from_slice(data)
+                .map_err(|e| format!("Invalid config format: {}", e))
+        })?
     } else {
-        Ok(Config::default())
-    }
+        Config::default()
+    };
+    config.migrate_notebooks();
+    Ok(config)
 }
 
 pub fn write_config(app: &AppHandle, config: &Config) -> Result<(), String> {
@@ -106,7 +235,7 @@ pub fn write_config(app: &AppHandle, config: &Config) -> Result<(), String> {
     let json = serde_json::to_string_pretty(config)
         .map_err(|e| format!("Failed to serialize config: {}", e))?;
 
-    fs::write(&config_path, json)
+    storage::write_with_backup(&config_path, json.as_bytes())
         .map_err(|e| format!("Failed to write config: {}", e))?;
 
     Ok(())
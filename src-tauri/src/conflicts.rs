@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use serde::Serialize;
+
+use crate::diary;
+use crate::sync::engine::CONFLICTS_DIR;
+
+/// Conflict copies are named `{stem}.{origin}-{%Y%m%d-%H%M%S}.{ext}` after the file they
+/// are a version of; the origin is a device label or "replaced" for a moved diary
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+const TIMESTAMP_LEN: usize = 15;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictCopy {
+    /// File name in the conflicts folder, passed back to read and resolve
+    pub name: String,
+    /// Diary file it is a version of, e.g. "2024-01-31.txt" or "images/1700000000-photo.png"
+    pub original: String,
+    /// Device the version came from
+    pub origin: String,
+    /// RFC3339 time the copy was made
+    pub timestamp: String,
+    pub is_entry: bool,
+}
+
+impl ConflictCopy {
+    fn parse(name: &str) -> Option<Self> {
+        let (rest, ext) = name.rsplit_once('.')?;
+        let (stem, label) = rest.rsplit_once('.')?;
+        let split = label.len().checked_sub(TIMESTAMP_LEN + 1)?;
+        let (origin, timestamp) = label.get(..split).zip(label.get(split..)?.strip_prefix('-'))?;
+        if stem.is_empty() || origin.is_empty() {
+            return None;
+        }
+        let time = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+
+        let file_name = format!("{}.{}", stem, ext);
+        let is_entry = diary::is_entry_file(Path::new(&file_name));
+        let is_image = mime_guess::from_path(&file_name).first()
+            .map(|mime_type| mime_type.type_() == mime_guess::mime::IMAGE)
+            .unwrap_or(false);
+        // Other diary files are merged rather than kept as copies
+        if !is_entry && !is_image {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            original: if is_entry { file_name } else { format!("images/{}", file_name) },
+            origin: origin.to_string(),
+            timestamp: Local.from_local_datetime(&time).earliest()
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            is_entry,
+        })
+    }
+}
+
+/// Conflict copies in the diary, newest first
+pub fn list(diary_dir: &Path) -> Result<Vec<ConflictCopy>, String> {
+    let dir = diary_dir.join(CONFLICTS_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut copies = Vec::new();
+    for entry in fs::read_dir(&dir).map_err(|e| format!("Failed to read conflicts: {}", e))? {
+        let path = entry.map_err(|e| format!("Failed to read conflicts: {}", e))?.path();
+        if let Some(copy) = path.file_name().and_then(|n| n.to_str()).and_then(ConflictCopy::parse) {
+            copies.push(copy);
+        }
+    }
+    copies.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(copies)
+}
+
+/// A conflict copy by name, with its path
+pub fn get(diary_dir: &Path, name: &str) -> Result<(ConflictCopy, PathBuf), String> {
+    // Names come from the frontend; only accept copies in the conflicts folder
+    let copy = ConflictCopy::parse(name)
+        .filter(|_| !name.contains(['/', '\\']))
+        .ok_or_else(|| format!("Invalid conflict copy: {}", name))?;
+    let path = diary_dir.join(CONFLICTS_DIR).join(name);
+    if !path.exists() {
+        return Err(format!("Conflict copy not found: {}", name));
+    }
+    Ok((copy, path))
+}
+
+/// Delete a conflict copy, keeping the current version
+pub fn discard(diary_dir: &Path, name: &str) -> Result<(), String> {
+    let (_, path) = get(diary_dir, name)?;
+    fs::remove_file(&path)
+        .map_err(|e| format!("Failed to delete conflict copy: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_entry_and_image_copies() {
+        let copy = ConflictCopy::parse("2024-01-31.My_Laptop-20240131-093000.txt").unwrap();
+        assert_eq!((copy.original.as_str(), copy.origin.as_str(), copy.is_entry), ("2024-01-31.txt", "My_Laptop", true));
+
+        let copy = ConflictCopy::parse("1700000000-photo.replaced-20240131-093000.png").unwrap();
+        assert_eq!((copy.original.as_str(), copy.origin.as_str(), copy.is_entry), ("images/1700000000-photo.png", "replaced", false));
+    }
+
+    #[test]
+    fn parse_rejects_other_files() {
+        assert!(ConflictCopy::parse("2024-01-31.txt").is_none());
+        assert!(ConflictCopy::parse("2024-01-31.Laptop-2024013-093000.txt").is_none());
+        assert!(ConflictCopy::parse("2024-01-31.-20240131-093000.txt").is_none());
+        assert!(ConflictCopy::parse("tags.Laptop-20240131-093000.json").is_none());
+    }
+
+    #[test]
+    fn get_only_accepts_names_in_the_conflicts_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let conflicts = dir.path().join(CONFLICTS_DIR);
+        fs::create_dir_all(&conflicts).unwrap();
+        let name = "2024-01-31.Laptop-20240131-093000.txt";
+        fs::write(conflicts.join(name), b"copy").unwrap();
+
+        assert_eq!(list(dir.path()).unwrap().len(), 1);
+        assert!(get(dir.path(), &format!("../{}", name)).is_err());
+        discard(dir.path(), name).unwrap();
+        assert!(list(dir.path()).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,39 @@
 use std::fs;
+use std::path::Path;
 use tauri::AppHandle;
 use serde::{Deserialize, Serialize};
-use crate::config;
+use crate::{config, history, sync};
+use crate::vault::Vault;
+
+/// Extensions of entry files; the file stem is the entry date
+pub const ENTRY_EXTENSIONS: [&str; 2] = ["txt", "md"];
+
+/// Whether a file name or path is a diary entry in one of the recognized formats
+pub fn is_entry_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ENTRY_EXTENSIONS.contains(&ext))
+        .unwrap_or(false)
+}
+
+/// Folders the app keeps in a diary directory next to the entries
+pub const DIARY_DIRS: [&str; 3] = ["images", history::HISTORY_DIR, sync::engine::CONFLICTS_DIR];
+
+/// State files the app keeps in a diary directory; their backups belong to the diary too
+const DIARY_FILES: [&str; 2] = ["tags.json", crate::vault::VAULT_FILE];
+
+/// Whether an item at the top of a diary directory belongs to the diary, as opposed
+/// to other files the user keeps in the same folder
+pub fn is_diary_item(path: &Path) -> bool {
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if path.is_dir() {
+        return DIARY_DIRS.contains(&name.as_str());
+    }
+    let name = name.strip_suffix(".bak").unwrap_or(&name);
+    is_entry_file(path) || DIARY_FILES.contains(&name)
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DiaryEntry {
@@ -70,7 +102,7 @@ pub async fn get_events_for_date(date: &str, app: &AppHandle) -> Result<Vec<Sche
     let file_path = diary_dir.join(format!("{}.txt", date));
 
     if file_path.exists() {
-        let content = fs::read_to_string(&file_path)
+        let content = Vault::open(app, &diary_dir).read_to_string(&file_path)
             .map_err(|e| format!("Failed to read diary: {}", e))?;
         let (_, events) = parse_diary_with_events(&content);
         Ok(events)
@@ -83,9 +115,10 @@ pub async fn get_events_for_date(date: &str, app: &AppHandle) -> Result<Vec<Sche
 pub async fn save_event(date: &str, event: ScheduleEvent, app: &AppHandle) -> Result<(), String> {
     let diary_dir = config::get_diary_dir(app).await?;
     let file_path = diary_dir.join(format!("{}.txt", date));
+    let vault = Vault::open(app, &diary_dir);
 
     let (content, mut events) = if file_path.exists() {
-        let raw = fs::read_to_string(&file_path)
+        let raw = vault.read_to_string(&file_path)
             .map_err(|e| format!("Failed to read diary: {}", e))?;
         parse_diary_with_events(&raw)
     } else {
@@ -103,8 +136,13 @@ pub async fn save_event(date: &str, event: ScheduleEvent, app: &AppHandle) -> Re
     events.sort_by(|a, b| a.time.cmp(&b.time));
 
     let new_content = serialize_with_events(&content, &events);
-    fs::write(&file_path, new_content)
+    let file_name = format!("{}.txt", date);
+    if let Err(e) = history::record(&vault, &diary_dir, &file_name, new_content.as_bytes(), history::SOURCE_SAVE) {
+        println!("[History] Failed to keep revision of {}: {}", file_name, e);
+    }
+    vault.write(&file_path, new_content.as_bytes())
         .map_err(|e| format!("Failed to save: {}", e))?;
+    sync::dirty::mark(app, &format!("entries/{}", file_name));
 
     Ok(())
 }
@@ -118,15 +156,21 @@ pub async fn delete_event(date: &str, event_id: &str, app: &AppHandle) -> Result
         return Ok(());
     }
 
-    let raw = fs::read_to_string(&file_path)
+    let vault = Vault::open(app, &diary_dir);
+    let raw = vault.read_to_string(&file_path)
         .map_err(|e| format!("Failed to read diary: {}", e))?;
     let (content, mut events) = parse_diary_with_events(&raw);
 
     events.retain(|e| e.id != event_id);
 
     let new_content = serialize_with_events(&content, &events);
-    fs::write(&file_path, new_content)
+    let file_name = format!("{}.txt", date);
+    if let Err(e) = history::record(&vault, &diary_dir, &file_name, new_content.as_bytes(), history::SOURCE_SAVE) {
+        println!("[History] Failed to keep revision of {}: {}", file_name, e);
+    }
+    vault.write(&file_path, new_content.as_bytes())
         .map_err(|e| format!("Failed to save: {}", e))?;
+    sync::dirty::mark(app, &format!("entries/{}", file_name));
 
     Ok(())
 }
@@ -138,23 +182,19 @@ pub async fn get_all_diaries(app: &AppHandle) -> Result<Vec<DiaryEntry>, String>
         return Ok(Vec::new());
     }
 
+    let vault = Vault::open(app, &diary_dir);
     let mut diaries = Vec::new();
 
     for entry in fs::read_dir(&diary_dir).map_err(|e| format!("Failed to read directory: {}", e))? {
         let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
         let path = entry.path();
 
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if ext == "txt" || ext == "md" {
-                    if let Some(file_name) = path.file_stem() {
-                        let date = file_name.to_string_lossy().to_string();
-                        let content = fs::read_to_string(&path)
-                            .map_err(|e| format!("Failed to read file: {}", e))?;
+        if path.is_file() && is_entry_file(&path) {
+            if let Some(file_name) = path.file_stem() {
+                let date = file_name.to_string_lossy().to_string();
+                let content = vault.read_to_string(&path)?;
 
-                        diaries.push(DiaryEntry { date, content });
-                    }
-                }
+                diaries.push(DiaryEntry { date, content });
             }
         }
     }
@@ -191,13 +231,71 @@ pub async fn save_image(
     let file_path = images_dir.join(&new_filename);
 
     // Write file
-    fs::write(&file_path, data)
+    Vault::open(app, &diary_dir).write(&file_path, &data)
         .map_err(|e| format!("Failed to write image: {}", e))?;
+    sync::dirty::mark(app, &format!("images/{}", new_filename));
 
     // Return full path
     Ok(file_path.to_string_lossy().to_string())
 }
 
+/// URI scheme the frontend shows diary images with, served decrypted by `image_response`
+pub const IMAGE_PROTOCOL: &str = "diary-image";
+
+/// Read an image from the diary, decrypting it if needed
+pub async fn read_image(path: &str, app: &AppHandle) -> Result<Vec<u8>, String> {
+    let diary_dir = config::get_diary_dir(app).await?;
+    let images_dir = diary_dir.join("images");
+
+    // Entries from older versions hold full paths, possibly into a folder the diary
+    // has since been moved from; look those up by name in the current images folder
+    let file_path = if Path::new(path).is_absolute() {
+        let name = Path::new(path).file_name().ok_or("Invalid image path")?;
+        images_dir.join(name)
+    } else {
+        diary_dir.join(path)
+    };
+
+    if !file_path.starts_with(&images_dir) || path.split(['/', '\\']).any(|part| part == "..") {
+        return Err("Image is outside the diary images directory".to_string());
+    }
+
+    // Images may be left out of sync on this device; fetch them when an entry needs one
+    if !file_path.exists() {
+        let name = file_path.file_name()
+            .and_then(|n| n.to_str())
+            .ok_or("Invalid image path")?;
+        return sync::fetch_image(app, name).await;
+    }
+
+    Vault::open(app, &diary_dir).read(&file_path)
+        .map_err(|e| format!("Failed to read image: {}", e))
+}
+
+/// Response to a `diary-image` request, whose path is the image path written in the entry
+pub async fn image_response(app: &AppHandle, uri_path: &str) -> tauri::http::Response<Vec<u8>> {
+    let path = urlencoding::decode(uri_path.strip_prefix('/').unwrap_or(uri_path))
+        .map(|path| path.into_owned())
+        .unwrap_or_default();
+
+    let response = tauri::http::Response::builder();
+    match read_image(&path, app).await {
+        Ok(data) => {
+            let mime_type = mime_guess::from_path(&path).first_or_octet_stream();
+            response
+                .header(tauri::http::header::CONTENT_TYPE, mime_type.as_ref())
+                .body(data)
+        }
+        Err(e) => {
+            println!("[Diary] Failed to show image {}: {}", path, e);
+            response
+                .status(tauri::http::StatusCode::NOT_FOUND)
+                .body(Vec::new())
+        }
+    }
+    .unwrap_or_default()
+}
+
 #[cfg(not(target_os = "android"))]
 pub async fn export_images(app: &AppHandle) -> Result<String, String> {
     use tauri_plugin_dialog::{DialogExt, FilePath};
@@ -231,13 +329,16 @@ pub async fn export_images(app: &AppHandle) -> Result<String, String> {
                 .map_err(|e| format!("Failed to create target directory: {}", e))?;
         }
 
+        // Exported images are always written decrypted
+        let vault = Vault::open(app, &diary_dir);
         let mut copied = 0;
         for file in files {
             let src = file.path();
             let file_name = src.file_name().unwrap();
             let dest = target_path.join(file_name);
 
-            fs::copy(&src, &dest)
+            let data = vault.read(&src)?;
+            fs::write(&dest, data)
                 .map_err(|e| format!("Failed to copy file: {}", e))?;
             copied += 1;
         }
@@ -276,6 +377,7 @@ pub async fn import_images(app: &AppHandle) -> Result<String, String> {
                 .map_err(|e| format!("Failed to create images directory: {}", e))?;
         }
 
+        let vault = Vault::open(app, &diary_dir);
         let allowed_exts = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
         let mut imported = 0;
         let mut skipped = 0;
@@ -315,8 +417,13 @@ pub async fn import_images(app: &AppHandle) -> Result<String, String> {
                 dest_path = target_dir.join(new_name);
             }
 
-            fs::copy(&path, &dest_path)
+            let data = fs::read(&path)
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+            vault.write(&dest_path, &data)
                 .map_err(|e| format!("Failed to copy file: {}", e))?;
+            if let Some(name) = dest_path.file_name() {
+                sync::dirty::mark(app, &format!("images/{}", name.to_string_lossy()));
+            }
             imported += 1;
         }
 
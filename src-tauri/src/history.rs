@@ -0,0 +1,310 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+use crate::sync::merge::match_lines;
+use crate::vault::Vault;
+
+/// Per-entry snapshots live in `{diary_dir}/.history/{file_name}/`, one gzip file each,
+/// named `{unix_millis}-{source}-{size}.gz` so listing needs no decompression
+pub const HISTORY_DIR: &str = ".history";
+
+// Saves closer together than this replace the previous snapshot instead of adding one
+const SAVE_COALESCE_MS: i64 = 5 * 60 * 1000;
+
+// Every revision is kept this long, afterwards only the last one of each day
+const KEEP_ALL_DAYS: i64 = 7;
+
+// Values of `Revision::source`
+pub const SOURCE_SAVE: &str = "save";
+pub const SOURCE_IMPORT: &str = "import";
+pub const SOURCE_SYNC: &str = "sync";
+pub const SOURCE_RESTORE: &str = "restore";
+/// Content found on disk that no snapshot covered yet, e.g. written by an older version or another app
+pub const SOURCE_PREVIOUS: &str = "previous";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Revision {
+    /// Opaque id passed back to diff and restore
+    pub id: String,
+    /// RFC3339 time the snapshot was taken
+    pub timestamp: String,
+    /// Size of the entry text in bytes
+    pub size: u64,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLine {
+    /// "equal", "added" or "removed"
+    pub kind: String,
+    pub text: String,
+}
+
+struct StoredRevision {
+    id: String,
+    millis: i64,
+    size: u64,
+    source: String,
+}
+
+impl StoredRevision {
+    fn parse(id: &str) -> Option<Self> {
+        let stem = id.strip_suffix(".gz")?;
+        let mut parts = stem.splitn(3, '-');
+        let millis = parts.next()?.parse().ok()?;
+        let source = parts.next()?.to_string();
+        let size = parts.next()?.parse().ok()?;
+        Some(Self { id: id.to_string(), millis, size, source })
+    }
+
+    fn to_revision(&self) -> Revision {
+        Revision {
+            id: self.id.clone(),
+            timestamp: DateTime::<Utc>::from_timestamp_millis(self.millis)
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default(),
+            size: self.size,
+            source: self.source.clone(),
+        }
+    }
+}
+
+fn entry_history_dir(diary_dir: &Path, file_name: &str) -> PathBuf {
+    diary_dir.join(HISTORY_DIR).join(file_name)
+}
+
+/// Stored revisions of an entry, newest first
+fn list_stored(dir: &Path) -> Result<Vec<StoredRevision>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut revisions = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| format!("Failed to read history: {}", e))? {
+        let path = entry.map_err(|e| format!("Failed to read history: {}", e))?.path();
+        if let Some(revision) = path.file_name().and_then(|n| n.to_str()).and_then(StoredRevision::parse) {
+            revisions.push(revision);
+        }
+    }
+    revisions.sort_by_key(|r| std::cmp::Reverse(r.millis));
+    Ok(revisions)
+}
+
+fn read_stored(vault: &Vault, dir: &Path, id: &str) -> Result<Vec<u8>, String> {
+    let compressed = vault.read(&dir.join(id))?;
+    let mut content = Vec::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut content)
+        .map_err(|e| format!("Failed to decompress revision: {}", e))?;
+    Ok(content)
+}
+
+fn write_stored(vault: &Vault, dir: &Path, millis: i64, source: &str, content: &[u8]) -> Result<StoredRevision, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)
+        .map_err(|e| format!("Failed to compress revision: {}", e))?;
+    let compressed = encoder.finish()
+        .map_err(|e| format!("Failed to compress revision: {}", e))?;
+
+    let id = format!("{}-{}-{}.gz", millis, source, content.len());
+    vault.write(&dir.join(&id), &compressed)?;
+    Ok(StoredRevision { id, millis, size: content.len() as u64, source: source.to_string() })
+}
+
+/// Snapshot `content` before it replaces entry `file_name`.
+/// The version currently on disk is kept too if no snapshot has it yet.
+pub fn record(vault: &Vault, diary_dir: &Path, file_name: &str, content: &[u8], source: &str) -> Result<(), String> {
+    let dir = entry_history_dir(diary_dir, file_name);
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create history directory: {}", e))?;
+
+    let mut revisions = list_stored(&dir)?;
+    let mut latest = match revisions.first() {
+        Some(revision) => Some(read_stored(vault, &dir, &revision.id)?),
+        None => None,
+    };
+    let now = Utc::now().timestamp_millis();
+
+    let current_path = diary_dir.join(file_name);
+    if current_path.exists() {
+        let current = vault.read(&current_path)?;
+        if latest.as_deref() != Some(current.as_slice()) && current != content {
+            let millis = next_millis(now, &revisions);
+            revisions.insert(0, write_stored(vault, &dir, millis, SOURCE_PREVIOUS, &current)?);
+            latest = Some(current);
+        }
+    }
+
+    if latest.as_deref() == Some(content) {
+        return Ok(());
+    }
+
+    // Autosave while typing would otherwise leave a snapshot every few seconds
+    let coalesce = revisions.first()
+        .map(|r| r.source == SOURCE_SAVE && source == SOURCE_SAVE && now - r.millis < SAVE_COALESCE_MS)
+        .unwrap_or(false);
+    if coalesce {
+        let replaced = revisions.remove(0);
+        fs::remove_file(dir.join(&replaced.id))
+            .map_err(|e| format!("Failed to replace revision: {}", e))?;
+    }
+
+    let millis = next_millis(now, &revisions);
+    revisions.insert(0, write_stored(vault, &dir, millis, source, content)?);
+
+    apply_retention(&dir, &revisions, now)
+}
+
+/// Snapshot the entry as it is on disk, e.g. before a sync deletes it
+pub fn preserve(vault: &Vault, diary_dir: &Path, file_name: &str) -> Result<(), String> {
+    let path = diary_dir.join(file_name);
+    if !path.exists() {
+        return Ok(());
+    }
+    let content = vault.read(&path)?;
+    record(vault, diary_dir, file_name, &content, SOURCE_PREVIOUS)
+}
+
+/// Keep snapshot names unique and ordered even when taken within the same millisecond
+fn next_millis(now: i64, revisions: &[StoredRevision]) -> i64 {
+    match revisions.first() {
+        Some(latest) if latest.millis >= now => latest.millis + 1,
+        _ => now,
+    }
+}
+
+/// Drop revisions from before the last `KEEP_ALL_DAYS` UTC days except the newest of each day.
+/// Whole days are compared, so a day is either kept entirely or thinned entirely.
+fn apply_retention(dir: &Path, revisions: &[StoredRevision], now: i64) -> Result<(), String> {
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+    let first_kept_day = now.div_euclid(DAY_MS) - KEEP_ALL_DAYS;
+    let mut last_day = None;
+
+    for revision in revisions {
+        let day = revision.millis.div_euclid(DAY_MS);
+        let keep = day >= first_kept_day || last_day != Some(day);
+        last_day = Some(day);
+        if !keep {
+            fs::remove_file(dir.join(&revision.id))
+                .map_err(|e| format!("Failed to prune history: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the history along with an entry file renamed to another format
+pub fn rename(diary_dir: &Path, from: &str, to: &str) -> Result<(), String> {
+    let from_dir = entry_history_dir(diary_dir, from);
+    let to_dir = entry_history_dir(diary_dir, to);
+    if !from_dir.exists() || to_dir.exists() {
+        return Ok(());
+    }
+    fs::rename(&from_dir, &to_dir)
+        .map_err(|e| format!("Failed to move history: {}", e))
+}
+
+/// Revisions of an entry, newest first
+pub fn list(diary_dir: &Path, file_name: &str) -> Result<Vec<Revision>, String> {
+    let revisions = list_stored(&entry_history_dir(diary_dir, file_name))?;
+    Ok(revisions.iter().map(StoredRevision::to_revision).collect())
+}
+
+/// Content of one revision
+pub fn read(vault: &Vault, diary_dir: &Path, file_name: &str, revision_id: &str) -> Result<Vec<u8>, String> {
+    // Ids come from the frontend; only accept names this module generates
+    if revision_id.contains(['/', '\\']) || StoredRevision::parse(revision_id).is_none() {
+        return Err(format!("Invalid revision id: {}", revision_id));
+    }
+    let dir = entry_history_dir(diary_dir, file_name);
+    if !dir.join(revision_id).exists() {
+        return Err(format!("Revision not found: {}", revision_id));
+    }
+    read_stored(vault, &dir, revision_id)
+}
+
+/// Line diff turning `from` into `to`
+pub fn diff(from: &str, to: &str) -> Vec<DiffLine> {
+    let from_lines: Vec<&str> = from.lines().collect();
+    let to_lines: Vec<&str> = to.lines().collect();
+    let line = |kind: &str, text: &str| DiffLine { kind: kind.to_string(), text: text.to_string() };
+
+    // Too large to align: show it as a full replacement
+    let Some(matches) = match_lines(&from_lines, &to_lines) else {
+        return from_lines.iter().map(|text| line("removed", text))
+            .chain(to_lines.iter().map(|text| line("added", text)))
+            .collect();
+    };
+
+    let mut result = Vec::new();
+    let mut next_to = 0;
+    for (i, matched) in matches.iter().enumerate() {
+        match matched {
+            Some(j) => {
+                result.extend(to_lines[next_to..*j].iter().map(|text| line("added", text)));
+                result.push(line("equal", from_lines[i]));
+                next_to = j + 1;
+            }
+            None => result.push(line("removed", from_lines[i])),
+        }
+    }
+    result.extend(to_lines[next_to..].iter().map(|text| line("added", text)));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+    const HOUR_MS: i64 = 60 * 60 * 1000;
+
+    fn remaining(dir: &Path) -> Vec<i64> {
+        list_stored(dir).unwrap().iter().map(|r| r.millis).collect()
+    }
+
+    #[test]
+    fn retention_thins_whole_days_past_the_boundary() {
+        let dir = tempfile::tempdir().unwrap();
+        // Noon, so every snapshot below falls on a known UTC day
+        let now = 20_000 * DAY_MS + 12 * HOUR_MS;
+        let boundary_day = now - KEEP_ALL_DAYS * DAY_MS;
+        let snapshots = [
+            now - HOUR_MS,
+            now - 2 * HOUR_MS,
+            // On the boundary day, before and after the time of day of `now`
+            boundary_day + 6 * HOUR_MS,
+            boundary_day - 6 * HOUR_MS,
+            // The day before the boundary keeps only its newest snapshot
+            boundary_day - DAY_MS + 2 * HOUR_MS,
+            boundary_day - DAY_MS + HOUR_MS,
+            boundary_day - DAY_MS - HOUR_MS,
+        ];
+        for millis in snapshots {
+            fs::write(dir.path().join(format!("{}-{}-1.gz", millis, SOURCE_SAVE)), b"x").unwrap();
+        }
+
+        apply_retention(dir.path(), &list_stored(dir.path()).unwrap(), now).unwrap();
+        assert_eq!(remaining(dir.path()), [
+            now - HOUR_MS,
+            now - 2 * HOUR_MS,
+            boundary_day + 6 * HOUR_MS,
+            boundary_day - 6 * HOUR_MS,
+            boundary_day - DAY_MS + 2 * HOUR_MS,
+        ]);
+    }
+
+    #[test]
+    fn stored_names_round_trip() {
+        let revision = StoredRevision::parse("1700000000000-sync-42.gz").unwrap();
+        assert_eq!((revision.millis, revision.size, revision.source.as_str()), (1_700_000_000_000, 42, SOURCE_SYNC));
+        assert!(StoredRevision::parse("1700000000000-sync-42").is_none());
+        assert!(StoredRevision::parse("notanumber-save-1.gz").is_none());
+    }
+}
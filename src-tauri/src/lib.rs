@@ -6,6 +6,14 @@ mod config;
 mod pdf;
 mod tags;
 mod sync;
+mod vault;
+mod search;
+mod secrets;
+mod history;
+mod conflicts;
+mod notebooks;
+mod relocate;
+mod storage;
 
 #[cfg(desktop)]
 use tauri::Emitter;
@@ -18,6 +26,15 @@ pub fn run() {
     .plugin(tauri_plugin_shell::init())
     .plugin(tauri_plugin_os::init())
     .plugin(tauri_plugin_notification::init())
+    .manage(vault::VaultState::default())
+    .manage(search::SearchState::default())
+    .manage(sync::scheduler::SyncScheduler::default())
+    .register_asynchronous_uri_scheme_protocol(diary::IMAGE_PROTOCOL, |ctx, request, responder| {
+      let app = ctx.app_handle().clone();
+      tauri::async_runtime::spawn(async move {
+        responder.respond(diary::image_response(&app, request.uri().path()).await);
+      });
+    })
     .setup(|app| {
       if cfg!(debug_assertions) {
         app.handle().plugin(
@@ -27,6 +44,12 @@ pub fn run() {
         )?;
       }
 
+      // Clean up after writes cut short by a crash before anything reads or writes again
+      storage::recover(app.handle());
+
+      // Background auto sync, idle unless enabled in the sync settings
+      tauri::async_runtime::spawn(sync::scheduler::run(app.handle().clone()));
+
       // Build application menu (desktop only)
       #[cfg(desktop)]
       {
@@ -54,9 +77,17 @@ pub fn run() {
       commands::load_diary,
       commands::get_all_diaries,
       commands::save_image,
+      commands::read_image,
       commands::export_json,
       commands::export_json_range,
       commands::import_json,
+      commands::search_diaries,
+      commands::list_revisions,
+      commands::diff_revisions,
+      commands::restore_revision,
+      commands::list_conflicts,
+      commands::diff_conflict,
+      commands::resolve_conflict,
       commands::export_images,
       commands::import_images,
       commands::get_storage_path,
@@ -71,7 +102,16 @@ pub fn run() {
       commands::verify_password,
       commands::clear_password,
       commands::has_password,
+      commands::get_vault_status,
+      commands::lock_vault,
+      commands::migrate_vault,
       commands::save_background_image,
+      // Notebook commands
+      commands::list_notebooks,
+      commands::create_notebook,
+      commands::rename_notebook,
+      commands::switch_notebook,
+      commands::delete_notebook,
       // Tag commands
       commands::create_tag,
       commands::update_tag,
@@ -88,18 +128,29 @@ pub fn run() {
       // Sync commands
       commands::get_sync_status,
       commands::start_sync,
+      commands::preview_sync,
       commands::force_upload_sync,
+      commands::get_sync_history,
+      commands::get_device_info,
+      commands::set_device_name,
+      commands::list_sync_devices,
+      commands::set_sync_passphrase,
+      commands::disable_sync_encryption,
       commands::get_google_auth_url,
       commands::handle_oauth_callback,
       commands::disconnect_google,
+      commands::prepare_remote_deletion,
       commands::get_sync_settings,
       commands::save_sync_settings,
       commands::save_google_client_id,
       commands::save_google_credentials,
+      commands::save_webdav_password,
       commands::get_google_client_id,
       commands::clear_sync_credentials,
       #[cfg(desktop)]
       commands::start_oauth_callback_server,
+      #[cfg(desktop)]
+      commands::cancel_oauth_callback_server,
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use rand::RngCore;
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::config::{self, Config, Notebook};
+use crate::diary;
+use crate::sync::drive::APP_FOLDER_NAME;
+use crate::sync::scheduler::SyncScheduler;
+use crate::vault;
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookInfo {
+    pub id: String,
+    pub name: String,
+    /// Entry directory, resolved to the default location when none was chosen
+    pub diary_dir: String,
+    pub has_password: bool,
+    pub active: bool,
+    /// Folder on the sync target; enter it on another device to sync the same notebook
+    pub remote_folder: String,
+}
+
+fn info(app: &AppHandle, config: &Config, notebook: &Notebook) -> Result<NotebookInfo, String> {
+    Ok(NotebookInfo {
+        id: notebook.id.clone(),
+        name: notebook.name.clone(),
+        diary_dir: config::get_notebook_diary_dir(app, notebook)?.to_string_lossy().to_string(),
+        has_password: notebook.password_hash.is_some(),
+        active: config.active().id == notebook.id,
+        remote_folder: notebook.remote_folder(),
+    })
+}
+
+/// Trimmed name, unique among the other notebooks regardless of case
+fn clean_name(config: &Config, name: &str, id: Option<&str>) -> Result<String, String> {
+    let name: String = name.trim().chars().take(MAX_NAME_LEN).collect();
+    if name.is_empty() {
+        return Err("Notebook name can't be empty".to_string());
+    }
+    let taken = config.notebooks.iter()
+        .any(|n| Some(n.id.as_str()) != id && n.name.to_lowercase() == name.to_lowercase());
+    if taken {
+        return Err(format!("A notebook named {} already exists", name));
+    }
+    Ok(name)
+}
+
+/// Remote folder name that no other notebook syncs into. Folders of other devices'
+/// notebooks have the app name as prefix, so that prefix is kept.
+fn clean_remote_folder(config: &Config, folder: &str) -> Result<String, String> {
+    let folder = folder.trim();
+    if !folder.starts_with(APP_FOLDER_NAME) || folder == APP_FOLDER_NAME {
+        return Err(format!("Remote folder names start with {}-", APP_FOLDER_NAME));
+    }
+    if folder.contains(['/', '\\']) || folder.len() > MAX_NAME_LEN * 2 {
+        return Err(format!("Invalid remote folder name: {}", folder));
+    }
+    if let Some(notebook) = config.notebooks.iter().find(|n| n.remote_folder() == folder) {
+        return Err(format!("The notebook {} already syncs into {}", notebook.name, folder));
+    }
+    Ok(folder.to_string())
+}
+
+/// Delete the entries, images, tags, history and vault header in `dir`, then `dir`
+/// itself if nothing else is left in it
+fn remove_diary(dir: &Path) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read notebook entries: {}", e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !diary::is_diary_item(&path) {
+            continue;
+        }
+        let removed = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        removed.map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+    }
+
+    if fs::remove_dir(dir).is_err() {
+        println!("[Notebooks] Kept {}, it holds other files", dir.display());
+    }
+    Ok(())
+}
+
+pub fn list(app: &AppHandle) -> Result<Vec<NotebookInfo>, String> {
+    let config = config::read_config(app)?;
+    config.notebooks.iter()
+        .map(|notebook| info(app, &config, notebook))
+        .collect()
+}
+
+/// Add a notebook, stored in `diary_dir` or a new directory in app data. It syncs
+/// into `remote_folder` when joining a notebook created on another device, or
+/// into a new folder otherwise.
+pub fn create(
+    app: &AppHandle,
+    name: &str,
+    diary_dir: Option<String>,
+    remote_folder: Option<String>,
+) -> Result<NotebookInfo, String> {
+    let mut config = config::read_config(app)?;
+    let name = clean_name(&config, name, None)?;
+    let remote_folder = remote_folder
+        .filter(|folder| !folder.trim().is_empty())
+        .map(|folder| clean_remote_folder(&config, &folder))
+        .transpose()?;
+
+    if let Some(dir) = &diary_dir {
+        let path = PathBuf::from(dir);
+        fs::create_dir_all(&path)
+            .map_err(|e| format!("Failed to create diary dir: {}", e))?;
+        // Two notebooks in one directory would share entries, tags and password
+        for notebook in &config.notebooks {
+            if config::get_notebook_diary_dir(app, notebook)? == path {
+                return Err(format!("{} already stores the notebook {}", dir, notebook.name));
+            }
+        }
+    }
+
+    let mut id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id = hex::encode(id);
+    let remote_folder = remote_folder.unwrap_or_else(|| format!("{}-{}", APP_FOLDER_NAME, id));
+    let notebook = Notebook {
+        id,
+        name,
+        diary_dir,
+        password_hash: None,
+        remote_folder: Some(remote_folder),
+    };
+    config.notebooks.push(notebook.clone());
+    config::write_config(app, &config)?;
+
+    println!("[Notebooks] Created {} ({})", notebook.name, notebook.id);
+    info(app, &config, &notebook)
+}
+
+pub fn rename(app: &AppHandle, id: &str, name: &str) -> Result<NotebookInfo, String> {
+    let mut config = config::read_config(app)?;
+    let name = clean_name(&config, name, Some(id))?;
+    let notebook = config.notebook_mut(id)
+        .ok_or_else(|| format!("Notebook not found: {}", id))?;
+    notebook.name = name;
+    let notebook = notebook.clone();
+    config::write_config(app, &config)?;
+    info(app, &config, &notebook)
+}
+
+/// Make `id` the notebook every other command works on. Its password has to be
+/// entered again, as the unlocked key belongs to the previous notebook.
+pub fn switch(app: &AppHandle, id: &str) -> Result<NotebookInfo, String> {
+    let mut config = config::read_config(app)?;
+    if config.active().id == id {
+        return info(app, &config, config.active());
+    }
+    let notebook = config.notebook(id)
+        .ok_or_else(|| format!("Notebook not found: {}", id))?
+        .clone();
+    // Syncs write into the active notebook's directories until they finish
+    let scheduler = app.state::<SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    config.active_notebook = Some(notebook.id.clone());
+    config::write_config(app, &config)?;
+    vault::lock(app);
+
+    println!("[Notebooks] Switched to {}", notebook.name);
+    info(app, &config, &notebook)
+}
+
+/// Remove a notebook with its sync state and search index. The diary files are
+/// only deleted with `delete_entries`; other files in its directory are never touched.
+pub fn delete(app: &AppHandle, id: &str, delete_entries: bool) -> Result<(), String> {
+    let mut config = config::read_config(app)?;
+    let notebook = config.notebook(id)
+        .ok_or_else(|| format!("Notebook not found: {}", id))?
+        .clone();
+    if notebook.is_default() {
+        return Err("The default notebook can't be deleted".to_string());
+    }
+    if config.active().id == notebook.id {
+        return Err("Switch to another notebook before deleting this one".to_string());
+    }
+    let scheduler = app.state::<SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let diary_dir = config::get_notebook_diary_dir(app, &notebook)?;
+    let state_dir = config::get_notebook_state_dir(app, &notebook)?;
+
+    config.notebooks.retain(|n| n.id != notebook.id);
+    config::write_config(app, &config)?;
+
+    fs::remove_dir_all(&state_dir)
+        .map_err(|e| format!("Failed to remove notebook state: {}", e))?;
+    if delete_entries {
+        remove_diary(&diary_dir)?;
+    }
+
+    println!("[Notebooks] Deleted {} ({})", notebook.name, notebook.id);
+    Ok(())
+}
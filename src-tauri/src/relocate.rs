@@ -0,0 +1,300 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+use walkdir::WalkDir;
+
+use crate::{config, diary, storage};
+use crate::sync::engine::CONFLICTS_DIR;
+use crate::sync::metadata::calculate_content_hash;
+use crate::sync::scheduler::SyncScheduler;
+use crate::sync::SyncMetadata;
+use crate::tags::{self, TagsData};
+use crate::vault::{self, Vault, VAULT_FILE};
+
+// Values of the mode passed to change_storage_path
+pub const MODE_MOVE: &str = "move";
+pub const MODE_COPY: &str = "copy";
+/// Only store new changes in the new folder, leaving the files where they are
+pub const MODE_POINT: &str = "point";
+
+const TAGS_FILE: &str = "tags.json";
+
+/// Created and removed again to check the new folder is writable
+const WRITE_PROBE: &str = ".bingo-diary-write-test";
+
+#[derive(Clone, Serialize)]
+struct RelocationProgress {
+    current: u32,
+    total: u32,
+    file: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelocationReport {
+    pub path: String,
+    pub mode: String,
+    /// Files written to the new folder
+    pub copied: usize,
+    /// Files the new folder already had with the same content
+    pub unchanged: usize,
+    /// Whether the new folder already held a diary, which was merged with this one
+    pub merged: bool,
+    /// Conflict copies of files that differed between the two folders
+    pub conflicts: Vec<String>,
+    /// Files removed from the old folder after moving them
+    pub removed: usize,
+}
+
+/// Whether `dir` holds entries or tags of a diary
+fn has_diary(dir: &Path) -> bool {
+    if dir.join(TAGS_FILE).exists() {
+        return true;
+    }
+    fs::read_dir(dir)
+        .map(|entries| entries.flatten().any(|entry| crate::diary::is_entry_file(&entry.path())))
+        .unwrap_or(false)
+}
+
+fn check_target(app: &AppHandle, source: &Path, target: &Path) -> Result<(), String> {
+    if target == source {
+        return Err("The diary is already stored in this folder".to_string());
+    }
+    if target.starts_with(source) {
+        return Err("Choose a folder outside the current diary folder".to_string());
+    }
+
+    let config = config::read_config(app)?;
+    for notebook in config.notebooks.iter().filter(|n| n.id != config.active().id) {
+        let dir = config::get_notebook_diary_dir(app, notebook)?;
+        if fs::canonicalize(&dir).unwrap_or(dir) == target {
+            return Err(format!("This folder stores the notebook {}", notebook.name));
+        }
+    }
+
+    let probe = target.join(WRITE_PROBE);
+    fs::write(&probe, b"")
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| format!("Can't write to {}: {}", target.display(), e))
+}
+
+/// Two diaries can only be merged if their entries are encrypted with the same key, or not at all
+fn check_vaults(source: &Path, target: &Path) -> Result<(), String> {
+    let header = |dir: &Path| fs::read(dir.join(VAULT_FILE)).ok();
+    match (header(source), header(target)) {
+        (None, None) => Ok(()),
+        (Some(ours), Some(theirs)) if ours == theirs => Ok(()),
+        (Some(_), Some(_)) => Err("The folder holds a diary encrypted with another password".to_string()),
+        _ => Err("Only one of the diaries is encrypted; encrypt or decrypt the other first".to_string()),
+    }
+}
+
+/// Write `data` to `path` and read it back to check it arrived intact
+fn write_verified(path: &Path, data: &[u8], hash: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    storage::write_atomic(path, data)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    let written = fs::read(path)
+        .map_err(|e| format!("Failed to read back {}: {}", path.display(), e))?;
+    if calculate_content_hash(&written) != hash {
+        return Err(format!("Verification failed for {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Combine two versions of tags.json, which can be read with the same vault
+fn merge_tags(vault: &Vault, ours: &[u8], theirs: &[u8], path: &Path) -> Result<(), String> {
+    let parse = |data: &[u8]| -> Result<TagsData, String> {
+        serde_json::from_slice(&vault.decrypt(data)?)
+            .map_err(|e| format!("Invalid tags format: {}", e))
+    };
+    let merged = tags::merge_tags_data(parse(theirs)?, parse(ours)?);
+    let json = serde_json::to_string_pretty(&merged)
+        .map_err(|e| format!("Failed to serialize tags: {}", e))?;
+    storage::write_with_backup(path, &vault.encrypt(json.as_bytes())?)
+        .map_err(|e| format!("Failed to write tags: {}", e))
+}
+
+/// Where the new folder's version of a file that differs from ours is kept
+fn conflict_copy_path(target: &Path, relative: &Path) -> PathBuf {
+    let name = relative.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let label = format!("replaced-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    let copy_name = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}.{}.{}", stem, label, ext),
+        _ => format!("{}.{}", name, label),
+    };
+    target.join(CONFLICTS_DIR).join(copy_name)
+}
+
+/// Files of the diary in `source`, leaving out anything else the user keeps there
+fn diary_files(source: &Path) -> Vec<PathBuf> {
+    let items = fs::read_dir(source)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    items.into_iter()
+        .filter(|path| diary::is_diary_item(path))
+        .flat_map(|path| WalkDir::new(path).into_iter().filter_map(|entry| entry.ok()))
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Copy the diary files of `source` into `target`, returning the source files that are
+/// now safely in `target`. Where `target` has a different version, ours replaces it
+/// and theirs is kept as a conflict copy.
+fn copy_files(
+    app: &AppHandle,
+    source: &Path,
+    target: &Path,
+    vault: &Vault,
+    report: &mut RelocationReport,
+) -> Result<Vec<PathBuf>, String> {
+    let files = diary_files(source);
+
+    let total = files.len() as u32;
+    let mut done = Vec::with_capacity(files.len());
+    for (i, path) in files.into_iter().enumerate() {
+        let relative = path.strip_prefix(source)
+            .map_err(|e| e.to_string())?
+            .to_path_buf();
+        let progress = RelocationProgress {
+            current: i as u32 + 1,
+            total,
+            file: relative.to_string_lossy().to_string(),
+        };
+        app.emit("storage-migration-progress", &progress).ok();
+
+        let data = fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let hash = calculate_content_hash(&data);
+        let dest = target.join(&relative);
+
+        match fs::read(&dest) {
+            Err(_) => {
+                write_verified(&dest, &data, &hash)?;
+                report.copied += 1;
+            }
+            Ok(existing) if calculate_content_hash(&existing) == hash => report.unchanged += 1,
+            Ok(existing) if relative == Path::new(TAGS_FILE) => {
+                merge_tags(vault, &data, &existing, &dest)?;
+                report.copied += 1;
+            }
+            // Backups are only a fallback for the file next to them, which is handled itself
+            Ok(_) if relative.to_string_lossy().ends_with(".bak") => {}
+            Ok(existing) => {
+                let copy = conflict_copy_path(target, &relative);
+                write_verified(&copy, &existing, &calculate_content_hash(&existing))?;
+                write_verified(&dest, &data, &hash)?;
+                println!("[Storage] {} differs in the new folder, kept theirs as {}", relative.display(), copy.display());
+                report.copied += 1;
+                report.conflicts.push(copy.strip_prefix(target).unwrap_or(&copy).to_string_lossy().to_string());
+            }
+        }
+        done.push(path);
+    }
+
+    Ok(done)
+}
+
+/// Remove moved files from the old folder, then the diary folders left empty
+fn remove_moved(source: &Path, files: &[PathBuf]) -> usize {
+    let mut removed = 0;
+    for path in files {
+        match fs::remove_file(path) {
+            Ok(()) => removed += 1,
+            Err(e) => println!("[Storage] Failed to remove {}: {}", path.display(), e),
+        }
+    }
+    for name in diary::DIARY_DIRS {
+        for entry in WalkDir::new(source.join(name)).contents_first(true).into_iter().flatten() {
+            if entry.file_type().is_dir() {
+                // Fails on folders that still hold files we didn't move
+                let _ = fs::remove_dir(entry.path());
+            }
+        }
+    }
+    // Kept if the user stores anything else in it
+    let _ = fs::remove_dir(source);
+    removed
+}
+
+/// Make the files already synced unknown again, so the next sync compares the new
+/// folder with the sync target instead of taking missing files for deletions
+fn forget_synced_files(app: &AppHandle) -> Result<(), String> {
+    let mut metadata = SyncMetadata::load(app)
+        .map_err(|e| format!("Failed to read sync metadata: {}", e))?;
+    metadata.files.clear();
+    metadata.changes_token = None;
+    metadata.last_full_sync = None;
+    metadata.save(app)
+        .map_err(|e| format!("Failed to save sync metadata: {}", e))
+}
+
+/// Store the active notebook in `target`. `mode` moves or copies its files there
+/// (merging them with a diary already in `target`), or just points to the folder.
+pub fn relocate(app: &AppHandle, target: &Path, mode: &str) -> Result<RelocationReport, String> {
+    if ![MODE_MOVE, MODE_COPY, MODE_POINT].contains(&mode) {
+        return Err(format!("Unknown storage change mode: {}", mode));
+    }
+    // Syncs write into the diary directory, and no sync may start before the switch is saved
+    let scheduler = app.state::<SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let config = config::read_config(app)?;
+    let source = config::get_notebook_diary_dir(app, config.active())?;
+    let source = fs::canonicalize(&source)
+        .map_err(|e| format!("Failed to resolve {}: {}", source.display(), e))?;
+    fs::create_dir_all(target)
+        .map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+    let target = fs::canonicalize(target)
+        .map_err(|e| format!("Failed to resolve {}: {}", target.display(), e))?;
+    check_target(app, &source, &target)?;
+
+    let mut report = RelocationReport {
+        path: target.to_string_lossy().to_string(),
+        mode: mode.to_string(),
+        merged: has_diary(&target),
+        ..RelocationReport::default()
+    };
+
+    let mut moved = Vec::new();
+    if mode != MODE_POINT {
+        let vault = Vault::open(app, &source);
+        if report.merged {
+            check_vaults(&source, &target)?;
+            if vault.is_locked() {
+                return Err("Unlock the diary before merging it with another one".to_string());
+            }
+        }
+        moved = copy_files(app, &source, &target, &vault, &mut report)?;
+    }
+
+    // The new folder holds other files than the sync metadata describes
+    if mode == MODE_POINT || report.merged {
+        forget_synced_files(app)?;
+    }
+
+    // Switch only once every file is verified in the new folder
+    let mut config = config::read_config(app)?;
+    config.active_mut().diary_dir = Some(report.path.clone());
+    config::write_config(app, &config)?;
+
+    if mode == MODE_POINT && fs::read(source.join(VAULT_FILE)).ok() != fs::read(target.join(VAULT_FILE)).ok() {
+        // The unlocked key belongs to the old folder's diary
+        vault::lock(app);
+    }
+    if mode == MODE_MOVE {
+        report.removed = remove_moved(&source, &moved);
+    }
+
+    println!("[Storage] Diary now in {} ({}): {} copied, {} unchanged, {} conflicts",
+        report.path, mode, report.copied, report.unchanged, report.conflicts.len());
+    Ok(report)
+}
@@ -0,0 +1,624 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tauri::{AppHandle, Manager};
+use serde::{Deserialize, Serialize};
+
+use crate::diary::{is_entry_file, parse_diary_with_events};
+use crate::{config, storage, tags};
+use crate::vault::Vault;
+
+/// Index folder in the state dir: a manifest, and one file per entry under `docs`
+/// so a save only rewrites the part of the index for that entry
+const INDEX_DIR: &str = "search_index";
+const INDEX_MANIFEST: &str = "index.json";
+const INDEX_DOCS_DIR: &str = "docs";
+const INDEX_VERSION: u32 = 1;
+
+// Characters of context shown on each side of the first match
+const SNIPPET_CONTEXT_CHARS: usize = 40;
+const DEFAULT_LIMIT: usize = 50;
+
+/// Inverted index over entry bodies, persisted per entry.
+/// Terms are lowercased words, or single characters for CJK text.
+#[derive(Debug, Default)]
+struct SearchIndex {
+    diary_dir: PathBuf,
+    /// Entry file name -> file state when it was indexed
+    docs: HashMap<String, DocInfo>,
+    /// Term -> entry file name -> token positions
+    postings: BTreeMap<String, HashMap<String, Vec<u32>>>,
+    /// Entries added or removed since the index was last saved
+    dirty: HashSet<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexManifest {
+    version: u32,
+    diary_dir: String,
+}
+
+/// Stored index of one entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocInfo {
+    modified: u64,
+    size: u64,
+    /// Terms of the entry in order, so positions can be rebuilt on load
+    terms: Vec<String>,
+}
+
+/// In-memory copy of the index, loaded on first use
+#[derive(Default)]
+pub struct SearchState {
+    index: Mutex<Option<SearchIndex>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchQuery {
+    /// Words, "quoted phrases" and prefix* terms, all of which must match
+    #[serde(default)]
+    pub query: String,
+    /// Only entries carrying all of these tags
+    #[serde(default)]
+    pub tag_ids: Vec<String>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub date: String,
+    pub snippet: String,
+    /// Highlighted ranges in the snippet, as [start, end) character offsets
+    pub highlights: Vec<(usize, usize)>,
+    pub score: u32,
+}
+
+struct Token {
+    term: String,
+    start: usize,
+    end: usize,
+}
+
+enum Clause {
+    /// Consecutive terms; a single word is a one-term phrase
+    Phrase(Vec<String>),
+    Prefix(String),
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF      // Hiragana, Katakana
+        | 0x3400..=0x4DBF    // CJK Extension A
+        | 0x4E00..=0x9FFF    // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF    // Hangul
+        | 0xF900..=0xFAFF    // CJK Compatibility Ideographs
+        | 0x20000..=0x2FA1F  // CJK Extensions B-F
+    )
+}
+
+/// Split text into terms with byte offsets. CJK characters are indexed one by one,
+/// so a run of them is searched as a phrase of consecutive characters.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word_start: Option<usize> = None;
+
+    let flush = |tokens: &mut Vec<Token>, start: &mut Option<usize>, end: usize| {
+        if let Some(s) = start.take() {
+            tokens.push(Token { term: text[s..end].to_lowercase(), start: s, end });
+        }
+    };
+
+    for (i, c) in text.char_indices() {
+        if is_cjk(c) {
+            flush(&mut tokens, &mut word_start, i);
+            tokens.push(Token { term: c.to_string(), start: i, end: i + c.len_utf8() });
+        } else if c.is_alphanumeric() {
+            if word_start.is_none() {
+                word_start = Some(i);
+            }
+        } else {
+            flush(&mut tokens, &mut word_start, i);
+        }
+    }
+    flush(&mut tokens, &mut word_start, text.len());
+
+    tokens
+}
+
+fn terms_of(text: &str) -> Vec<String> {
+    tokenize(text).into_iter().map(|t| t.term).collect()
+}
+
+fn parse_query(query: &str) -> Vec<Clause> {
+    let mut clauses = Vec::new();
+
+    // Odd segments are inside double quotes
+    for (i, segment) in query.split('"').enumerate() {
+        if i % 2 == 1 {
+            let terms = terms_of(segment);
+            if !terms.is_empty() {
+                clauses.push(Clause::Phrase(terms));
+            }
+            continue;
+        }
+
+        for word in segment.split_whitespace() {
+            let (stem, is_prefix) = match word.strip_suffix('*') {
+                Some(stem) => (stem, true),
+                None => (word, false),
+            };
+            let mut terms = terms_of(stem);
+            if is_prefix && terms.len() == 1 {
+                clauses.push(Clause::Prefix(terms.remove(0)));
+            } else if !terms.is_empty() {
+                clauses.push(Clause::Phrase(terms));
+            }
+        }
+    }
+
+    clauses
+}
+
+/// Entry text that is indexed: the body without the events frontmatter
+fn index_text(content: &str) -> String {
+    parse_diary_with_events(content).0
+}
+
+fn date_of(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn doc_state(path: &Path) -> Option<(u64, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    let modified = meta.modified().ok()?
+        .duration_since(UNIX_EPOCH).ok()?
+        .as_millis() as u64;
+    Some((modified, meta.len()))
+}
+
+/// Entry files in the diary directory, matching what `diary::get_all_diaries` reads
+fn entry_files(diary_dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(diary_dir).map_err(|e| format!("Failed to read directory: {}", e))? {
+        let path = entry.map_err(|e| format!("Failed to read entry: {}", e))?.path();
+        if path.is_file() && is_entry_file(&path) {
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                files.push((name.to_string(), path.clone()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+impl SearchIndex {
+    fn new(diary_dir: &Path) -> Self {
+        Self {
+            diary_dir: diary_dir.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, name: &str, text: &str, modified: u64, size: u64) {
+        let terms = terms_of(text);
+        self.insert(name, DocInfo { modified, size, terms });
+        self.dirty.insert(name.to_string());
+    }
+
+    /// Add the postings of an entry, replacing those it had
+    fn insert(&mut self, name: &str, doc: DocInfo) {
+        self.unlink(name);
+        for (pos, term) in doc.terms.iter().enumerate() {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .entry(name.to_string())
+                .or_default()
+                .push(pos as u32);
+        }
+        self.docs.insert(name.to_string(), doc);
+    }
+
+    fn remove(&mut self, name: &str) {
+        if self.unlink(name) {
+            self.dirty.insert(name.to_string());
+        }
+    }
+
+    fn unlink(&mut self, name: &str) -> bool {
+        let Some(doc) = self.docs.remove(name) else { return false };
+        for term in &doc.terms {
+            if let Some(docs) = self.postings.get_mut(term) {
+                docs.remove(name);
+                if docs.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        true
+    }
+
+    /// Re-index entries changed outside the app (or since the index was saved).
+    /// Entries that can't be read are left out until they can.
+    fn reconcile(&mut self, diary_dir: &Path, vault: &Vault) -> Result<(), String> {
+        let files = entry_files(diary_dir)?;
+
+        let present: HashSet<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        let removed: Vec<String> = self.docs.keys()
+            .filter(|name| !present.contains(name.as_str()))
+            .cloned()
+            .collect();
+        for name in removed {
+            self.remove(&name);
+        }
+
+        for (name, path) in &files {
+            let Some((modified, size)) = doc_state(path) else { continue };
+            let up_to_date = self.docs.get(name)
+                .map(|doc| doc.modified == modified && doc.size == size)
+                .unwrap_or(false);
+            if up_to_date {
+                continue;
+            }
+            match vault.read_to_string(path) {
+                Ok(content) => self.add(name, &index_text(&content), modified, size),
+                Err(e) => {
+                    println!("[Search] Skipping {}: {}", name, e);
+                    self.remove(name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Matches of a clause as doc -> [(start position, length)]
+    fn match_clause(&self, clause: &Clause) -> HashMap<String, Vec<(u32, u32)>> {
+        let mut matches: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+
+        match clause {
+            Clause::Phrase(terms) => {
+                let Some(first) = self.postings.get(&terms[0]) else { return matches };
+                for (doc, positions) in first {
+                    for &pos in positions {
+                        let is_match = terms.iter().enumerate().skip(1).all(|(i, term)| {
+                            self.postings.get(term)
+                                .and_then(|docs| docs.get(doc))
+                                .map(|p| p.binary_search(&(pos + i as u32)).is_ok())
+                                .unwrap_or(false)
+                        });
+                        if is_match {
+                            matches.entry(doc.clone()).or_default().push((pos, terms.len() as u32));
+                        }
+                    }
+                }
+            }
+            Clause::Prefix(prefix) => {
+                let terms = self.postings.range(prefix.clone()..)
+                    .take_while(|(term, _)| term.starts_with(prefix.as_str()));
+                for (_, docs) in terms {
+                    for (doc, positions) in docs {
+                        matches.entry(doc.clone()).or_default()
+                            .extend(positions.iter().map(|&pos| (pos, 1)));
+                    }
+                }
+            }
+        }
+
+        matches
+    }
+}
+
+fn get_index_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(config::get_state_dir(app)?.join(INDEX_DIR))
+}
+
+fn load_index(index_dir: &Path, diary_dir: &Path, vault: &Vault) -> Result<SearchIndex, String> {
+    let manifest = fs::read(index_dir.join(INDEX_MANIFEST)).ok()
+        .and_then(|data| serde_json::from_slice::<IndexManifest>(&data).ok());
+    let docs_dir = index_dir.join(INDEX_DOCS_DIR);
+    let current = manifest
+        .map(|m| m.version == INDEX_VERSION && Path::new(&m.diary_dir) == diary_dir)
+        .unwrap_or(false);
+
+    let mut index = SearchIndex::new(diary_dir);
+    if !current || !docs_dir.is_dir() {
+        // Missing, outdated or built for another directory - rebuild from scratch
+        if index_dir.exists() {
+            fs::remove_dir_all(index_dir)
+                .map_err(|e| format!("Failed to clear search index: {}", e))?;
+        }
+        fs::create_dir_all(&docs_dir)
+            .map_err(|e| format!("Failed to create search index: {}", e))?;
+        let manifest = IndexManifest {
+            version: INDEX_VERSION,
+            diary_dir: diary_dir.to_string_lossy().to_string(),
+        };
+        let data = serde_json::to_vec(&manifest)
+            .map_err(|e| format!("Failed to serialize search index: {}", e))?;
+        storage::write_atomic(&index_dir.join(INDEX_MANIFEST), &data)
+            .map_err(|e| format!("Failed to write search index: {}", e))?;
+        return Ok(index);
+    }
+
+    let entries = fs::read_dir(&docs_dir)
+        .map_err(|e| format!("Failed to read search index: {}", e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".json")) else {
+            continue;
+        };
+        // Unreadable parts are rebuilt by `reconcile`
+        let doc = vault.read(&path).ok()
+            .and_then(|data| serde_json::from_slice::<DocInfo>(&data).ok());
+        if let Some(doc) = doc {
+            index.insert(name, doc);
+        }
+    }
+    Ok(index)
+}
+
+/// Write the entries changed since the last save
+fn save_index(index_dir: &Path, index: &mut SearchIndex, vault: &Vault) -> Result<(), String> {
+    let docs_dir = index_dir.join(INDEX_DOCS_DIR);
+    for name in index.dirty.drain() {
+        let path = docs_dir.join(format!("{}.json", name));
+        match index.docs.get(&name) {
+            Some(doc) => {
+                let data = serde_json::to_vec(doc)
+                    .map_err(|e| format!("Failed to serialize search index: {}", e))?;
+                // The index contains entry text, so it is encrypted along with the diary
+                vault.write(&path, &data)?;
+            }
+            None if path.exists() => {
+                fs::remove_file(&path)
+                    .map_err(|e| format!("Failed to update search index: {}", e))?;
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Run `f` on the up-to-date index, saving the entries it (or reconciling) changed
+fn with_index<R>(
+    app: &AppHandle,
+    diary_dir: &Path,
+    reconcile: bool,
+    f: impl FnOnce(&mut SearchIndex) -> R,
+) -> Result<R, String> {
+    let state = app.state::<SearchState>();
+    let mut guard = state.index.lock().unwrap();
+    let vault = Vault::open(app, diary_dir);
+    if vault.is_locked() {
+        return Err("Diary is locked".to_string());
+    }
+    let index_dir = get_index_dir(app)?;
+
+    let needs_load = guard.as_ref()
+        .map(|index| index.diary_dir != diary_dir)
+        .unwrap_or(true);
+    if needs_load {
+        let mut index = load_index(&index_dir, diary_dir, &vault)?;
+        index.reconcile(diary_dir, &vault)?;
+        *guard = Some(index);
+    } else if reconcile {
+        guard.as_mut().unwrap().reconcile(diary_dir, &vault)?;
+    }
+
+    let index = guard.as_mut().unwrap();
+    let result = f(index);
+    save_index(&index_dir, index, &vault)?;
+
+    Ok(result)
+}
+
+/// Update the index after an entry was written
+pub fn index_entry(app: &AppHandle, diary_dir: &Path, file_name: &str, content: &str) -> Result<(), String> {
+    let path = diary_dir.join(file_name);
+    with_index(app, diary_dir, false, |index| {
+        match doc_state(&path) {
+            Some((modified, size)) => index.add(file_name, &index_text(content), modified, size),
+            None => index.remove(file_name),
+        }
+    })
+}
+
+/// Pick up entries written in bulk (imports, sync downloads and deletions)
+pub fn refresh(app: &AppHandle, diary_dir: &Path) -> Result<(), String> {
+    with_index(app, diary_dir, true, |_| ())
+}
+
+/// Drop the index so it is rebuilt on next use
+pub fn reset(app: &AppHandle) -> Result<(), String> {
+    if let Some(state) = app.try_state::<SearchState>() {
+        *state.index.lock().unwrap() = None;
+    }
+    let index_dir = get_index_dir(app)?;
+    if index_dir.exists() {
+        fs::remove_dir_all(&index_dir)
+            .map_err(|e| format!("Failed to remove search index: {}", e))?;
+    }
+    Ok(())
+}
+
+pub async fn search(app: &AppHandle, diary_dir: &Path, query: SearchQuery) -> Result<Vec<SearchHit>, String> {
+    let clauses = parse_query(&query.query);
+
+    // Entries matching every clause, with their match positions
+    let candidates: Vec<(String, Vec<(u32, u32)>)> = with_index(app, diary_dir, true, |index| {
+        let mut result: Option<HashMap<String, Vec<(u32, u32)>>> = None;
+        for clause in &clauses {
+            let matches = index.match_clause(clause);
+            result = Some(match result {
+                None => matches,
+                Some(mut acc) => {
+                    acc.retain(|doc, _| matches.contains_key(doc));
+                    for (doc, positions) in acc.iter_mut() {
+                        positions.extend(&matches[doc]);
+                    }
+                    acc
+                }
+            });
+        }
+
+        // An empty query lists every entry that passes the filters
+        let result = result.unwrap_or_else(|| {
+            index.docs.keys().map(|name| (name.clone(), Vec::new())).collect()
+        });
+        result.into_iter().collect()
+    })?;
+
+    let tagged_dates: Option<HashSet<String>> = if query.tag_ids.is_empty() {
+        None
+    } else {
+        let data = tags::read_tags_data(app).await?;
+        Some(data.entry_tags.into_iter()
+            .filter(|(_, ids)| query.tag_ids.iter().all(|id| ids.contains(id)))
+            .map(|(date, _)| date)
+            .collect())
+    };
+
+    let vault = Vault::open(app, diary_dir);
+    let mut hits = Vec::new();
+
+    for (name, mut matches) in candidates {
+        let date = date_of(&name);
+
+        if query.start_date.as_ref().map(|start| &date < start).unwrap_or(false)
+            || query.end_date.as_ref().map(|end| &date > end).unwrap_or(false)
+        {
+            continue;
+        }
+        if let Some(ref dates) = tagged_dates {
+            if !dates.contains(&date) {
+                continue;
+            }
+        }
+
+        let content = match vault.read_to_string(&diary_dir.join(&name)) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        matches.sort();
+        let (snippet, highlights) = build_snippet(&index_text(&content), &matches);
+
+        hits.push(SearchHit {
+            date,
+            snippet,
+            highlights,
+            score: matches.len() as u32,
+        });
+    }
+
+    // Most matches first, then newest first
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| b.date.cmp(&a.date)));
+    hits.truncate(query.limit.unwrap_or(DEFAULT_LIMIT));
+
+    Ok(hits)
+}
+
+/// Cut a snippet around the first match and map all matches inside it to character ranges
+fn build_snippet(text: &str, matches: &[(u32, u32)]) -> (String, Vec<(usize, usize)>) {
+    let tokens = tokenize(text);
+
+    // Byte ranges of matched text
+    let ranges: Vec<(usize, usize)> = matches.iter()
+        .filter_map(|&(pos, len)| {
+            let first = tokens.get(pos as usize)?;
+            let last = tokens.get((pos + len - 1) as usize)?;
+            Some((first.start, last.end))
+        })
+        .collect();
+
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let char_at = |byte: usize| chars.partition_point(|&(i, _)| i < byte);
+
+    let (window_start, window_end) = match ranges.first() {
+        Some(&(start, end)) => (
+            char_at(start).saturating_sub(SNIPPET_CONTEXT_CHARS),
+            (char_at(end) + SNIPPET_CONTEXT_CHARS).min(chars.len()),
+        ),
+        None => (0, (SNIPPET_CONTEXT_CHARS * 2).min(chars.len())),
+    };
+
+    let snippet: String = chars[window_start..window_end].iter()
+        .map(|&(_, c)| if c == '\n' || c == '\r' { ' ' } else { c })
+        .collect();
+
+    let highlights = ranges.iter()
+        .map(|&(start, end)| (char_at(start), char_at(end)))
+        .filter(|&(start, end)| start >= window_start && end <= window_end)
+        .map(|(start, end)| (start - window_start, end - window_start))
+        .collect();
+
+    (snippet, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phrases(clauses: &[Clause]) -> Vec<String> {
+        clauses.iter()
+            .map(|clause| match clause {
+                Clause::Phrase(terms) => terms.join(" "),
+                Clause::Prefix(prefix) => format!("{}*", prefix),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_lowercases_words_and_splits_cjk() {
+        let tokens = tokenize("Hello, World! 今天好");
+        let terms: Vec<&str> = tokens.iter().map(|t| t.term.as_str()).collect();
+        assert_eq!(terms, ["hello", "world", "今", "天", "好"]);
+        // Offsets are bytes into the original text
+        assert_eq!((tokens[1].start, tokens[1].end), (7, 12));
+        assert_eq!((tokens[3].start, tokens[3].end), (17, 20));
+    }
+
+    #[test]
+    fn tokenize_splits_words_next_to_cjk() {
+        assert_eq!(terms_of("abc中def"), ["abc", "中", "def"]);
+    }
+
+    #[test]
+    fn parse_query_reads_phrases_and_prefixes() {
+        let clauses = parse_query(r#"walk "Rainy Day" tea* 下雨"#);
+        assert_eq!(phrases(&clauses), ["walk", "rainy day", "tea*", "下 雨"]);
+    }
+
+    #[test]
+    fn parse_query_ignores_empty_parts() {
+        assert!(parse_query(r#"  "" * ,"#).is_empty());
+        // An unclosed quote runs to the end of the query
+        assert_eq!(phrases(&parse_query(r#"a "b c"#)), ["a", "b c"]);
+    }
+
+    #[test]
+    fn phrases_match_consecutive_terms_only() {
+        let mut index = SearchIndex::new(Path::new("/diary"));
+        index.add("2024-01-01.txt", "a rainy day", 0, 0);
+        index.add("2024-01-02.txt", "rainy and a sunny day", 0, 0);
+
+        let matches = index.match_clause(&Clause::Phrase(vec!["rainy".to_string(), "day".to_string()]));
+        assert_eq!(matches.keys().collect::<Vec<_>>(), ["2024-01-01.txt"]);
+        assert_eq!(matches["2024-01-01.txt"], [(1, 2)]);
+
+        index.remove("2024-01-01.txt");
+        let matches = index.match_clause(&Clause::Prefix("rain".to_string()));
+        assert_eq!(matches.keys().collect::<Vec<_>>(), ["2024-01-02.txt"]);
+        assert!(index.dirty.contains("2024-01-01.txt"));
+    }
+}
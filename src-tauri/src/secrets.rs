@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::Path;
+
+use crate::storage;
+use crate::vault::{self, VaultKey};
+
+/// Key for platforms without a keychain (Android, or a Linux desktop without a
+/// Secret Service). It sits next to the data, so there it only guards against
+/// the credentials being read from a copy of the data files alone.
+const FALLBACK_KEY_FILE: &str = "credentials.local.key";
+
+#[cfg(not(target_os = "android"))]
+const KEYCHAIN_SERVICE: &str = "bingo-diary";
+#[cfg(not(target_os = "android"))]
+const KEYCHAIN_USER: &str = "credentials-key";
+
+/// Key read from the OS keychain, kept to avoid a keychain prompt per file
+#[cfg(not(target_os = "android"))]
+static KEYCHAIN_KEY: std::sync::Mutex<Option<VaultKey>> = std::sync::Mutex::new(None);
+
+fn parse_key(hex_key: &str) -> Result<VaultKey, String> {
+    hex::decode(hex_key.trim())
+        .ok()
+        .and_then(|bytes| VaultKey::try_from(bytes.as_slice()).ok())
+        .ok_or_else(|| "Invalid credentials key".to_string())
+}
+
+/// Credentials key in the OS keychain (Keychain, Credential Manager, Secret Service),
+/// created on first use. None if it doesn't exist and `create` is false.
+#[cfg(not(target_os = "android"))]
+fn keychain_key(create: bool) -> Result<Option<VaultKey>, String> {
+    let mut cached = KEYCHAIN_KEY.lock().unwrap();
+    if let Some(key) = *cached {
+        return Ok(Some(key));
+    }
+
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER)
+        .map_err(|e| format!("Failed to open keychain: {}", e))?;
+    let key = match entry.get_password() {
+        Ok(hex_key) => parse_key(&hex_key)?,
+        Err(keyring::Error::NoEntry) if !create => return Ok(None),
+        Err(keyring::Error::NoEntry) => {
+            let key = vault::generate_key();
+            entry.set_password(&hex::encode(key))
+                .map_err(|e| format!("Failed to store credentials key in keychain: {}", e))?;
+            println!("[Secrets] Created credentials key in keychain");
+            key
+        }
+        Err(e) => return Err(format!("Failed to read keychain: {}", e)),
+    };
+    *cached = Some(key);
+    Ok(Some(key))
+}
+
+#[cfg(target_os = "android")]
+fn keychain_key(_create: bool) -> Result<Option<VaultKey>, String> {
+    Err("No keychain on this platform".to_string())
+}
+
+fn credentials_dir(path: &Path) -> Result<&Path, String> {
+    path.parent()
+        .ok_or_else(|| format!("Invalid credentials path: {}", path.display()))
+}
+
+/// Key from `FALLBACK_KEY_FILE` in `dir`, created on first use
+fn fallback_key(dir: &Path, create: bool) -> Result<Option<VaultKey>, String> {
+    let key_path = dir.join(FALLBACK_KEY_FILE);
+    match fs::read_to_string(&key_path) {
+        Ok(hex_key) => return parse_key(&hex_key).map(Some),
+        Err(_) if !create => return Ok(None),
+        Err(_) => {}
+    }
+
+    let key = vault::generate_key();
+    storage::write_private(&key_path, hex::encode(key).as_bytes())
+        .map_err(|e| format!("Failed to create credentials key: {}", e))?;
+    Ok(Some(key))
+}
+
+/// Key new credentials in `dir` are encrypted with: the keychain's where there is one
+fn write_key(dir: &Path) -> Result<VaultKey, String> {
+    match keychain_key(true) {
+        Ok(Some(key)) => return Ok(key),
+        Ok(None) => {}
+        Err(e) => println!("[Secrets] {}, using a local key", e),
+    }
+    fallback_key(dir, true)?.ok_or_else(|| "Failed to create credentials key".to_string())
+}
+
+/// Keys credentials in `dir` may be encrypted with, the current one first
+fn read_keys(dir: &Path) -> Vec<VaultKey> {
+    let keychain = keychain_key(false).unwrap_or_else(|e| {
+        println!("[Secrets] {}", e);
+        None
+    });
+    let fallback = fallback_key(dir, false).unwrap_or_else(|e| {
+        println!("[Secrets] {}", e);
+        None
+    });
+    keychain.into_iter().chain(fallback).collect()
+}
+
+/// Encrypt `data` with the credentials key and write it readable by the owner only
+pub fn write(path: &Path, data: &[u8]) -> Result<(), String> {
+    let key = write_key(credentials_dir(path)?)?;
+    let sealed = vault::seal(&key, data)?;
+    storage::write_private(path, &sealed)
+        .map_err(|e| format!("Failed to save credentials: {}", e))
+}
+
+/// Read credentials saved with `write`. Files from older versions are still plaintext;
+/// they are encrypted on first read.
+/// None if the file is missing or can't be decrypted, e.g. after the keychain was reset.
+pub fn read(path: &Path) -> Option<Vec<u8>> {
+    let dir = credentials_dir(path).ok()?;
+    let data = fs::read(path).ok()?;
+    if !vault::is_encrypted(&data) {
+        match write(path, &data) {
+            Ok(()) => println!("[Secrets] Encrypted {}", path.display()),
+            Err(e) => println!("[Secrets] Failed to encrypt {}: {}", path.display(), e),
+        }
+        return Some(data);
+    }
+
+    let keys = read_keys(dir);
+    let Some((i, plaintext)) = keys.iter()
+        .enumerate()
+        .find_map(|(i, key)| vault::unseal(key, &data).ok().map(|plaintext| (i, plaintext)))
+    else {
+        println!("[Secrets] No key opens {}", path.display());
+        return None;
+    };
+
+    // Sealed with the local key while the keychain was unavailable
+    if i > 0 {
+        if let Err(e) = write(path, &plaintext) {
+            println!("[Secrets] Failed to move {} to the keychain key: {}", path.display(), e);
+        }
+    }
+    Some(plaintext)
+}
@@ -0,0 +1,266 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{AppHandle, Manager};
+
+use crate::{config, diary};
+use crate::sync::engine::UPLOAD_STAGING_DIR;
+use crate::sync::shadow::SHADOW_DIR;
+use crate::sync::SyncMetadata;
+
+// Deep enough for entry history (`.history/{entry}/{revision}`) below a diary directory
+const DIARY_SCAN_DEPTH: usize = 2;
+
+// Distinguishes temp files of concurrent writes to the same file
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Hidden sibling the data is written to before it replaces `path`
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let counter = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.tmp", name, counter))
+}
+
+/// Whether `name` was made by `temp_path`
+fn is_temp_file(name: &str) -> bool {
+    name.strip_prefix('.')
+        .and_then(|n| n.strip_suffix(".tmp"))
+        .and_then(|n| n.rsplit_once('.'))
+        .map(|(name, counter)| {
+            !name.is_empty() && !counter.is_empty() && counter.chars().all(|c| c.is_ascii_digit())
+        })
+        .unwrap_or(false)
+}
+
+/// Previous version of a state file, kept by `write_with_backup`
+pub fn backup_path(path: &Path) -> PathBuf {
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.bak", name))
+}
+
+/// Replace `path` so that readers see either the old or the new content, never a partial write
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    replace_with(path, data, |temp| File::create(temp))
+}
+
+/// Atomic write of a file only the current user may read, for credentials.
+/// Unix permissions are set to 0600; on Windows the file gets an owner-only ACL.
+pub fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    replace_with(path, data, |temp| {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(temp)?;
+        #[cfg(windows)]
+        restrict_to_owner(temp)?;
+        Ok(file)
+    })
+}
+
+/// Replace the inherited ACL of `path` with full access for the current user only.
+/// The ACL moves with the file when the temp file is renamed into place.
+#[cfg(windows)]
+fn restrict_to_owner(path: &Path) -> io::Result<()> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    let user = std::env::var("USERNAME")
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Unknown Windows user"))?;
+    let status = std::process::Command::new("icacls")
+        .arg(path)
+        .args(["/inheritance:r", "/grant:r"])
+        .arg(format!("{}:F", user))
+        .creation_flags(CREATE_NO_WINDOW)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("icacls failed: {}", status)));
+    }
+    Ok(())
+}
+
+fn replace_with(path: &Path, data: &[u8], create: impl Fn(&Path) -> io::Result<File>) -> io::Result<()> {
+    let temp = temp_path(path);
+
+    let result = create(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+
+    // Persist the rename itself; not possible (or needed) on every platform
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+
+    Ok(())
+}
+
+/// Atomic write that first keeps the current content as `{name}.bak`
+pub fn write_with_backup(path: &Path, data: &[u8]) -> io::Result<()> {
+    if path.exists() {
+        write_atomic(&backup_path(path), &fs::read(path)?)?;
+    }
+    write_atomic(path, data)
+}
+
+/// Read and parse a state file, falling back to its backup if it is unreadable or corrupt
+pub fn read_with_recovery<T>(path: &Path, parse: impl Fn(&[u8]) -> Result<T, String>) -> Result<T, String> {
+    let error = match fs::read(path) {
+        Ok(data) => match parse(&data) {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        },
+        Err(e) => e.to_string(),
+    };
+
+    let data = fs::read(backup_path(path)).map_err(|_| error.clone())?;
+    let value = parse(&data).map_err(|_| error.clone())?;
+
+    println!("[Storage] Restored {} from backup after: {}", path.display(), error);
+    // Put the good copy back so the next backup isn't taken from the broken file
+    if let Err(e) = write_atomic(path, &data) {
+        println!("[Storage] Failed to replace {}: {}", path.display(), e);
+    }
+
+    Ok(value)
+}
+
+/// Delete temp files left behind by writes interrupted by a crash, up to `depth`
+/// folders below `dir`; the files they were meant to replace are still intact.
+/// Returns how many were removed.
+pub fn remove_temp_files(dir: &Path, depth: usize) -> usize {
+    let mut removed = 0;
+    for entry in walkdir::WalkDir::new(dir).max_depth(depth).into_iter().flatten() {
+        let is_temp = entry.file_type().is_file()
+            && is_temp_file(&entry.file_name().to_string_lossy());
+        if is_temp && fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// Folders the app writes files to, with how deep their temp files can be.
+/// Diary directories may be shared with other files, so only the diary's own folders are scanned.
+fn write_dirs(app: &AppHandle, cfg: &config::Config) -> Vec<(PathBuf, usize)> {
+    let mut dirs = Vec::new();
+    if let Ok(app_data) = app.path().app_data_dir() {
+        dirs.push((app_data, 1));
+    }
+    for notebook in &cfg.notebooks {
+        if let Ok(state_dir) = config::get_notebook_state_dir(app, notebook) {
+            dirs.push((state_dir.join(SHADOW_DIR), 1));
+            dirs.push((state_dir.join(UPLOAD_STAGING_DIR), 1));
+            dirs.push((state_dir, 1));
+        }
+        if let Ok(diary_dir) = config::get_notebook_diary_dir(app, notebook) {
+            for name in diary::DIARY_DIRS {
+                dirs.push((diary_dir.join(name), DIARY_SCAN_DEPTH));
+            }
+            dirs.push((diary_dir, 1));
+        }
+    }
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+/// Startup check of the app data and diary directories, run before any command can write
+pub fn recover(app: &AppHandle) {
+    // Reading the config restores it from its backup if needed
+    let dirs = match config::read_config(app) {
+        Ok(cfg) => write_dirs(app, &cfg),
+        Err(e) => {
+            println!("[Storage] Config is unreadable: {}", e);
+            app.path().app_data_dir().map(|dir| vec![(dir, 1)]).unwrap_or_default()
+        }
+    };
+    if let Err(e) = SyncMetadata::load(app) {
+        println!("[Storage] Sync metadata is unreadable: {}", e);
+    }
+
+    for (dir, depth) in dirs.iter().filter(|(dir, _)| dir.exists()) {
+        let removed = remove_temp_files(dir, *depth);
+        if removed > 0 {
+            println!("[Storage] Removed {} unfinished writes in {}", removed, dir.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces_without_leaving_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry.txt");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn temp_files_are_recognized_by_name() {
+        let temp = temp_path(Path::new("/diary/2024-01-01.txt"));
+        assert!(is_temp_file(&temp.file_name().unwrap().to_string_lossy()));
+        assert!(is_temp_file(".tags.json.12.tmp"));
+        assert!(!is_temp_file("tags.json"));
+        assert!(!is_temp_file(".tags.json.tmp"));
+        assert!(!is_temp_file(".tags.json.x1.tmp"));
+        assert!(!is_temp_file("..1.tmp"));
+    }
+
+    #[test]
+    fn remove_temp_files_keeps_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("images");
+        fs::create_dir(&nested).unwrap();
+        for path in [
+            dir.path().join(".2024-01-01.txt.0.tmp"),
+            dir.path().join("2024-01-01.txt"),
+            dir.path().join(".hidden"),
+            nested.join(".photo.png.3.tmp"),
+        ] {
+            fs::write(path, b"x").unwrap();
+        }
+
+        // Depth 1 only reaches files directly in the folder
+        assert_eq!(remove_temp_files(dir.path(), 1), 1);
+        assert!(dir.path().join("2024-01-01.txt").exists());
+        assert!(dir.path().join(".hidden").exists());
+        assert_eq!(remove_temp_files(dir.path(), 2), 1);
+    }
+
+    #[test]
+    fn read_with_recovery_falls_back_to_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        write_with_backup(&path, b"good").unwrap();
+        write_with_backup(&path, b"").unwrap();
+
+        let parse = |data: &[u8]| if data.is_empty() {
+            Err("empty".to_string())
+        } else {
+            Ok(data.to_vec())
+        };
+        assert_eq!(read_with_recovery(&path, parse).unwrap(), b"good");
+        // The good copy is put back in place
+        assert_eq!(fs::read(&path).unwrap(), b"good");
+    }
+}
@@ -4,23 +4,29 @@ use oauth2::{
     basic::BasicClient,
     reqwest::async_http_client,
 };
+use crate::{config, secrets};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
 use tauri::{AppHandle, Manager};
-use tokio::sync::Mutex;
 
 // Google OAuth2 endpoints
 const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+// Revocation runs while disconnecting, which shouldn't hang on an unreachable server
+const REVOKE_TIMEOUT_SECS: u64 = 15;
 
 // Google Drive API scope - only access files created by this app
 const DRIVE_SCOPE: &str = "https://www.googleapis.com/auth/drive.file";
 const EMAIL_SCOPE: &str = "https://www.googleapis.com/auth/userinfo.email";
 
-// Default localhost port for desktop OAuth callback
-const DESKTOP_REDIRECT_PORT: u16 = 8234;
+// Redirect target when the code is copied from the browser by hand. Nothing listens there;
+// desktop builds listen on a loopback port of their own, see `callback_server`.
+const MANUAL_REDIRECT_URI: &str = "http://localhost:8234/callback";
+
+// Authorization started by `get_auth_url` and not completed yet
+const PENDING_AUTH_FILE: &str = "pending_auth.json";
 
 // Replace with your Google Cloud Console Client ID
 // This should be configured by the user or stored in a config file
@@ -42,12 +48,25 @@ pub struct AuthConfig {
     pub client_secret: Option<String>,
 }
 
+/// What the token exchange needs from the authorization request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingAuth {
+    pkce_verifier: String,
+    /// CSRF token the redirect must carry back
+    state: String,
+    redirect_uri: String,
+}
+
 pub struct GoogleAuth {
     client_id: String,
     client_secret: Option<String>,
     tokens: Option<TokenSet>,
-    pkce_verifier: Arc<Mutex<Option<PkceCodeVerifier>>>,
+    pending: Option<PendingAuth>,
+    /// The OAuth client configuration is shared by all notebooks
     app_data_dir: PathBuf,
+    /// Tokens and pending authorization of the active notebook, which may sync with its own account
+    state_dir: PathBuf,
 }
 
 impl GoogleAuth {
@@ -55,22 +74,22 @@ impl GoogleAuth {
         let app_data_dir = app.path().app_data_dir()
             .map_err(|e| e.to_string())?;
         fs::create_dir_all(&app_data_dir).map_err(|e| e.to_string())?;
+        let state_dir = config::get_state_dir(app)?;
 
         let auth_config = Self::load_auth_config(&app_data_dir);
         let client_id = auth_config.client_id.unwrap_or_else(|| DEFAULT_CLIENT_ID.to_string());
         let client_secret = auth_config.client_secret;
 
-        let tokens = Self::load_tokens(&app_data_dir);
-
-        // Load PKCE verifier from file if exists
-        let pkce_verifier = Self::load_pkce_verifier(&app_data_dir);
+        let tokens = Self::load_tokens(&state_dir);
+        let pending = Self::load_pending(&state_dir);
 
         Ok(Self {
             client_id,
             client_secret,
             tokens,
-            pkce_verifier: Arc::new(Mutex::new(pkce_verifier)),
+            pending,
             app_data_dir,
+            state_dir,
         })
     }
 
@@ -78,37 +97,32 @@ impl GoogleAuth {
         Self::new(app)
     }
 
-    fn get_pkce_path(app_data_dir: &PathBuf) -> PathBuf {
-        app_data_dir.join("pkce_verifier.txt")
+    fn load_pending(state_dir: &Path) -> Option<PendingAuth> {
+        secrets::read(&state_dir.join(PENDING_AUTH_FILE))
+            .and_then(|content| serde_json::from_slice(&content).ok())
     }
 
-    fn load_pkce_verifier(app_data_dir: &PathBuf) -> Option<PkceCodeVerifier> {
-        let path = Self::get_pkce_path(app_data_dir);
-        fs::read_to_string(&path)
-            .ok()
-            .map(|s| PkceCodeVerifier::new(s.trim().to_string()))
+    fn save_pending(&self, pending: &PendingAuth) -> Result<(), String> {
+        let content = serde_json::to_string(pending)
+            .map_err(|e| e.to_string())?;
+        secrets::write(&self.state_dir.join(PENDING_AUTH_FILE), content.as_bytes())
     }
 
-    fn save_pkce_verifier(&self, verifier: &PkceCodeVerifier) -> Result<(), String> {
-        let path = Self::get_pkce_path(&self.app_data_dir);
-        fs::write(&path, verifier.secret()).map_err(|e| e.to_string())
+    fn clear_pending(&mut self) {
+        self.pending = None;
+        let _ = fs::remove_file(self.state_dir.join(PENDING_AUTH_FILE));
     }
 
-    fn clear_pkce_verifier(&self) {
-        let path = Self::get_pkce_path(&self.app_data_dir);
-        let _ = fs::remove_file(&path);
+    /// CSRF token expected back from the authorization started last
+    pub fn pending_state(&self) -> Option<&str> {
+        self.pending.as_ref().map(|p| p.state.as_str())
     }
 
     fn load_auth_config(app_data_dir: &PathBuf) -> AuthConfig {
         let config_path = app_data_dir.join("google_auth_config.json");
-        if config_path.exists() {
-            fs::read_to_string(&config_path)
-                .ok()
-                .and_then(|content| serde_json::from_str(&content).ok())
-                .unwrap_or_default()
-        } else {
-            AuthConfig::default()
-        }
+        secrets::read(&config_path)
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
     }
 
     pub fn save_client_id(&self, client_id: &str) -> Result<(), String> {
@@ -118,7 +132,7 @@ impl GoogleAuth {
         let config_path = self.app_data_dir.join("google_auth_config.json");
         let content = serde_json::to_string_pretty(&config)
             .map_err(|e| e.to_string())?;
-        fs::write(&config_path, content).map_err(|e| e.to_string())
+        secrets::write(&config_path, content.as_bytes())
     }
 
     pub fn save_client_credentials(&self, client_id: &str, client_secret: Option<&str>) -> Result<(), String> {
@@ -129,30 +143,25 @@ impl GoogleAuth {
         let config_path = self.app_data_dir.join("google_auth_config.json");
         let content = serde_json::to_string_pretty(&config)
             .map_err(|e| e.to_string())?;
-        fs::write(&config_path, content).map_err(|e| e.to_string())
+        secrets::write(&config_path, content.as_bytes())
     }
 
-    fn get_tokens_path(app_data_dir: &PathBuf) -> PathBuf {
-        app_data_dir.join("google_auth.json")
+    fn get_tokens_path(state_dir: &Path) -> PathBuf {
+        state_dir.join("google_auth.json")
     }
 
-    fn load_tokens(app_data_dir: &PathBuf) -> Option<TokenSet> {
-        let path = Self::get_tokens_path(app_data_dir);
-        if path.exists() {
-            fs::read_to_string(&path)
-                .ok()
-                .and_then(|content| serde_json::from_str(&content).ok())
-        } else {
-            None
-        }
+    fn load_tokens(state_dir: &Path) -> Option<TokenSet> {
+        let path = Self::get_tokens_path(state_dir);
+        secrets::read(&path)
+            .and_then(|content| serde_json::from_slice(&content).ok())
     }
 
     fn save_tokens(&self) -> Result<(), String> {
-        let path = Self::get_tokens_path(&self.app_data_dir);
+        let path = Self::get_tokens_path(&self.state_dir);
         if let Some(ref tokens) = self.tokens {
             let content = serde_json::to_string_pretty(tokens)
                 .map_err(|e| e.to_string())?;
-            fs::write(&path, content).map_err(|e| e.to_string())
+            secrets::write(&path, content.as_bytes())
         } else {
             // Delete tokens file if disconnecting
             if path.exists() {
@@ -179,10 +188,7 @@ impl GoogleAuth {
         self.client_id = client_id;
     }
 
-    fn create_oauth_client(&self, _is_mobile: bool) -> Result<BasicClient, String> {
-        // Always use localhost redirect - works for both desktop and mobile
-        let redirect_url = format!("http://localhost:{}/callback", DESKTOP_REDIRECT_PORT);
-
+    fn create_oauth_client(&self, redirect_url: &str) -> Result<BasicClient, String> {
         // Use client_secret if available (required for Desktop apps)
         let client_secret = self.client_secret.as_ref()
             .map(|s| ClientSecret::new(s.clone()));
@@ -193,27 +199,20 @@ impl GoogleAuth {
             AuthUrl::new(AUTH_URL.to_string()).map_err(|e| e.to_string())?,
             Some(TokenUrl::new(TOKEN_URL.to_string()).map_err(|e| e.to_string())?),
         )
-        .set_redirect_uri(RedirectUrl::new(redirect_url).map_err(|e| e.to_string())?);
+        .set_redirect_uri(RedirectUrl::new(redirect_url.to_string()).map_err(|e| e.to_string())?);
 
         Ok(client)
     }
 
-    pub async fn get_auth_url(&self, is_mobile: bool) -> Result<String, String> {
-        let client = self.create_oauth_client(is_mobile)?;
+    /// Start an authorization whose response is sent to `redirect_uri`
+    pub async fn get_auth_url(&mut self, redirect_uri: &str) -> Result<String, String> {
+        let client = self.create_oauth_client(redirect_uri)?;
 
         // Generate PKCE challenge
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
 
-        // Store verifier for later use - both in memory and on disk
-        println!("[OAuth] Saving PKCE verifier...");
-        self.save_pkce_verifier(&pkce_verifier)?;
-        {
-            let mut verifier_guard = self.pkce_verifier.lock().await;
-            *verifier_guard = Some(pkce_verifier);
-        }
-
         // Build authorization URL
-        let (auth_url, _csrf_token) = client
+        let (auth_url, csrf_token) = client
             .authorize_url(CsrfToken::new_random)
             .add_scope(Scope::new(DRIVE_SCOPE.to_string()))
             .add_scope(Scope::new(EMAIL_SCOPE.to_string()))
@@ -222,23 +221,30 @@ impl GoogleAuth {
             .add_extra_param("prompt", "consent") // Force consent to get refresh token
             .url();
 
+        // Kept on disk, since the code may come back to a later command
+        let pending = PendingAuth {
+            pkce_verifier: pkce_verifier.secret().clone(),
+            state: csrf_token.secret().clone(),
+            redirect_uri: redirect_uri.to_string(),
+        };
+        self.save_pending(&pending)?;
+        self.pending = Some(pending);
+
         println!("[OAuth] Auth URL generated");
         Ok(auth_url.to_string())
     }
 
-    pub async fn exchange_code(&mut self, code: &str, is_mobile: bool) -> Result<(), String> {
+    /// Finish the pending authorization. `state` from the redirect must match the one
+    /// sent with it, or the code is refused.
+    pub async fn exchange_code(&mut self, code: &str, state: &str) -> Result<(), String> {
         println!("[OAuth] Starting token exchange...");
-        let client = self.create_oauth_client(is_mobile)?;
-
-        // Get stored PKCE verifier
-        let pkce_verifier = {
-            let mut verifier_guard = self.pkce_verifier.lock().await;
-            verifier_guard.take().ok_or_else(|| {
-                println!("[OAuth] Error: No PKCE verifier found");
-                "No PKCE verifier found".to_string()
-            })?
-        };
-        println!("[OAuth] Got PKCE verifier: {}...", &pkce_verifier.secret()[..pkce_verifier.secret().len().min(10)]);
+        let pending = self.pending.clone()
+            .ok_or("No authorization in progress, connect again")?;
+        if state != pending.state {
+            return Err("Authorization response doesn't match the request, connect again".to_string());
+        }
+        let client = self.create_oauth_client(&pending.redirect_uri)?;
+        let pkce_verifier = PkceCodeVerifier::new(pending.pkce_verifier);
 
         // Exchange authorization code for tokens
         println!("[OAuth] Exchanging code for tokens...");
@@ -291,8 +297,8 @@ impl GoogleAuth {
         let result = self.save_tokens();
         println!("[OAuth] Token save result: {:?}", result.is_ok());
 
-        // Clear PKCE verifier file after successful exchange
-        self.clear_pkce_verifier();
+        // A code can only be exchanged once
+        self.clear_pending();
 
         result
     }
@@ -333,6 +339,14 @@ impl GoogleAuth {
             .ok_or_else(|| "No access token available".to_string())
     }
 
+    /// Treat the access token as expired, e.g. after the API rejected it,
+    /// so the next `get_valid_access_token` refreshes it
+    pub fn expire_access_token(&mut self) {
+        if let Some(tokens) = self.tokens.as_mut() {
+            tokens.expires_at = Some(0);
+        }
+    }
+
     async fn refresh_token(&mut self) -> Result<(), String> {
         let tokens = self.tokens.as_ref().ok_or("Not authenticated")?;
         let refresh_token = tokens.refresh_token.as_ref()
@@ -387,15 +401,53 @@ impl GoogleAuth {
         self.save_tokens()
     }
 
-    pub fn disconnect(&mut self) -> Result<(), String> {
+    /// Revoke the grant at Google, so that the refresh token stops working everywhere
+    pub async fn revoke(&self) -> Result<(), String> {
+        let Some(tokens) = &self.tokens else {
+            return Ok(());
+        };
+        // Revoking the refresh token also revokes the access tokens issued from it
+        let token = tokens.refresh_token.as_ref().unwrap_or(&tokens.access_token);
+
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(REVOKE_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        let response = client
+            .post(REVOKE_URL)
+            .form(&[("token", token.as_str())])
+            .send()
+            .await
+            .map_err(|e| format!("Revoke request failed: {}", e))?;
+
+        if response.status().is_success() {
+            println!("[OAuth] Access revoked");
+            return Ok(());
+        }
+        let error_text = response.text().await.unwrap_or_default();
+        // Already expired or revoked, e.g. from the Google account page
+        if error_text.contains("invalid_token") {
+            return Ok(());
+        }
+        Err(format!("Token revocation failed: {}", error_text))
+    }
+
+    /// Revoke access and delete the tokens. They are deleted even if revocation fails;
+    /// that error is returned as `Ok(Some(error))`.
+    pub async fn disconnect(&mut self) -> Result<Option<String>, String> {
+        let revoke_error = self.revoke().await.err();
         self.tokens = None;
-        self.save_tokens()
+        self.save_tokens()?;
+        Ok(revoke_error)
     }
 
-    pub fn clear_all_credentials(&mut self) -> Result<(), String> {
+    /// `disconnect`, and also forget the OAuth client configuration
+    pub async fn clear_all_credentials(&mut self) -> Result<Option<String>, String> {
+        let revoke_error = self.revoke().await.err();
+
         // Clear tokens
         self.tokens = None;
-        let tokens_path = Self::get_tokens_path(&self.app_data_dir);
+        let tokens_path = Self::get_tokens_path(&self.state_dir);
         if tokens_path.exists() {
             fs::remove_file(&tokens_path).ok();
         }
@@ -406,105 +458,231 @@ impl GoogleAuth {
             fs::remove_file(&config_path).ok();
         }
 
-        // Clear PKCE verifier
-        self.clear_pkce_verifier();
+        // Clear any authorization in progress
+        self.clear_pending();
 
         // Reset to defaults
         self.client_id = DEFAULT_CLIENT_ID.to_string();
         self.client_secret = None;
 
-        Ok(())
+        Ok(revoke_error)
     }
 }
 
+/// Where the authorization response should be sent. Desktop builds start a loopback
+/// listener for it, which `callback_server::wait_for_code` then serves.
+pub async fn prepare_redirect(is_mobile: bool) -> Result<String, String> {
+    #[cfg(desktop)]
+    if !is_mobile {
+        return callback_server::listen().await;
+    }
+    #[cfg(not(desktop))]
+    let _ = is_mobile;
+    Ok(MANUAL_REDIRECT_URI.to_string())
+}
+
 // Desktop-only: HTTP server for OAuth callback
 #[cfg(desktop)]
 pub mod callback_server {
-    use super::DESKTOP_REDIRECT_PORT;
-    use std::io::{Read, Write};
-    use std::net::TcpListener;
+    use super::GoogleAuth;
+    use serde::Serialize;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
     use std::time::Duration;
+    use tauri::AppHandle;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
 
-    pub fn start_callback_server() -> Result<String, String> {
-        println!("[OAuth] Starting callback server on port {}...", DESKTOP_REDIRECT_PORT);
+    // How long the user has to finish signing in
+    const CALLBACK_TIMEOUT_SECS: u64 = 5 * 60;
 
-        // Try binding to 0.0.0.0 first (accepts connections on all interfaces including localhost)
-        // This helps with Windows where localhost might resolve differently
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", DESKTOP_REDIRECT_PORT))
-            .or_else(|_| TcpListener::bind(format!("127.0.0.1:{}", DESKTOP_REDIRECT_PORT)))
-            .map_err(|e| {
-                println!("[OAuth] Failed to bind: {}", e);
-                format!("Failed to start callback server: {}", e)
-            })?;
+    // A browser sends the request line right away; don't let a stray connection hold the server
+    const REQUEST_TIMEOUT_SECS: u64 = 10;
 
-        println!("[OAuth] Server bound successfully, waiting for connection...");
-        listener.set_nonblocking(false).map_err(|e| e.to_string())?;
+    const MAX_REQUEST_BYTES: usize = 8192;
 
-        // Wait for callback (with timeout)
-        let (mut stream, addr) = listener.accept()
-            .map_err(|e| {
-                println!("[OAuth] Failed to accept: {}", e);
-                format!("Failed to accept connection: {}", e)
-            })?;
+    /// Listener bound by `listen`, waiting for `wait_for_code` to serve it
+    struct Pending {
+        listener: TcpListener,
+        cancelled: oneshot::Receiver<()>,
+    }
 
-        println!("[OAuth] Connection accepted from: {}", addr);
-        stream.set_read_timeout(Some(Duration::from_secs(30))).ok();
+    static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+    static CANCEL: Mutex<Option<oneshot::Sender<()>>> = Mutex::new(None);
 
-        let mut buffer = [0; 4096];
-        let bytes_read = stream.read(&mut buffer)
-            .map_err(|e| {
-                println!("[OAuth] Failed to read: {}", e);
-                format!("Failed to read request: {}", e)
-            })?;
+    /// Redirect of the pending authorization, to pass on to `GoogleAuth::exchange_code`
+    #[derive(Debug, Clone, Serialize)]
+    pub struct AuthorizationResponse {
+        pub code: String,
+        pub state: String,
+    }
 
-        println!("[OAuth] Read {} bytes", bytes_read);
-        let request = String::from_utf8_lossy(&buffer[..bytes_read]);
-        println!("[OAuth] Request: {}", request.lines().next().unwrap_or(""));
+    /// Outcome of one request to the listener
+    enum Callback {
+        Code(String),
+        Denied(String),
+        /// Not the redirect, or not for the pending authorization; keep waiting
+        Ignored,
+    }
 
-        // Extract authorization code from URL
-        let code = extract_code_from_request(&request)
-            .ok_or_else(|| {
-                println!("[OAuth] No code found in request");
-                "No authorization code found in callback".to_string()
-            })?;
+    /// Bind a loopback-only listener on a free port, returning the redirect URI that reaches it.
+    /// Replaces a listener from an earlier attempt.
+    pub async fn listen() -> Result<String, String> {
+        cancel();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await
+            .map_err(|e| format!("Failed to start callback server: {}", e))?;
+        let port = listener.local_addr()
+            .map_err(|e| format!("Failed to start callback server: {}", e))?
+            .port();
+
+        let (cancel_tx, cancelled) = oneshot::channel();
+        *PENDING.lock().unwrap() = Some(Pending { listener, cancelled });
+        *CANCEL.lock().unwrap() = Some(cancel_tx);
+
+        println!("[OAuth] Callback server listening on port {}", port);
+        Ok(format!("http://127.0.0.1:{}/callback", port))
+    }
 
-        println!("[OAuth] Got authorization code");
+    /// Stop waiting for the browser, e.g. when the user closes the sign-in dialog
+    pub fn cancel() {
+        PENDING.lock().unwrap().take();
+        if let Some(cancel_tx) = CANCEL.lock().unwrap().take() {
+            let _ = cancel_tx.send(());
+        }
+    }
 
-        // Send success response
-        let response = r#"HTTP/1.1 200 OK
-Content-Type: text/html; charset=utf-8
+    /// Serve the listener from `listen` until the redirect for the pending authorization
+    /// arrives, returning its code and state. Gives up after a timeout or on `cancel`.
+    pub async fn wait_for_code(app: &AppHandle) -> Result<AuthorizationResponse, String> {
+        let Pending { listener, mut cancelled } = PENDING.lock().unwrap().take()
+            .ok_or("No sign-in in progress, connect again")?;
+        let expected_state = GoogleAuth::load(app)?
+            .pending_state()
+            .map(|state| state.to_string())
+            .ok_or("No sign-in in progress, connect again")?;
+
+        let deadline = tokio::time::sleep(Duration::from_secs(CALLBACK_TIMEOUT_SECS));
+        tokio::pin!(deadline);
+
+        loop {
+            let (mut stream, _) = tokio::select! {
+                accepted = listener.accept() => accepted
+                    .map_err(|e| format!("Failed to accept connection: {}", e))?,
+                _ = &mut deadline => return Err("Timed out waiting for Google sign-in".to_string()),
+                _ = &mut cancelled => return Err("Sign-in cancelled".to_string()),
+            };
+
+            match handle(&mut stream, &expected_state).await {
+                Ok(Callback::Code(code)) => {
+                    println!("[OAuth] Got authorization code");
+                    return Ok(AuthorizationResponse { code, state: expected_state });
+                }
+                Ok(Callback::Denied(error)) => return Err(format!("Google sign-in failed: {}", error)),
+                Ok(Callback::Ignored) => {}
+                Err(e) => println!("[OAuth] Ignored callback request: {}", e),
+            }
+        }
+    }
 
-<!DOCTYPE html>
-<html>
-<head><title>BingoDiary - Authorization Successful</title></head>
-<body style="font-family: system-ui; display: flex; justify-content: center; align-items: center; height: 100vh; margin: 0;">
-<div style="text-align: center;">
-<h1 style="color: #4CAF50;">Authorization Successful!</h1>
-<p>You can close this window and return to BingoDiary.</p>
-</div>
-</body>
-</html>"#;
+    async fn handle(stream: &mut TcpStream, expected_state: &str) -> Result<Callback, String> {
+        let request = tokio::time::timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS), read_request(stream)).await
+            .map_err(|_| "Request timed out".to_string())??;
+
+        // GET /callback?code=...&state=... HTTP/1.1
+        let target = request.lines().next()
+            .and_then(|line| line.strip_prefix("GET "))
+            .and_then(|line| line.split_whitespace().next());
+        let Some((path, query)) = target.map(|t| t.split_once('?').unwrap_or((t, ""))) else {
+            respond(stream, "400 Bad Request", false, "Invalid request", "").await;
+            return Ok(Callback::Ignored);
+        };
+        if path != "/callback" {
+            // e.g. the browser asking for /favicon.ico
+            respond(stream, "404 Not Found", false, "Not found", "").await;
+            return Ok(Callback::Ignored);
+        }
 
-        stream.write_all(response.as_bytes()).ok();
+        let params = parse_query(query);
+        if params.get("state").map(|s| s.as_str()) != Some(expected_state) {
+            respond(stream, "400 Bad Request", false, "Authorization failed",
+                "This response doesn't belong to the sign-in started in BingoDiary. Start it again from the app.").await;
+            return Ok(Callback::Ignored);
+        }
+        if let Some(error) = params.get("error") {
+            respond(stream, "200 OK", false, "Authorization failed",
+                &format!("Google returned: {}. You can close this window and try again in BingoDiary.", error)).await;
+            return Ok(Callback::Denied(error.clone()));
+        }
+        let Some(code) = params.get("code").filter(|code| !code.is_empty()) else {
+            respond(stream, "400 Bad Request", false, "Authorization failed", "The response has no authorization code.").await;
+            return Ok(Callback::Ignored);
+        };
 
-        Ok(code)
+        respond(stream, "200 OK", true, "Authorization Successful!", "You can close this window and return to BingoDiary.").await;
+        Ok(Callback::Code(code.clone()))
     }
 
-    fn extract_code_from_request(request: &str) -> Option<String> {
-        // Parse GET /callback?code=xxx&... HTTP/1.1
-        let first_line = request.lines().next()?;
-        let path = first_line.split_whitespace().nth(1)?;
-
-        if let Some(query_start) = path.find('?') {
-            let query = &path[query_start + 1..];
-            for param in query.split('&') {
-                if let Some((key, value)) = param.split_once('=') {
-                    if key == "code" {
-                        return Some(urlencoding::decode(value).ok()?.into_owned());
-                    }
-                }
+    /// Request line and headers; the redirect has no body
+    async fn read_request(stream: &mut TcpStream) -> Result<String, String> {
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await
+                .map_err(|e| format!("Failed to read request: {}", e))?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+            if request.len() > MAX_REQUEST_BYTES {
+                return Err("Request too large".to_string());
             }
         }
-        None
+        Ok(String::from_utf8_lossy(&request).into_owned())
+    }
+
+    fn parse_query(query: &str) -> HashMap<String, String> {
+        query.split('&')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                let value = value.replace('+', " ");
+                let value = urlencoding::decode(&value).map(|v| v.into_owned()).unwrap_or(value);
+                (key.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn escape_html(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    async fn respond(stream: &mut TcpStream, status: &str, success: bool, title: &str, message: &str) {
+        let color = if success { "#4CAF50" } else { "#E53935" };
+        let body = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>BingoDiary - {title}</title></head>
+<body style="font-family: system-ui; display: flex; justify-content: center; align-items: center; height: 100vh; margin: 0;">
+<div style="text-align: center;">
+<h1 style="color: {color};">{title}</h1>
+<p>{message}</p>
+</div>
+</body>
+</html>"#,
+            title = escape_html(title),
+            color = color,
+            message = escape_html(message),
+        );
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body,
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+        let _ = stream.shutdown().await;
     }
 }
@@ -0,0 +1,171 @@
+use crate::storage;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+/// Identity of this installation, created on first use
+const DEVICE_FILE: &str = "device.json";
+
+/// Devices that synced to the account, in the remote app folder
+pub const REMOTE_DEVICES_FILE: &str = "devices.json";
+
+// appProperties keys naming the device that uploaded a file
+pub const DEVICE_ID_PROPERTY: &str = "deviceId";
+pub const DEVICE_NAME_PROPERTY: &str = "deviceName";
+
+// Drive limits a property key and value to 124 bytes together
+const MAX_NAME_BYTES: usize = 124 - DEVICE_NAME_PROPERTY.len();
+
+/// How long a device's last sync time in the remote list may lag before it is refreshed
+const LAST_SYNC_REFRESH_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+}
+
+/// Entry of the remote device list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncedDevice {
+    pub id: String,
+    pub name: String,
+    /// "windows", "macos", "linux", "android" or "ios"
+    pub platform: String,
+    /// RFC3339 time of the device's last successful sync
+    pub last_sync: String,
+}
+
+fn get_device_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app.path().app_data_dir()
+        .map_err(|e| e.to_string())?;
+    fs::create_dir_all(&app_data_dir).map_err(|e| e.to_string())?;
+    Ok(app_data_dir.join(DEVICE_FILE))
+}
+
+fn save(app: &AppHandle, device: &Device) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(device)
+        .map_err(|e| e.to_string())?;
+    storage::write_atomic(&get_device_path(app)?, content.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Name used until the user picks one
+fn default_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| format!("{} device", std::env::consts::OS))
+}
+
+fn clean_name(name: &str) -> String {
+    let name = name.trim();
+    let mut end = name.len().min(MAX_NAME_BYTES);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name[..end].trim_end().to_string()
+}
+
+/// This device, created with a random id the first time
+pub fn load(app: &AppHandle) -> Result<Device, String> {
+    let path = get_device_path(app)?;
+    if let Ok(content) = fs::read_to_string(&path) {
+        if let Ok(device) = serde_json::from_str(&content) {
+            return Ok(device);
+        }
+    }
+
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let device = Device { id: hex::encode(id), name: clean_name(&default_name()) };
+    save(app, &device)?;
+    println!("[Sync] Created device id {}", device.id);
+    Ok(device)
+}
+
+pub fn rename(app: &AppHandle, name: &str) -> Result<Device, String> {
+    let name = clean_name(name);
+    if name.is_empty() {
+        return Err("Device name can't be empty".to_string());
+    }
+    let device = Device { name, ..load(app)? };
+    save(app, &device)?;
+    Ok(device)
+}
+
+/// Conflict copy label for a version made on `name`, safe to use in a file name
+pub fn file_label(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Add or refresh `device` in a parsed remote device list. Returns whether the list
+/// changed; the last sync time is only moved on once it is a day old, so the list isn't
+/// rewritten by every sync of every device.
+pub fn upsert(devices: &mut Vec<SyncedDevice>, device: &Device) -> bool {
+    let now = chrono::Utc::now();
+    let platform = std::env::consts::OS;
+    match devices.iter_mut().find(|d| d.id == device.id) {
+        Some(existing) => {
+            let stale = chrono::DateTime::parse_from_rfc3339(&existing.last_sync)
+                .map(|last_sync| now.signed_duration_since(last_sync) >= chrono::Duration::hours(LAST_SYNC_REFRESH_HOURS))
+                .unwrap_or(true);
+            if existing.name == device.name && existing.platform == platform && !stale {
+                return false;
+            }
+            existing.name = device.name.clone();
+            existing.platform = platform.to_string();
+            existing.last_sync = now.to_rfc3339();
+        }
+        None => devices.push(SyncedDevice {
+            id: device.id.clone(),
+            name: device.name.clone(),
+            platform: platform.to_string(),
+            last_sync: now.to_rfc3339(),
+        }),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_name_trims_and_fits_drive_properties() {
+        assert_eq!(clean_name("  Laptop  "), "Laptop");
+        assert_eq!(clean_name(&"a".repeat(200)).len(), MAX_NAME_BYTES);
+
+        // Multi-byte characters are never cut in half
+        let name = clean_name(&"日".repeat(100));
+        assert!(name.len() <= MAX_NAME_BYTES);
+        assert_eq!(name.len() % "日".len(), 0);
+    }
+
+    #[test]
+    fn file_label_is_safe_in_file_names() {
+        assert_eq!(file_label("Anna's Mac/Book"), "Anna_s_Mac_Book");
+    }
+
+    #[test]
+    fn upsert_rewrites_the_list_only_on_change() {
+        let device = Device { id: "d1".to_string(), name: "Laptop".to_string() };
+        let mut devices = Vec::new();
+        assert!(upsert(&mut devices, &device));
+        assert!(!upsert(&mut devices, &device));
+
+        devices[0].last_sync = (chrono::Utc::now() - chrono::Duration::hours(LAST_SYNC_REFRESH_HOURS)).to_rfc3339();
+        assert!(upsert(&mut devices, &device));
+
+        let renamed = Device { name: "Desktop".to_string(), ..device };
+        assert!(upsert(&mut devices, &renamed));
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "Desktop");
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{config, storage};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+/// Files changed locally since the last sync, as `SyncMetadata::files` keys
+/// (`entries/{name}`, `images/{name}`) with the time of the last change in unix millis.
+/// Routine syncs only hash and compare these.
+const DIRTY_FILE: &str = "sync_dirty.json";
+
+// Saves and the sync engine update the set from different tasks
+static DIRTY_LOCK: Mutex<()> = Mutex::new(());
+
+fn get_dirty_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(config::get_state_dir(app)?.join(DIRTY_FILE))
+}
+
+fn load(path: &Path) -> HashMap<String, i64> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save(path: &Path, keys: &HashMap<String, i64>) -> Result<(), String> {
+    let content = serde_json::to_string(keys).map_err(|e| e.to_string())?;
+    storage::write_atomic(path, content.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Record a local change; a failure only costs a full comparison at the next full sync
+pub fn mark(app: &AppHandle, key: &str) {
+    let _lock = DIRTY_LOCK.lock().unwrap();
+    let result = get_dirty_path(app).and_then(|path| {
+        let mut keys = load(&path);
+        keys.insert(key.to_string(), chrono::Utc::now().timestamp_millis());
+        save(&path, &keys)
+    });
+    if let Err(e) = result {
+        println!("[Sync] Failed to record change of {}: {}", key, e);
+    }
+}
+
+pub fn snapshot(app: &AppHandle) -> HashMap<String, i64> {
+    let _lock = DIRTY_LOCK.lock().unwrap();
+    get_dirty_path(app).map(|path| load(&path)).unwrap_or_default()
+}
+
+/// Forget the changes in `synced`, keeping files changed again while the sync ran
+pub fn clear(app: &AppHandle, synced: &HashMap<String, i64>) -> Result<(), String> {
+    let _lock = DIRTY_LOCK.lock().unwrap();
+    let path = get_dirty_path(app)?;
+    let mut keys = load(&path);
+    keys.retain(|key, changed| synced.get(key) != Some(changed));
+    save(&path, &keys)
+}
@@ -0,0 +1,126 @@
+use super::scheduler::SyncScheduler;
+use super::store::BACKEND_GOOGLE_DRIVE;
+use super::{encryption, DriveClient, GoogleAuth, SyncMetadata};
+use crate::config;
+use rand::RngCore;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+// How long a confirmation token from `prepare_remote_deletion` stays valid
+const CONFIRMATION_TTL: Duration = Duration::from_secs(5 * 60);
+
+struct Confirmation {
+    token: String,
+    email: Option<String>,
+    issued: Instant,
+}
+
+static CONFIRMATION: Mutex<Option<Confirmation>> = Mutex::new(None);
+
+/// What the user confirms before the remote folder is deleted
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteDeletion {
+    /// Pass back to `disconnect` to delete the folder
+    pub token: String,
+    pub account_email: Option<String>,
+    pub folder_name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectReport {
+    /// Whether Google confirmed the revocation; the local tokens are deleted either way
+    pub revoked: bool,
+    pub revoke_error: Option<String>,
+    pub remote_deleted: bool,
+}
+
+/// Issue a single-use token that allows `disconnect` to delete the active notebook's remote folder
+pub fn prepare_remote_deletion(app: &AppHandle) -> Result<RemoteDeletion, String> {
+    let auth = GoogleAuth::load(app)?;
+    if !auth.is_authenticated() {
+        return Err("Not connected to Google Drive".to_string());
+    }
+
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let email = auth.get_email();
+
+    *CONFIRMATION.lock().unwrap() = Some(Confirmation {
+        token: token.clone(),
+        email: email.clone(),
+        issued: Instant::now(),
+    });
+
+    Ok(RemoteDeletion {
+        token,
+        account_email: email,
+        folder_name: config::read_config(app)?.active().remote_folder(),
+    })
+}
+
+/// Consume the confirmation token, checking it was issued for the connected account
+fn confirm(token: &str, email: Option<&String>) -> Result<(), String> {
+    let confirmation = CONFIRMATION.lock().unwrap().take()
+        .ok_or("No remote deletion was requested")?;
+    if confirmation.token != token || confirmation.email.as_ref() != email {
+        return Err("Invalid confirmation token".to_string());
+    }
+    if confirmation.issued.elapsed() > CONFIRMATION_TTL {
+        return Err("Confirmation expired, please try again".to_string());
+    }
+    Ok(())
+}
+
+/// Delete the app folder from Drive and, if Drive is the sync target, forget what was synced with it
+async fn delete_remote(app: &AppHandle, auth: &mut GoogleAuth) -> Result<(), String> {
+    let token = auth.get_valid_access_token().await?;
+    let folder = config::read_config(app)?.active().remote_folder();
+    let deleted = DriveClient::new(token).with_app_folder(folder).delete_app_folder().await?;
+    println!("[Sync] Deleted {} remote app folder(s)", deleted);
+
+    // The sync state of another backend is unaffected by the Drive folder
+    let metadata = SyncMetadata::load(app).unwrap_or_default();
+    if metadata.settings.backend != BACKEND_GOOGLE_DRIVE {
+        return Ok(());
+    }
+
+    // Nothing remote is left, so the next sync must not read the missing files as deletions.
+    // The encryption key file went with the folder too.
+    let mut settings = metadata.settings;
+    settings.e2e_encryption = false;
+    SyncMetadata { settings, ..SyncMetadata::default() }
+        .save(app)
+        .map_err(|e| format!("Failed to reset sync metadata: {}", e))?;
+    encryption::clear_key(app)
+}
+
+/// Revoke the Google grant and delete the local tokens. With a token from
+/// `prepare_remote_deletion`, the remote app folder is deleted first; if that
+/// fails the account stays connected so it can be retried.
+pub async fn disconnect(app: &AppHandle, confirmation: Option<&str>) -> Result<DisconnectReport, String> {
+    // No sync may use the account or the folder while they go away
+    let scheduler = app.state::<SyncScheduler>();
+    let _guard = scheduler.begin()
+        .map_err(|_| "Sync in progress, try again when it finishes".to_string())?;
+
+    let mut auth = GoogleAuth::load(app)?;
+    let mut report = DisconnectReport::default();
+
+    if let Some(token) = confirmation {
+        confirm(token, auth.get_email().as_ref())?;
+        delete_remote(app, &mut auth).await?;
+        report.remote_deleted = true;
+    }
+
+    report.revoke_error = auth.disconnect().await?;
+    report.revoked = report.revoke_error.is_none();
+    if let Some(e) = &report.revoke_error {
+        println!("[OAuth] {}", e);
+    }
+    Ok(report)
+}
@@ -1,16 +1,36 @@
-use reqwest::{multipart, Client};
+use super::auth::GoogleAuth;
+use super::device::{DEVICE_ID_PROPERTY, DEVICE_NAME_PROPERTY};
+use super::encryption::ENCRYPTED_PROPERTY;
+use super::store::{RemoteChanges, RemoteFile, RemoteStore, UploadSource};
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::{multipart, Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
 use std::time::Duration;
-use tokio::fs;
+use tokio::sync::Mutex;
 
-const DRIVE_API_BASE: &str = "https://www.googleapis.com/drive/v3";
-const UPLOAD_API_BASE: &str = "https://www.googleapis.com/upload/drive/v3";
+/// Default for `DriveClient::with_base_url`
+pub const GOOGLE_API_BASE: &str = "https://www.googleapis.com";
 
 // Timeout settings
 const REQUEST_TIMEOUT_SECS: u64 = 30;
 const CONNECT_TIMEOUT_SECS: u64 = 10;
 
+// Retries of transient failures: delays double from the base, with random jitter
+const MAX_ATTEMPTS: u32 = 5;
+const BACKOFF_BASE_MS: u64 = 500;
+const BACKOFF_MAX_MS: u64 = 32_000;
+// Longest `Retry-After` wait honored; a longer one fails the request instead
+const RETRY_AFTER_MAX_SECS: u64 = 120;
+// Reasons of 403 responses that only ask to slow down
+const RATE_LIMIT_REASONS: [&str; 2] = ["rateLimitExceeded", "userRateLimitExceeded"];
+
+// Resumable upload chunk: a multiple of 256 KiB as Drive requires, small enough
+// to finish within the request timeout on a slow connection
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
 // App folder name in Google Drive
 pub const APP_FOLDER_NAME: &str = "BingoDiary";
 pub const ENTRIES_FOLDER_NAME: &str = "entries";
@@ -26,6 +46,34 @@ pub struct DriveFile {
     pub size: Option<String>,
     pub md5_checksum: Option<String>,
     pub parents: Option<Vec<String>>,
+    pub app_properties: Option<HashMap<String, String>>,
+    pub trashed: Option<bool>,
+}
+
+impl DriveFile {
+    /// Read a custom property set by this app on upload
+    pub fn app_property(&self, key: &str) -> Option<&str> {
+        self.app_properties.as_ref()?.get(key).map(|v| v.as_str())
+    }
+}
+
+impl From<DriveFile> for RemoteFile {
+    fn from(file: DriveFile) -> Self {
+        // Files uploaded before encryption was available carry no property
+        let encrypted = file.app_property(ENCRYPTED_PROPERTY) == Some("true");
+        let device_id = file.app_property(DEVICE_ID_PROPERTY).map(|id| id.to_string());
+        let device_name = file.app_property(DEVICE_NAME_PROPERTY).map(|name| name.to_string());
+        Self {
+            id: file.id,
+            name: file.name,
+            modified_time: file.modified_time,
+            size: file.size.and_then(|s| s.parse().ok()),
+            encrypted: Some(encrypted),
+            checksum: file.md5_checksum,
+            device_id,
+            device_name,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,9 +83,59 @@ struct FileListResponse {
     next_page_token: Option<String>,
 }
 
+/// State of a resumable upload session after a request to it
+enum UploadProgress {
+    /// Bytes the session has received so far
+    Received(u64),
+    Done(Box<DriveFile>),
+    Expired,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartPageTokenResponse {
+    start_page_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Change {
+    file_id: Option<String>,
+    #[serde(default)]
+    removed: bool,
+    file: Option<DriveFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeListResponse {
+    #[serde(default)]
+    changes: Vec<Change>,
+    next_page_token: Option<String>,
+    new_start_page_token: Option<String>,
+}
+
 pub struct DriveClient {
     http_client: Client,
-    access_token: String,
+    access_token: RwLock<String>,
+    /// Refreshes the access token when Drive rejects it mid-sync
+    auth: Option<Mutex<GoogleAuth>>,
+    api_base: String,
+    upload_base: String,
+    /// Custom properties attached to every uploaded file
+    app_properties: HashMap<String, String>,
+    /// Name of the app folder in the Drive root
+    app_folder: String,
+}
+
+/// Whether a request can be sent again after a failure that may have reached Drive
+#[derive(Clone, Copy, PartialEq)]
+enum Retry {
+    /// Repeating it has the same effect as sending it once (reads, updates, deletes)
+    Idempotent,
+    /// A repeat could duplicate the effect (creating files); only retried when Drive
+    /// certainly did not process it
+    Unsafe,
 }
 
 impl DriveClient {
@@ -50,12 +148,110 @@ impl DriveClient {
 
         Self {
             http_client,
-            access_token,
+            access_token: RwLock::new(access_token),
+            auth: None,
+            api_base: String::new(),
+            upload_base: String::new(),
+            app_properties: HashMap::new(),
+            app_folder: APP_FOLDER_NAME.to_string(),
         }
+        .with_base_url(GOOGLE_API_BASE)
+    }
+
+    pub fn with_app_properties(mut self, app_properties: HashMap<String, String>) -> Self {
+        self.app_properties = app_properties;
+        self
+    }
+
+    /// Sync into another app folder than `APP_FOLDER_NAME`, e.g. that of a notebook
+    pub fn with_app_folder(mut self, name: String) -> Self {
+        self.app_folder = name;
+        self
+    }
+
+    /// Refresh the access token through `auth` when a request is rejected as unauthorized
+    pub fn with_auth(mut self, auth: GoogleAuth) -> Self {
+        self.auth = Some(Mutex::new(auth));
+        self
+    }
+
+    /// Send requests to another server than Google's, e.g. a local mock
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        self.api_base = format!("{}/drive/v3", base_url);
+        self.upload_base = format!("{}/upload/drive/v3", base_url);
+        self
     }
 
     fn auth_header(&self) -> String {
-        format!("Bearer {}", self.access_token)
+        format!("Bearer {}", self.access_token.read().unwrap())
+    }
+
+    /// Send the request made by `build`, retrying rate limits, server errors and network
+    /// failures with exponential backoff, and refreshing the access token once on a 401.
+    /// Returns the first response that is not retried, whatever its status.
+    async fn send<F>(&self, retry: Retry, build: F) -> Result<Response, String>
+    where
+        F: Fn(&Client) -> Result<RequestBuilder, String>,
+    {
+        let mut refreshed = false;
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let result = build(&self.http_client)?
+                .header("Authorization", self.auth_header())
+                .send()
+                .await;
+            let last_attempt = attempt >= MAX_ATTEMPTS;
+
+            let response = match result {
+                Ok(response) => response,
+                // Nothing was sent if the connection failed
+                Err(e) if !last_attempt && (retry == Retry::Idempotent || e.is_connect()) => {
+                    let delay = backoff_delay(attempt);
+                    println!("[Sync] Drive request failed ({}), retrying in {}ms", e, delay.as_millis());
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+
+            let status = response.status();
+            if status == StatusCode::UNAUTHORIZED && !refreshed {
+                if let Some(auth) = &self.auth {
+                    refreshed = true;
+                    let mut auth = auth.lock().await;
+                    auth.expire_access_token();
+                    let token = auth.get_valid_access_token().await?;
+                    *self.access_token.write().unwrap() = token;
+                    println!("[Sync] Drive access token refreshed");
+                    continue;
+                }
+            }
+
+            // Drive reports most rate limits as 403, with the reason in the body
+            let (response, rate_limited) = if status == StatusCode::FORBIDDEN {
+                let (response, body) = buffer_response(response).await?;
+                (response, is_rate_limit_error(&body))
+            } else {
+                (response, status == StatusCode::TOO_MANY_REQUESTS)
+            };
+
+            // A rate-limited request was not processed; other server errors may have been
+            let server_error = retry == Retry::Idempotent && is_transient_server_error(status);
+            if !(rate_limited || server_error) || last_attempt {
+                return Ok(response);
+            }
+
+            let delay = match retry_after(&response) {
+                Some(delay) if delay > Duration::from_secs(RETRY_AFTER_MAX_SECS) => return Ok(response),
+                Some(delay) => delay,
+                None => backoff_delay(attempt),
+            };
+            println!("[Sync] Drive responded {}, retrying in {}ms", status, delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
     }
 
     /// Find a folder by name under a parent (or root if parent is None)
@@ -72,14 +268,11 @@ impl DriveClient {
 
         let url = format!(
             "{}/files?q={}&fields=files(id,name,mimeType,modifiedTime)",
-            DRIVE_API_BASE,
+            self.api_base,
             urlencoding::encode(&query)
         );
 
-        let response = self.http_client
-            .get(&url)
-            .header("Authorization", self.auth_header())
-            .send()
+        let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
             .await
             .map_err(|e| format!("Failed to search for folder: {}", e))?;
 
@@ -105,12 +298,12 @@ impl DriveClient {
             metadata["parents"] = serde_json::json!([id]);
         }
 
-        let response = self.http_client
-            .post(&format!("{}/files", DRIVE_API_BASE))
-            .header("Authorization", self.auth_header())
-            .header("Content-Type", "application/json")
-            .body(metadata.to_string())
-            .send()
+        let url = format!("{}/files", self.api_base);
+        let response = self
+            .send(Retry::Unsafe, |client| Ok(client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .body(metadata.to_string())))
             .await
             .map_err(|e| format!("Failed to create folder: {}", e))?;
 
@@ -123,12 +316,26 @@ impl DriveClient {
             .map_err(|e| format!("Failed to parse response: {}", e))
     }
 
+    /// Permanently delete the app folder with everything in it, returning how many
+    /// app folders there were (several if devices once created one each)
+    pub async fn delete_app_folder(&self) -> Result<usize, String> {
+        let mut deleted = HashSet::new();
+        while let Some(folder) = self.find_folder(&self.app_folder, None).await? {
+            // Search can lag behind a delete, or list a folder that is already gone (404)
+            if !deleted.insert(folder.id.clone()) {
+                break;
+            }
+            self.delete_file(&folder.id).await?;
+        }
+        Ok(deleted.len())
+    }
+
     /// Ensure app folder structure exists, returns (app_folder_id, entries_folder_id, images_folder_id)
     pub async fn ensure_folder_structure(&self) -> Result<(String, String, String), String> {
         // Find or create main app folder
-        let app_folder = match self.find_folder(APP_FOLDER_NAME, None).await? {
+        let app_folder = match self.find_folder(&self.app_folder, None).await? {
             Some(folder) => folder,
-            None => self.create_folder(APP_FOLDER_NAME, None).await?,
+            None => self.create_folder(&self.app_folder, None).await?,
         };
 
         // Find or create entries subfolder
@@ -146,6 +353,16 @@ impl DriveClient {
         Ok((app_folder.id, entries_folder.id, images_folder.id))
     }
 
+    /// Find the app folder and its subfolders without creating any
+    pub async fn find_folder_structure(&self) -> Result<(Option<String>, Option<String>, Option<String>), String> {
+        let Some(app_folder) = self.find_folder(&self.app_folder, None).await? else {
+            return Ok((None, None, None));
+        };
+        let entries_folder = self.find_folder(ENTRIES_FOLDER_NAME, Some(&app_folder.id)).await?;
+        let images_folder = self.find_folder(IMAGES_FOLDER_NAME, Some(&app_folder.id)).await?;
+        Ok((Some(app_folder.id), entries_folder.map(|f| f.id), images_folder.map(|f| f.id)))
+    }
+
     /// List all files in a folder
     pub async fn list_files(&self, folder_id: &str) -> Result<Vec<DriveFile>, String> {
         let mut all_files = Vec::new();
@@ -154,8 +371,8 @@ impl DriveClient {
         loop {
             let query = format!("'{}' in parents and trashed = false", folder_id);
             let mut url = format!(
-                "{}/files?q={}&fields=files(id,name,mimeType,modifiedTime,size,md5Checksum)&pageSize=1000",
-                DRIVE_API_BASE,
+                "{}/files?q={}&fields=files(id,name,mimeType,modifiedTime,size,md5Checksum,appProperties)&pageSize=1000",
+                self.api_base,
                 urlencoding::encode(&query)
             );
 
@@ -163,10 +380,7 @@ impl DriveClient {
                 url.push_str(&format!("&pageToken={}", token));
             }
 
-            let response = self.http_client
-                .get(&url)
-                .header("Authorization", self.auth_header())
-                .send()
+            let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
                 .await
                 .map_err(|e| format!("Failed to list files: {}", e))?;
 
@@ -189,30 +403,6 @@ impl DriveClient {
         Ok(all_files)
     }
 
-    /// Upload a file to Drive
-    pub async fn upload_file(
-        &self,
-        local_path: &Path,
-        file_name: &str,
-        folder_id: &str,
-        existing_file_id: Option<&str>,
-    ) -> Result<DriveFile, String> {
-        let content = fs::read(local_path).await
-            .map_err(|e| format!("Failed to read local file: {}", e))?;
-
-        let mime_type = mime_guess::from_path(local_path)
-            .first_or_octet_stream()
-            .to_string();
-
-        if let Some(file_id) = existing_file_id {
-            // Update existing file
-            self.update_file_content(file_id, &content, &mime_type).await
-        } else {
-            // Create new file
-            self.create_file(file_name, folder_id, &content, &mime_type).await
-        }
-    }
-
     /// Upload content directly (for text files)
     pub async fn upload_content(
         &self,
@@ -238,33 +428,19 @@ impl DriveClient {
     ) -> Result<DriveFile, String> {
         let metadata = serde_json::json!({
             "name": file_name,
-            "parents": [folder_id]
+            "parents": [folder_id],
+            "appProperties": self.app_properties,
         });
 
-        let form = multipart::Form::new()
-            .part(
-                "metadata",
-                multipart::Part::text(metadata.to_string())
-                    .mime_str("application/json")
-                    .map_err(|e| e.to_string())?,
-            )
-            .part(
-                "file",
-                multipart::Part::bytes(content.to_vec())
-                    .mime_str(mime_type)
-                    .map_err(|e| e.to_string())?,
-            );
-
         let url = format!(
-            "{}/files?uploadType=multipart&fields=id,name,mimeType,modifiedTime,size,md5Checksum",
-            UPLOAD_API_BASE
+            "{}/files?uploadType=multipart&fields=id,name,mimeType,modifiedTime,size,md5Checksum,appProperties",
+            self.upload_base
         );
 
-        let response = self.http_client
-            .post(&url)
-            .header("Authorization", self.auth_header())
-            .multipart(form)
-            .send()
+        let response = self
+            .send(Retry::Unsafe, |client| Ok(client
+                .post(&url)
+                .multipart(multipart_form(&metadata, content, mime_type)?)))
             .await
             .map_err(|e| format!("Failed to upload file: {}", e))?;
 
@@ -283,17 +459,24 @@ impl DriveClient {
         content: &[u8],
         mime_type: &str,
     ) -> Result<DriveFile, String> {
+        // Media-only uploads can't carry metadata, so use multipart when properties are set
+        let upload_type = if self.app_properties.is_empty() { "media" } else { "multipart" };
         let url = format!(
-            "{}/files/{}?uploadType=media&fields=id,name,mimeType,modifiedTime,size,md5Checksum",
-            UPLOAD_API_BASE, file_id
+            "{}/files/{}?uploadType={}&fields=id,name,mimeType,modifiedTime,size,md5Checksum,appProperties",
+            self.upload_base, file_id, upload_type
         );
-
-        let response = self.http_client
-            .patch(&url)
-            .header("Authorization", self.auth_header())
-            .header("Content-Type", mime_type)
-            .body(content.to_vec())
-            .send()
+        let metadata = serde_json::json!({ "appProperties": self.app_properties });
+
+        // Replacing the content with the same bytes again is harmless
+        let response = self
+            .send(Retry::Idempotent, |client| {
+                let request = client.patch(&url);
+                if self.app_properties.is_empty() {
+                    Ok(request.header("Content-Type", mime_type).body(content.to_vec()))
+                } else {
+                    Ok(request.multipart(multipart_form(&metadata, content, mime_type)?))
+                }
+            })
             .await
             .map_err(|e| format!("Failed to update file: {}", e))?;
 
@@ -306,14 +489,110 @@ impl DriveClient {
             .map_err(|e| format!("Failed to parse response: {}", e))
     }
 
+    /// Open a resumable upload session for a new file, or new content of `existing_file_id`
+    pub async fn start_resumable_upload(
+        &self,
+        file_name: &str,
+        folder_id: &str,
+        mime_type: &str,
+        size: u64,
+        existing_file_id: Option<&str>,
+    ) -> Result<String, String> {
+        let (url, metadata) = match existing_file_id {
+            Some(file_id) => (
+                format!(
+                    "{}/files/{}?uploadType=resumable&fields=id,name,mimeType,modifiedTime,size,md5Checksum,appProperties",
+                    self.upload_base, file_id
+                ),
+                serde_json::json!({ "appProperties": self.app_properties }),
+            ),
+            None => (
+                format!(
+                    "{}/files?uploadType=resumable&fields=id,name,mimeType,modifiedTime,size,md5Checksum,appProperties",
+                    self.upload_base
+                ),
+                serde_json::json!({
+                    "name": file_name,
+                    "parents": [folder_id],
+                    "appProperties": self.app_properties,
+                }),
+            ),
+        };
+
+        // Opening a session creates nothing yet, so it is safe to retry
+        let response = self
+            .send(Retry::Idempotent, |client| {
+                let request = match existing_file_id {
+                    Some(_) => client.patch(&url),
+                    None => client.post(&url),
+                };
+                Ok(request
+                    .header("X-Upload-Content-Type", mime_type)
+                    .header("X-Upload-Content-Length", size.to_string())
+                    .json(&metadata))
+            })
+            .await
+            .map_err(|e| format!("Failed to start upload: {}", e))?;
+
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(format!("Upload failed: {}", error));
+        }
+
+        response.headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(|location| location.to_string())
+            .ok_or_else(|| "Upload failed: Drive returned no upload session".to_string())
+    }
+
+    /// Continue a resumable upload from where the session stopped receiving.
+    /// Returns None if the session expired.
+    pub async fn resume_upload(&self, session_uri: &str, source: &UploadSource<'_>) -> Result<Option<DriveFile>, String> {
+        let size = source.size().await?;
+
+        // An empty request with an open range asks how much has arrived
+        let response = self
+            .send(Retry::Idempotent, |client| Ok(client
+                .put(session_uri)
+                .header("Content-Range", format!("bytes */{}", size))
+                .header("Content-Length", "0")))
+            .await
+            .map_err(|e| format!("Failed to resume upload: {}", e))?;
+        let mut offset = match upload_progress(response).await? {
+            UploadProgress::Received(received) => received,
+            UploadProgress::Done(file) => return Ok(Some(*file)),
+            UploadProgress::Expired => return Ok(None),
+        };
+
+        loop {
+            let chunk = source.read_chunk(offset, UPLOAD_CHUNK_SIZE).await?;
+            if chunk.is_empty() {
+                return Err(format!("Upload failed: file ended after {} of {} bytes", offset, size));
+            }
+            let range = format!("bytes {}-{}/{}", offset, offset + chunk.len() as u64 - 1, size);
+
+            // Sending the same range twice is harmless
+            let response = self
+                .send(Retry::Idempotent, |client| Ok(client
+                    .put(session_uri)
+                    .header("Content-Range", range.as_str())
+                    .body(chunk.clone())))
+                .await
+                .map_err(|e| format!("Failed to upload file: {}", e))?;
+            offset = match upload_progress(response).await? {
+                UploadProgress::Received(received) => received,
+                UploadProgress::Done(file) => return Ok(Some(*file)),
+                UploadProgress::Expired => return Ok(None),
+            };
+        }
+    }
+
     /// Download a file from Drive
     pub async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, String> {
-        let url = format!("{}/files/{}?alt=media", DRIVE_API_BASE, file_id);
+        let url = format!("{}/files/{}?alt=media", self.api_base, file_id);
 
-        let response = self.http_client
-            .get(&url)
-            .header("Authorization", self.auth_header())
-            .send()
+        let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
             .await
             .map_err(|e| format!("Failed to download file: {}", e))?;
 
@@ -329,16 +608,14 @@ impl DriveClient {
 
     /// Delete a file from Drive
     pub async fn delete_file(&self, file_id: &str) -> Result<(), String> {
-        let url = format!("{}/files/{}", DRIVE_API_BASE, file_id);
+        let url = format!("{}/files/{}", self.api_base, file_id);
 
-        let response = self.http_client
-            .delete(&url)
-            .header("Authorization", self.auth_header())
-            .send()
+        let response = self.send(Retry::Idempotent, |client| Ok(client.delete(&url)))
             .await
             .map_err(|e| format!("Failed to delete file: {}", e))?;
 
-        if !response.status().is_success() && response.status().as_u16() != 404 {
+        // Already gone, possibly deleted by an earlier attempt that timed out or failed on the server
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
             let error = response.text().await.unwrap_or_default();
             return Err(format!("Delete failed: {}", error));
         }
@@ -346,17 +623,103 @@ impl DriveClient {
         Ok(())
     }
 
+    /// Token for the current state of the Drive, to list later changes from
+    pub async fn get_start_page_token(&self) -> Result<String, String> {
+        let url = format!("{}/changes/startPageToken", self.api_base);
+
+        let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
+            .await
+            .map_err(|e| format!("Failed to get change token: {}", e))?;
+
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(format!("Drive API error: {}", error));
+        }
+
+        let token: StartPageTokenResponse = response.json().await
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+        Ok(token.start_page_token)
+    }
+
+    /// All changes since `page_token`, following pagination
+    pub async fn list_changes(&self, page_token: &str) -> Result<RemoteChanges, String> {
+        let mut result = RemoteChanges::default();
+        let mut page_token = page_token.to_string();
+
+        loop {
+            let url = format!(
+                "{}/changes?pageToken={}&pageSize=1000&spaces=drive&fields={}",
+                self.api_base,
+                urlencoding::encode(&page_token),
+                urlencoding::encode("nextPageToken,newStartPageToken,changes(fileId,removed,file(id,name,mimeType,modifiedTime,size,md5Checksum,parents,appProperties,trashed))")
+            );
+
+            let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
+                .await
+                .map_err(|e| format!("Failed to list changes: {}", e))?;
+
+            if !response.status().is_success() {
+                let error = response.text().await.unwrap_or_default();
+                return Err(format!("Drive API error: {}", error));
+            }
+
+            let list: ChangeListResponse = response.json().await
+                .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+            for change in list.changes {
+                match change.file {
+                    Some(file) if !change.removed && file.trashed != Some(true) => {
+                        let parents = file.parents.clone().unwrap_or_default();
+                        result.changed.push((RemoteFile::from(file), parents));
+                    }
+                    _ => result.removed.extend(change.file_id),
+                }
+            }
+
+            match (list.next_page_token, list.new_start_page_token) {
+                (Some(next), _) => page_token = next,
+                (None, Some(new_start)) => {
+                    result.next_token = new_start;
+                    break;
+                }
+                (None, None) => return Err("Drive returned no change token".to_string()),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Rename a file, keeping its id and content
+    pub async fn rename_file(&self, file_id: &str, new_name: &str) -> Result<DriveFile, String> {
+        let url = format!(
+            "{}/files/{}?fields=id,name,mimeType,modifiedTime,size,md5Checksum,appProperties",
+            self.api_base, file_id
+        );
+
+        let response = self
+            .send(Retry::Idempotent, |client| Ok(client
+                .patch(&url)
+                .json(&serde_json::json!({ "name": new_name }))))
+            .await
+            .map_err(|e| format!("Failed to rename file: {}", e))?;
+
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(format!("Rename failed: {}", error));
+        }
+
+        response.json().await
+            .map_err(|e| format!("Failed to parse response: {}", e))
+    }
+
     /// Get file metadata
     pub async fn get_file_metadata(&self, file_id: &str) -> Result<DriveFile, String> {
         let url = format!(
-            "{}/files/{}?fields=id,name,mimeType,modifiedTime,size,md5Checksum,parents",
-            DRIVE_API_BASE, file_id
+            "{}/files/{}?fields=id,name,mimeType,modifiedTime,size,md5Checksum,parents,appProperties",
+            self.api_base, file_id
         );
 
-        let response = self.http_client
-            .get(&url)
-            .header("Authorization", self.auth_header())
-            .send()
+        let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
             .await
             .map_err(|e| format!("Failed to get file metadata: {}", e))?;
 
@@ -377,15 +740,12 @@ impl DriveClient {
         );
 
         let url = format!(
-            "{}/files?q={}&fields=files(id,name,mimeType,modifiedTime,size,md5Checksum)",
-            DRIVE_API_BASE,
+            "{}/files?q={}&fields=files(id,name,mimeType,modifiedTime,size,md5Checksum,appProperties)",
+            self.api_base,
             urlencoding::encode(&query)
         );
 
-        let response = self.http_client
-            .get(&url)
-            .header("Authorization", self.auth_header())
-            .send()
+        let response = self.send(Retry::Idempotent, |client| Ok(client.get(&url)))
             .await
             .map_err(|e| format!("Failed to search for file: {}", e))?;
 
@@ -400,3 +760,298 @@ impl DriveClient {
         Ok(list.files.into_iter().next())
     }
 }
+
+/// Metadata and content of a multipart upload; built again for every attempt
+fn multipart_form(metadata: &serde_json::Value, content: &[u8], mime_type: &str) -> Result<multipart::Form, String> {
+    Ok(multipart::Form::new()
+        .part(
+            "metadata",
+            multipart::Part::text(metadata.to_string())
+                .mime_str("application/json")
+                .map_err(|e| e.to_string())?,
+        )
+        .part(
+            "file",
+            multipart::Part::bytes(content.to_vec())
+                .mime_str(mime_type)
+                .map_err(|e| e.to_string())?,
+        ))
+}
+
+/// Interpret the response to a request sent to a resumable upload session
+async fn upload_progress(response: Response) -> Result<UploadProgress, String> {
+    match response.status().as_u16() {
+        200 | 201 => response.json().await
+            .map(|file| UploadProgress::Done(Box::new(file)))
+            .map_err(|e| format!("Failed to parse response: {}", e)),
+        // "Resume Incomplete", with the received range as `bytes=0-{last}` if anything arrived
+        308 => {
+            let received = response.headers()
+                .get(reqwest::header::RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.rsplit('-').next())
+                .and_then(|last| last.parse::<u64>().ok())
+                .map(|last| last + 1)
+                .unwrap_or(0);
+            Ok(UploadProgress::Received(received))
+        }
+        404 | 410 => Ok(UploadProgress::Expired),
+        _ => {
+            let error = response.text().await.unwrap_or_default();
+            Err(format!("Upload failed: {}", error))
+        }
+    }
+}
+
+fn is_transient_server_error(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Delay requested by the server, in seconds or as an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str().ok()?;
+    parse_retry_after(value, chrono::Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    // A date already passed means no wait
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(date.signed_duration_since(now).to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Read the body of `response`, returning it with a response that can still be read
+async fn buffer_response(response: Response) -> Result<(Response, Vec<u8>), String> {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.bytes().await
+        .map_err(|e| format!("Failed to read response: {}", e))?
+        .to_vec();
+
+    let mut buffered = http::Response::new(body.clone());
+    *buffered.status_mut() = status;
+    *buffered.headers_mut() = headers;
+    Ok((Response::from(buffered), body))
+}
+
+/// Whether a 403 body is one of Drive's rate limit errors rather than a denied permission
+fn is_rate_limit_error(body: &[u8]) -> bool {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        error: ErrorDetails,
+    }
+    #[derive(Deserialize)]
+    struct ErrorDetails {
+        #[serde(default)]
+        errors: Vec<ErrorReason>,
+    }
+    #[derive(Deserialize)]
+    struct ErrorReason {
+        #[serde(default)]
+        reason: String,
+    }
+
+    serde_json::from_slice::<ErrorBody>(body)
+        .map(|body| body.error.errors.iter().any(|e| RATE_LIMIT_REASONS.contains(&e.reason.as_str())))
+        .unwrap_or(false)
+}
+
+/// Exponential backoff with jitter, so clients that failed together don't retry together
+fn backoff_delay(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE_MS
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(BACKOFF_MAX_MS);
+    Duration::from_millis(rand::thread_rng().gen_range(max / 2..=max))
+}
+
+#[async_trait]
+impl RemoteStore for DriveClient {
+    async fn ensure_folder_structure(&self) -> Result<(String, String, String), String> {
+        DriveClient::ensure_folder_structure(self).await
+    }
+
+    async fn find_folder_structure(&self) -> Result<(Option<String>, Option<String>, Option<String>), String> {
+        DriveClient::find_folder_structure(self).await
+    }
+
+    async fn list_files(&self, folder_id: &str) -> Result<Vec<RemoteFile>, String> {
+        let files = DriveClient::list_files(self, folder_id).await?;
+        Ok(files.into_iter().map(RemoteFile::from).collect())
+    }
+
+    async fn find_file(&self, name: &str, folder_id: &str) -> Result<Option<RemoteFile>, String> {
+        Ok(DriveClient::find_file(self, name, folder_id).await?.map(RemoteFile::from))
+    }
+
+    async fn get_file_metadata(&self, file_id: &str) -> Result<RemoteFile, String> {
+        DriveClient::get_file_metadata(self, file_id).await.map(RemoteFile::from)
+    }
+
+    async fn upload_content(
+        &self,
+        content: &[u8],
+        file_name: &str,
+        folder_id: &str,
+        mime_type: &str,
+        existing_file_id: Option<&str>,
+    ) -> Result<RemoteFile, String> {
+        DriveClient::upload_content(self, content, file_name, folder_id, mime_type, existing_file_id).await
+            .map(RemoteFile::from)
+    }
+
+    async fn start_resumable_upload(
+        &self,
+        file_name: &str,
+        folder_id: &str,
+        mime_type: &str,
+        size: u64,
+        existing_file_id: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        DriveClient::start_resumable_upload(self, file_name, folder_id, mime_type, size, existing_file_id).await
+            .map(Some)
+    }
+
+    async fn resume_upload(&self, session_uri: &str, source: &UploadSource<'_>) -> Result<Option<RemoteFile>, String> {
+        Ok(DriveClient::resume_upload(self, session_uri, source).await?.map(RemoteFile::from))
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, String> {
+        DriveClient::download_file(self, file_id).await
+    }
+
+    async fn start_change_token(&self) -> Result<Option<String>, String> {
+        DriveClient::get_start_page_token(self).await.map(Some)
+    }
+
+    async fn list_changes(&self, token: &str) -> Result<RemoteChanges, String> {
+        DriveClient::list_changes(self, token).await
+    }
+
+    async fn rename_file(&self, file_id: &str, new_name: &str) -> Result<RemoteFile, String> {
+        DriveClient::rename_file(self, file_id, new_name).await.map(RemoteFile::from)
+    }
+
+    async fn delete_file(&self, file_id: &str) -> Result<(), String> {
+        DriveClient::delete_file(self, file_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const FOLDER_LIST: &str = r#"{"files":[{"id":"folder-1","name":"BingoDiary"}]}"#;
+
+    fn forbidden(reason: &str) -> String {
+        format!(r#"{{"error":{{"code":403,"errors":[{{"domain":"usageLimits","reason":"{}"}}]}}}}"#, reason)
+    }
+
+    /// Local stand-in for Drive answering each request with the next of `responses`,
+    /// given as (status, extra header lines, body). Returns its URL and a request count.
+    async fn mock_drive(responses: Vec<(&'static str, &'static str, String)>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            for (status, headers, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                // The requests sent here have no body, so the head is all there is to read
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, headers, body.len(), body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.ok();
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_403() {
+        let (url, requests) = mock_drive(vec![
+            ("403 Forbidden", "Retry-After: 0\r\n", forbidden("userRateLimitExceeded")),
+            ("403 Forbidden", "Retry-After: 0\r\n", forbidden("rateLimitExceeded")),
+            ("200 OK", "", FOLDER_LIST.to_string()),
+        ]).await;
+        let client = DriveClient::new("token".to_string()).with_base_url(&url);
+
+        let folder = client.find_folder(APP_FOLDER_NAME, None).await.unwrap();
+        assert_eq!(folder.map(|f| f.id), Some("folder-1".to_string()));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn other_403_is_not_retried() {
+        let (url, requests) = mock_drive(vec![
+            ("403 Forbidden", "", forbidden("insufficientFilePermissions")),
+            ("200 OK", "", FOLDER_LIST.to_string()),
+        ]).await;
+        let client = DriveClient::new("token".to_string()).with_base_url(&url);
+
+        let error = client.find_folder(APP_FOLDER_NAME, None).await.unwrap_err();
+        assert!(error.contains("insufficientFilePermissions"), "{}", error);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retried_delete_of_a_deleted_file_succeeds() {
+        let (url, requests) = mock_drive(vec![
+            ("503 Service Unavailable", "Retry-After: 0\r\n", String::new()),
+            ("404 Not Found", "", String::new()),
+        ]).await;
+        let client = DriveClient::new("token".to_string()).with_base_url(&url);
+
+        client.delete_file("file-1").await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn delete_failure_is_reported() {
+        let (url, _) = mock_drive(vec![
+            ("400 Bad Request", "", r#"{"error":{"code":400}}"#.to_string()),
+        ]).await;
+        let client = DriveClient::new("token".to_string()).with_base_url(&url);
+
+        assert!(client.delete_file("file-1").await.is_err());
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates() {
+        let now = chrono::DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z").unwrap().with_timezone(&chrono::Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
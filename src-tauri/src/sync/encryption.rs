@@ -0,0 +1,236 @@
+use super::store::{RemoteFile, RemoteStore};
+use crate::{config, secrets};
+use crate::vault::{self, VaultHeader, VaultKey};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tauri::AppHandle;
+
+/// Wrapped sync key in the remote app folder, shared by every device
+pub const REMOTE_HEADER_FILE: &str = "encryption.json";
+
+/// appProperties key marking an uploaded file as end-to-end encrypted
+pub const ENCRYPTED_PROPERTY: &str = "encrypted";
+
+/// Sync key unlocked on this device, together with the header it was unwrapped from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredSyncKey {
+    header: VaultHeader,
+    key: String,
+}
+
+#[derive(Clone)]
+pub struct SyncKey {
+    pub header: VaultHeader,
+    pub key: VaultKey,
+}
+
+/// Content of the header file once encryption was turned off, so other devices
+/// stop encrypting instead of restoring their header
+#[derive(Debug, Serialize, Deserialize)]
+struct DisabledMarker {
+    disabled: bool,
+}
+
+enum RemoteHeader {
+    Key(VaultHeader),
+    Disabled,
+}
+
+fn get_key_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(config::get_state_dir(app)?.join("sync_key.json"))
+}
+
+pub fn load_key(app: &AppHandle) -> Option<SyncKey> {
+    let path = get_key_path(app).ok()?;
+    let content = secrets::read(&path)?;
+    let stored: StoredSyncKey = serde_json::from_slice(&content).ok()?;
+    let key = BASE64.decode(&stored.key).ok()?.try_into().ok()?;
+    Some(SyncKey { header: stored.header, key })
+}
+
+fn save_key(app: &AppHandle, sync_key: &SyncKey) -> Result<(), String> {
+    let stored = StoredSyncKey {
+        header: sync_key.header.clone(),
+        key: BASE64.encode(sync_key.key),
+    };
+    let content = serde_json::to_string_pretty(&stored)
+        .map_err(|e| e.to_string())?;
+    secrets::write(&get_key_path(app)?, content.as_bytes())
+}
+
+pub fn clear_key(app: &AppHandle) -> Result<(), String> {
+    let path = get_key_path(app)?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Unlock the shared sync key with the passphrase, creating it if this is the first device
+pub async fn setup(
+    app: &AppHandle,
+    store: &dyn RemoteStore,
+    app_folder_id: &str,
+    passphrase: &str,
+) -> Result<(), String> {
+    let sync_key = match read_header(store, app_folder_id).await? {
+        Some((_, RemoteHeader::Key(header))) => unlock(header, passphrase)?,
+        existing => {
+            let key = vault::generate_key();
+            let header = vault::wrap_key(&key, passphrase)?;
+            // A disabled marker is replaced in place
+            let existing_id = existing.map(|(remote, _)| remote.id);
+            let created = create_header(store, app_folder_id, &header, existing_id.as_deref()).await?;
+            if created == header {
+                SyncKey { header, key }
+            } else {
+                // Another device enabled encryption at the same time and got there first
+                unlock(created, passphrase)?
+            }
+        }
+    };
+
+    save_key(app, &sync_key)
+}
+
+/// Turn end-to-end encryption off for every device: re-upload the encrypted files in the
+/// given folders decrypted and replace the header with a disabled marker.
+/// Returns the number of files decrypted.
+pub async fn disable(
+    store: &dyn RemoteStore,
+    app_folder_id: &str,
+    folder_ids: &[&str],
+    sync_key: Option<&SyncKey>,
+) -> Result<usize, String> {
+    let mut decrypted = 0;
+    for folder_id in folder_ids {
+        for remote in store.list_files(folder_id).await? {
+            // Drive tells plaintext files (and folders) apart without a download
+            if remote.name == REMOTE_HEADER_FILE || remote.encrypted == Some(false) {
+                continue;
+            }
+            let content = store.download_file(&remote.id).await?;
+            if !vault::is_encrypted(&content) {
+                continue;
+            }
+            let sync_key = sync_key
+                .ok_or("Enter the sync passphrase to decrypt the synced files")?;
+            let content = vault::unseal(&sync_key.key, &content)
+                .map_err(|e| format!("Failed to decrypt {}: {}", remote.name, e))?;
+            let mime_type = mime_guess::from_path(&remote.name)
+                .first_or_octet_stream()
+                .to_string();
+            store.upload_content(&content, &remote.name, folder_id, &mime_type, Some(&remote.id)).await?;
+            decrypted += 1;
+        }
+    }
+
+    let marker = serde_json::to_vec_pretty(&DisabledMarker { disabled: true })
+        .map_err(|e| e.to_string())?;
+    let existing = store.find_file(REMOTE_HEADER_FILE, app_folder_id).await?;
+    store.upload_content(
+        &marker,
+        REMOTE_HEADER_FILE,
+        app_folder_id,
+        "application/json",
+        existing.as_ref().map(|remote| remote.id.as_str()),
+    ).await?;
+    Ok(decrypted)
+}
+
+/// Refuse to sync unless this device uses the same key as the synced data.
+/// With `repair`, a header missing on the sync target is uploaded again.
+pub async fn verify_remote(
+    store: &dyn RemoteStore,
+    app_folder_id: &str,
+    sync_key: Option<&SyncKey>,
+    enabled: bool,
+    repair: bool,
+) -> Result<(), String> {
+    let remote = read_header(store, app_folder_id).await?.map(|(_, header)| header);
+    let mismatch = || Err("Sync passphrase does not match the encryption key of the synced data".to_string());
+
+    match (enabled, sync_key, remote) {
+        (false, _, None | Some(RemoteHeader::Disabled)) => Ok(()),
+        (false, _, Some(RemoteHeader::Key(_))) => Err(
+            "Synced data is end-to-end encrypted. Enable sync encryption and enter the passphrase to sync.".to_string()
+        ),
+        (true, None, _) => Err("Enter the sync passphrase to use end-to-end encryption".to_string()),
+        (true, Some(_), Some(RemoteHeader::Disabled)) => Err(
+            "Sync encryption was turned off on another device. Turn it off here too to keep syncing.".to_string()
+        ),
+        (true, Some(_), None) if !repair => Ok(()),
+        (true, Some(sync_key), None) => {
+            // Header was removed remotely - restore it so other devices can unlock
+            let created = create_header(store, app_folder_id, &sync_key.header, None).await?;
+            if created == sync_key.header { Ok(()) } else { mismatch() }
+        }
+        (true, Some(sync_key), Some(RemoteHeader::Key(header))) => {
+            if header == sync_key.header { Ok(()) } else { mismatch() }
+        }
+    }
+}
+
+fn unlock(header: VaultHeader, passphrase: &str) -> Result<SyncKey, String> {
+    let key = vault::unwrap_key(&header, passphrase)
+        .map_err(|_| "Incorrect sync passphrase".to_string())?;
+    Ok(SyncKey { header, key })
+}
+
+async fn read_header(store: &dyn RemoteStore, app_folder_id: &str) -> Result<Option<(RemoteFile, RemoteHeader)>, String> {
+    let Some(remote) = store.find_file(REMOTE_HEADER_FILE, app_folder_id).await? else {
+        return Ok(None);
+    };
+    let content = store.download_file(&remote.id).await?;
+    let header = parse_header(&content)?;
+    Ok(Some((remote, header)))
+}
+
+fn parse_header(content: &[u8]) -> Result<RemoteHeader, String> {
+    if let Ok(DisabledMarker { disabled: true }) = serde_json::from_slice(content) {
+        return Ok(RemoteHeader::Disabled);
+    }
+    serde_json::from_slice(content)
+        .map(RemoteHeader::Key)
+        .map_err(|e| format!("Invalid encryption header on the sync target: {}", e))
+}
+
+/// Upload a header unless another device created one at the same time, returning the
+/// header that is in place. Stores can't create files conditionally, so the header is
+/// checked after the upload: of several created at once the oldest wins and the others
+/// are removed, and on stores where a later upload replaces the file the content tells.
+async fn create_header(
+    store: &dyn RemoteStore,
+    app_folder_id: &str,
+    header: &VaultHeader,
+    existing_id: Option<&str>,
+) -> Result<VaultHeader, String> {
+    let content = serde_json::to_vec_pretty(header)
+        .map_err(|e| e.to_string())?;
+    let created = store.upload_content(
+        &content,
+        REMOTE_HEADER_FILE,
+        app_folder_id,
+        "application/json",
+        existing_id,
+    ).await?;
+
+    let mut headers: Vec<RemoteFile> = store.list_files(app_folder_id).await?
+        .into_iter()
+        .filter(|remote| remote.name == REMOTE_HEADER_FILE)
+        .collect();
+    headers.sort_by(|a, b| a.modified_time.cmp(&b.modified_time).then_with(|| a.id.cmp(&b.id)));
+    let winner = headers.first()
+        .ok_or("Encryption header disappeared from the sync target")?;
+
+    if winner.id != created.id {
+        store.delete_file(&created.id).await?;
+    }
+    match parse_header(&store.download_file(&winner.id).await?)? {
+        RemoteHeader::Key(header) => Ok(header),
+        RemoteHeader::Disabled => Err("Sync encryption was turned off on another device".to_string()),
+    }
+}
@@ -1,10 +1,20 @@
-use super::drive::DriveClient;
-use super::metadata::{calculate_file_hash, SyncMetadata};
-use super::SyncReport;
+use super::device::{self, Device, SyncedDevice};
+use super::encryption::{self, SyncKey};
+use super::merge::{merge3, MergeResult};
+use super::metadata::{calculate_content_hash, calculate_file_hash, FileMetadata, PendingUpload, SyncMetadata, SyncSettings};
+use super::store::{self, ReadOnlyStore, RemoteChanges, RemoteFile, RemoteStore, UploadSource};
+use super::plan::{self, SyncPlan};
+use super::{dirty, shadow, SyncReport};
+use crate::{config, diary, history};
+use crate::tags::{self, TagsData};
+use crate::vault::{self, Vault};
+use futures_util::stream::{self, StreamExt};
 use serde::Serialize;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use tauri::{AppHandle, Emitter, Manager};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter};
 use tokio::fs;
 use walkdir::WalkDir;
 
@@ -18,22 +28,284 @@ struct SyncProgress {
 
 const TAGS_FILE: &str = "tags.json";
 
+/// Folder in the diary directory for versions that lost an unmergeable conflict
+pub const CONFLICTS_DIR: &str = "conflicts";
+
+// Upper bound for `SyncSettings::max_concurrent_transfers`
+const MAX_CONCURRENT_TRANSFERS: u32 = 16;
+
+// Images at least this large are uploaded in resumable chunks where the backend supports it
+const RESUMABLE_UPLOAD_THRESHOLD: u64 = 5 * 1024 * 1024;
+
+// Drive forgets upload sessions after a week
+const UPLOAD_SESSION_DAYS: i64 = 7;
+
+/// App data folder for end-to-end encrypted uploads in progress. Encryption is randomized,
+/// so a resumed upload has to send the bytes it started with rather than encrypt again.
+pub const UPLOAD_STAGING_DIR: &str = "sync_uploads";
+
+// Routine syncs rely on the change feed and dirty set; this often every file is compared
+// anyway, to catch changes made outside the app
+const FULL_SYNC_INTERVAL_HOURS: i64 = 24;
+
 #[derive(Debug)]
 enum SyncAction {
     Upload { local_path: PathBuf, remote_name: String, remote_id: Option<String> },
     Download { remote_id: String, remote_name: String, local_path: PathBuf },
     DeleteLocal { local_path: PathBuf },
     DeleteRemote { remote_id: String, remote_name: String },
+    /// Changed on both sides since the last sync (or never synced and present on both)
+    Merge { local_path: PathBuf, remote: RemoteFile, base_hash: Option<String> },
+}
+
+impl SyncAction {
+    fn kind(&self) -> &'static str {
+        match self {
+            SyncAction::Upload { .. } => plan::KIND_UPLOAD,
+            SyncAction::Download { .. } => plan::KIND_DOWNLOAD,
+            SyncAction::DeleteLocal { .. } => plan::KIND_DELETE_LOCAL,
+            SyncAction::DeleteRemote { .. } => plan::KIND_DELETE_REMOTE,
+            SyncAction::Merge { .. } => plan::KIND_MERGE,
+        }
+    }
+
+    fn file_name(&self) -> String {
+        match self {
+            SyncAction::Upload { remote_name, .. }
+            | SyncAction::Download { remote_name, .. }
+            | SyncAction::DeleteRemote { remote_name, .. } => remote_name.clone(),
+            SyncAction::DeleteLocal { local_path } => file_name_of(local_path),
+            SyncAction::Merge { remote, .. } => remote.name.clone(),
+        }
+    }
+
+    fn progress_message(&self) -> String {
+        let verb = match self {
+            SyncAction::Upload { .. } => "Uploading",
+            SyncAction::Download { .. } => "Downloading",
+            SyncAction::DeleteLocal { .. } => "Deleting local",
+            SyncAction::DeleteRemote { .. } => "Deleting remote",
+            SyncAction::Merge { .. } => "Merging",
+        };
+        format!("{} {}...", verb, self.file_name())
+    }
+}
+
+enum ImageAction {
+    Upload { name: String, local_path: PathBuf, remote_id: Option<String> },
+    Download { name: String, remote: RemoteFile },
+    /// Changed on both sides, or present on both before the first sync
+    Resolve { local_path: PathBuf, remote: RemoteFile },
+    DeleteLocal { name: String, local_path: PathBuf },
+    DeleteRemote { name: String, remote: RemoteFile },
+}
+
+impl ImageAction {
+    fn progress_message(&self) -> String {
+        match self {
+            ImageAction::Upload { name, .. } => format!("Uploading {}...", name),
+            ImageAction::Download { name, .. } => format!("Downloading {}...", name),
+            ImageAction::Resolve { remote, .. } => format!("Comparing {}...", remote.name),
+            ImageAction::DeleteLocal { name, .. } => format!("Deleting local {}...", name),
+            ImageAction::DeleteRemote { name, .. } => format!("Deleting remote {}...", name),
+        }
+    }
+}
+
+/// Progress of a sync stage whose actions run concurrently; `current` counts finished actions
+struct StageProgress<'a> {
+    engine: &'a SyncEngine,
+    stage: &'a str,
+    total: u32,
+    completed: AtomicU32,
+}
+
+impl<'a> StageProgress<'a> {
+    fn new(engine: &'a SyncEngine, stage: &'a str, total: usize) -> Self {
+        Self { engine, stage, total: total as u32, completed: AtomicU32::new(0) }
+    }
+
+    fn start(&self, message: &str) {
+        let completed = self.completed.load(Ordering::SeqCst);
+        self.engine.emit_progress(self.stage, completed, self.total, message);
+    }
+
+    fn finish(&self, message: &str) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        self.engine.emit_progress(self.stage, completed, self.total, message);
+    }
+}
+
+/// Files a routine sync has to look at: changed remotely since the last change token,
+/// or marked dirty locally since the last sync
+struct ChangeScope {
+    /// `SyncMetadata::files` keys to compare
+    keys: HashSet<String>,
+    /// Current state of files reported by the change feed, by key
+    remote: HashMap<String, RemoteFile>,
+    /// Ids of every file in the change feed, including deleted and moved ones
+    touched_ids: HashSet<String>,
+    next_token: String,
+}
+
+impl ChangeScope {
+    fn from_changes(changes: RemoteChanges, metadata: &SyncMetadata, dirty: &HashMap<String, i64>) -> Self {
+        let mut scope = Self {
+            keys: dirty.keys().cloned().collect(),
+            remote: HashMap::new(),
+            touched_ids: changes.removed.into_iter().collect(),
+            next_token: changes.next_token,
+        };
+
+        let folders = [
+            (metadata.entries_folder_id.as_ref(), "entries"),
+            (metadata.images_folder_id.as_ref(), "images"),
+        ];
+        for (file, parents) in changes.changed {
+            scope.touched_ids.insert(file.id.clone());
+            let prefix = folders.iter()
+                .find(|(folder_id, _)| folder_id.map(|id| parents.contains(id)).unwrap_or(false))
+                .map(|(_, prefix)| *prefix);
+            if let Some(prefix) = prefix {
+                let key = format!("{}/{}", prefix, file.name);
+                scope.keys.insert(key.clone());
+                scope.remote.insert(key, file);
+            }
+        }
+
+        // Files renamed, moved or deleted remotely are gone under the name they were synced as
+        for (key, file_meta) in &metadata.files {
+            let touched = file_meta.remote_id.as_ref()
+                .map(|id| scope.touched_ids.contains(id))
+                .unwrap_or(false);
+            if touched {
+                scope.keys.insert(key.clone());
+            }
+        }
+
+        scope
+    }
+
+    fn contains(&self, prefix: &str, name: &str) -> bool {
+        self.keys.contains(&format!("{}/{}", prefix, name))
+    }
+
+    /// Remote state of the files in scope under `prefix` ("entries" or "images"),
+    /// which are kept in `folder_id`
+    async fn remote_files(
+        &self,
+        store: &dyn RemoteStore,
+        metadata: &SyncMetadata,
+        prefix: &str,
+        folder_id: &str,
+    ) -> Result<Vec<RemoteFile>, String> {
+        let mut files = Vec::new();
+        for key in self.keys.iter().filter(|key| key.starts_with(&format!("{}/", prefix))) {
+            if let Some(file) = self.remote.get(key) {
+                files.push(file.clone());
+                continue;
+            }
+            // Not in the change feed, so unchanged remotely since the last sync - if it exists
+            let file_meta = metadata.get_file_metadata(key);
+            let remote_id = file_meta
+                .and_then(|file_meta| file_meta.remote_id.as_ref())
+                .filter(|id| !self.touched_ids.contains(*id));
+            if let Some(remote_id) = remote_id {
+                files.push(store.get_file_metadata(remote_id).await?);
+            } else if file_meta.is_none() {
+                // Never synced here, but the sync target may have had it since before the
+                // change token, e.g. from another device or outside the download window
+                let name = &key[prefix.len() + 1..];
+                if let Some(file) = store.find_file(name, folder_id).await? {
+                    files.push(file);
+                }
+            }
+        }
+        Ok(files)
+    }
 }
 
 pub struct SyncEngine {
     app: AppHandle,
     diary_dir: PathBuf,
+    vault: Vault,
+    settings: SyncSettings,
+    sync_key: Option<SyncKey>,
+    device: Option<Device>,
 }
 
 impl SyncEngine {
     pub fn new(app: AppHandle, diary_dir: PathBuf) -> Self {
-        Self { app, diary_dir }
+        // Files are synced in plaintext form; local encryption is applied on read/write
+        let vault = Vault::open(&app, &diary_dir);
+
+        let settings = SyncMetadata::load(&app).map(|m| m.settings).unwrap_or_default();
+        let sync_key = if settings.e2e_encryption {
+            encryption::load_key(&app)
+        } else {
+            None
+        };
+        let device = device::load(&app).ok();
+
+        Self {
+            app,
+            diary_dir,
+            vault,
+            settings,
+            sync_key,
+            device,
+        }
+    }
+
+    /// Open the sync target selected in the settings
+    pub async fn connect(&self) -> Result<Box<dyn RemoteStore>, String> {
+        store::connect(&self.app, &self.settings, self.sync_key.is_some()).await
+    }
+
+    /// Encrypt content for upload when end-to-end encryption is on
+    fn seal_upload(&self, content: &[u8]) -> Result<Vec<u8>, String> {
+        match &self.sync_key {
+            Some(sync_key) => vault::seal(&sync_key.key, content),
+            None => Ok(content.to_vec()),
+        }
+    }
+
+    /// Decrypt downloaded content if it was uploaded encrypted
+    fn open_download(&self, content: Vec<u8>) -> Result<Vec<u8>, String> {
+        if !vault::is_encrypted(&content) {
+            return Ok(content);
+        }
+        let sync_key = self.sync_key.as_ref()
+            .ok_or("Synced file is encrypted, enter the sync passphrase")?;
+        vault::unseal(&sync_key.key, &content)
+    }
+
+    /// A remote file that should be re-uploaded to match the current encryption setting
+    fn needs_reencryption(&self, remote: &RemoteFile, metadata: &SyncMetadata) -> bool {
+        needs_reencryption(remote, self.sync_key.is_some(), metadata.reencrypted)
+    }
+
+    /// Side whose version stays live when both changed and can't be merged
+    fn pick_winner(&self, local: &[u8], remote: &[u8], base_hash: Option<&str>, remote_file: &RemoteFile) -> Side {
+        pick_winner(
+            &calculate_content_hash(local),
+            &calculate_content_hash(remote),
+            base_hash,
+            remote_file.device_id.as_deref(),
+            self.device.as_ref().map(|device| device.id.as_str()),
+        )
+    }
+
+    /// Conflict copy label for the version on this device
+    fn local_label(&self) -> String {
+        self.device.as_ref()
+            .map(|device| device::file_label(&device.name))
+            .unwrap_or_else(|| "local".to_string())
+    }
+
+    /// How many transfers may run at once
+    fn concurrency(&self) -> usize {
+        self.settings.max_concurrent_transfers.clamp(1, MAX_CONCURRENT_TRANSFERS) as usize
     }
 
     fn emit_progress(&self, stage: &str, current: u32, total: u32, message: &str) {
@@ -46,87 +318,182 @@ impl SyncEngine {
         self.app.emit("sync-progress", &progress).ok();
     }
 
-    pub async fn sync(&self, access_token: &str) -> Result<SyncReport, String> {
+    /// Sync both sides, running the actions `plan` admits. A preview plan leaves
+    /// everything untouched, including the sync state.
+    pub async fn sync(&self, store: &dyn RemoteStore, plan: &mut SyncPlan) -> Result<SyncReport, String> {
         let start_time = std::time::Instant::now();
         let mut report = SyncReport::default();
 
-        println!("[Sync] Starting sync...");
+        if self.vault.is_locked() {
+            return Err("Diary is locked".to_string());
+        }
 
-        // Emit sync started event
-        self.app.emit("sync-started", ()).ok();
-        self.emit_progress("init", 0, 4, "Initializing...");
+        if plan.is_preview() {
+            println!("[Sync] Planning sync...");
+        } else {
+            println!("[Sync] Starting sync...");
 
-        // Create Drive client
-        let drive = DriveClient::new(access_token.to_string());
+            // Emit sync started event
+            self.app.emit("sync-started", ()).ok();
+        }
+        self.emit_progress("init", 0, 4, "Initializing...");
 
         // Load sync metadata
         let mut metadata = SyncMetadata::load(&self.app).unwrap_or_default();
 
-        // Ensure folder structure exists on Drive
+        // A preview reads through a store that creates no folders and refuses writes
+        let read_only;
+        let store: &dyn RemoteStore = if plan.is_preview() {
+            read_only = ReadOnlyStore::new(store);
+            &read_only
+        } else {
+            store
+        };
+
+        // Ensure folder structure exists on the sync target
         println!("[Sync] Ensuring folder structure...");
-        self.emit_progress("init", 1, 4, "Connecting to Google Drive...");
+        self.emit_progress("init", 1, 4, "Connecting...");
         let (app_folder_id, entries_folder_id, images_folder_id) =
-            drive.ensure_folder_structure().await?;
+            store.ensure_folder_structure().await?;
         println!("[Sync] Folder structure ready");
 
+        // Never mix keys: a device with a different passphrase must not overwrite data
+        encryption::verify_remote(store, &app_folder_id, self.sync_key.as_ref(), self.settings.e2e_encryption, !plan.is_preview()).await?;
+
+        // If the remote folders were recreated (e.g. deleted from the web UI) or the
+        // sync target changed, the recorded file states no longer describe the remote
+        // side. Forget them so that nothing is mistaken for a deletion.
+        let folders_changed = [
+            (&metadata.drive_folder_id, &app_folder_id),
+            (&metadata.entries_folder_id, &entries_folder_id),
+            (&metadata.images_folder_id, &images_folder_id),
+        ]
+        .iter()
+        .any(|(old, new)| old.as_ref().map(|id| id != *new).unwrap_or(false));
+        if folders_changed {
+            println!("[Sync] Remote folders changed, resetting file sync state");
+            metadata.files.clear();
+        }
+
         metadata.drive_folder_id = Some(app_folder_id.clone());
         metadata.entries_folder_id = Some(entries_folder_id.clone());
         metadata.images_folder_id = Some(images_folder_id.clone());
 
+        let dirty = dirty::snapshot(&self.app);
+        let scope = if folders_changed {
+            None
+        } else {
+            self.change_scope(store, &metadata, &dirty).await
+        };
+        let next_token = match &scope {
+            Some(scope) => Some(scope.next_token.clone()),
+            // Taken before listing, so changes made while this sync runs are seen next time
+            None => store.start_change_token().await.unwrap_or_else(|e| {
+                println!("[Sync] Failed to get change token: {}", e);
+                None
+            }),
+        };
+
         // Sync diary entries
         println!("[Sync] Syncing entries...");
-        match self.sync_entries(&drive, &mut metadata, &entries_folder_id).await {
-            Ok((mut uploaded, mut downloaded, mut errors)) => {
-                report.uploaded.append(&mut uploaded);
-                report.downloaded.append(&mut downloaded);
-                report.errors.append(&mut errors);
-            }
+        match self.sync_entries(store, &mut metadata, &entries_folder_id, scope.as_ref(), plan).await {
+            Ok(entries_report) => report.merge(entries_report),
             Err(e) => {
                 report.errors.push(format!("Entry sync failed: {}", e));
             }
         }
 
-        // Sync tags.json
-        println!("[Sync] Syncing tags...");
-        self.emit_progress("tags", 0, 1, "Syncing tags...");
-        match self.sync_tags(&drive, &mut metadata, &app_folder_id).await {
-            Ok(synced) => {
-                if !synced.is_empty() {
-                    if synced.starts_with("uploaded") {
-                        report.uploaded.push("tags.json".to_string());
-                    } else if synced.starts_with("downloaded") {
-                        report.downloaded.push("tags.json".to_string());
+        // Sync tags.json. Out of scope files are forgotten rather than compared,
+        // so that a copy missing on this device is never taken for a deletion.
+        if self.settings.syncs_tags() {
+            println!("[Sync] Syncing tags...");
+            self.emit_progress("tags", 0, 1, "Syncing tags...");
+            if !plan.is_preview() {
+                self.expire_tag_tombstones(store, &app_folder_id).await;
+            }
+            match self.sync_tags(store, &mut metadata, &app_folder_id, plan).await {
+                Ok(synced) => {
+                    if !synced.is_empty() {
+                        if synced.starts_with("uploaded") {
+                            report.uploaded.push("tags.json".to_string());
+                        } else if synced.starts_with("downloaded") {
+                            report.downloaded.push("tags.json".to_string());
+                        } else if synced.starts_with("deleted_local") {
+                            report.deleted_local.push("tags.json".to_string());
+                        } else if synced.starts_with("deleted_remote") {
+                            report.deleted_remote.push("tags.json".to_string());
+                        } else if synced.starts_with("merged") {
+                            report.conflicts_resolved.push("tags.json".to_string());
+                        }
                     }
                 }
+                Err(e) => {
+                    report.errors.push(format!("Tags sync failed: {}", e));
+                }
             }
-            Err(e) => {
-                report.errors.push(format!("Tags sync failed: {}", e));
-            }
+        } else {
+            metadata.remove_file_metadata(TAGS_FILE);
         }
 
         // Sync images
-        println!("[Sync] Syncing images...");
-        match self.sync_images(&drive, &mut metadata, &images_folder_id).await {
-            Ok((mut uploaded, mut downloaded, mut errors)) => {
-                report.uploaded.append(&mut uploaded);
-                report.downloaded.append(&mut downloaded);
-                report.errors.append(&mut errors);
+        if self.settings.syncs_images() {
+            println!("[Sync] Syncing images...");
+            match self.sync_images(store, &mut metadata, &images_folder_id, scope.as_ref(), plan).await {
+                Ok(images_report) => report.merge(images_report),
+                Err(e) => {
+                    report.errors.push(format!("Images sync failed: {}", e));
+                }
             }
-            Err(e) => {
-                report.errors.push(format!("Images sync failed: {}", e));
+        } else {
+            metadata.files.retain(|key, _| !key.starts_with("images/"));
+        }
+
+        if plan.is_preview() {
+            return Ok(report);
+        }
+        if let Some(error) = plan.changed_error() {
+            report.errors.push(error);
+        }
+
+        if report.errors.is_empty() && !plan.skipped_any() {
+            metadata.changes_token = next_token;
+            if scope.is_none() {
+                metadata.last_full_sync = Some(chrono::Utc::now().to_rfc3339());
+                // Every file was compared, so every plaintext copy was re-uploaded
+                metadata.reencrypted = self.sync_key.is_some();
             }
+            if let Err(e) = dirty::clear(&self.app, &dirty) {
+                println!("[Sync] Failed to clear local changes: {}", e);
+            }
+        } else {
+            // Compare everything next time rather than lose track of the files that failed or were skipped
+            metadata.changes_token = None;
         }
 
+        self.register_device(store, &app_folder_id).await;
+
         // Update sync time and save metadata
         metadata.update_last_sync_time();
         metadata.save(&self.app).map_err(|e| e.to_string())?;
 
+        // Index downloaded entries and drop deleted ones
+        let entries_changed = !report.downloaded.is_empty()
+            || !report.deleted_local.is_empty()
+            || !report.conflicts_resolved.is_empty()
+            || !report.renamed.is_empty();
+        if entries_changed {
+            if let Err(e) = crate::search::refresh(&self.app, &self.diary_dir) {
+                println!("[Sync] Failed to update search index: {}", e);
+            }
+        }
+
         report.duration_ms = start_time.elapsed().as_millis() as u64;
 
-        println!("[Sync] Sync completed in {}ms - uploaded: {}, downloaded: {}, errors: {}",
+        println!("[Sync] Sync completed in {}ms - uploaded: {}, downloaded: {}, deleted: {}, errors: {}",
             report.duration_ms,
             report.uploaded.len(),
             report.downloaded.len(),
+            report.deleted_local.len() + report.deleted_remote.len(),
             report.errors.len()
         );
 
@@ -136,173 +503,215 @@ impl SyncEngine {
         Ok(report)
     }
 
+    /// Files changed on either side since the last complete sync, or None when every file
+    /// has to be compared: no change feed, no previous sync, or the daily full sync is due
+    async fn change_scope(
+        &self,
+        store: &dyn RemoteStore,
+        metadata: &SyncMetadata,
+        dirty: &HashMap<String, i64>,
+    ) -> Option<ChangeScope> {
+        let token = metadata.changes_token.as_ref()?;
+        let full_sync_due = metadata.last_full_sync.as_ref()
+            .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| chrono::Utc::now().signed_duration_since(time) > chrono::Duration::hours(FULL_SYNC_INTERVAL_HOURS))
+            .unwrap_or(true);
+        if full_sync_due {
+            return None;
+        }
+
+        match store.list_changes(token).await {
+            Ok(changes) => {
+                let scope = ChangeScope::from_changes(changes, metadata, dirty);
+                println!("[Sync] {} files changed since the last sync", scope.keys.len());
+                Some(scope)
+            }
+            Err(e) => {
+                println!("[Sync] Failed to list remote changes, comparing all files: {}", e);
+                None
+            }
+        }
+    }
+
     async fn sync_entries(
         &self,
-        drive: &DriveClient,
+        store: &dyn RemoteStore,
         metadata: &mut SyncMetadata,
         folder_id: &str,
-    ) -> Result<(Vec<String>, Vec<String>, Vec<String>), String> {
-        let mut uploaded = Vec::new();
-        let mut downloaded = Vec::new();
-        let mut errors = Vec::new();
+        scope: Option<&ChangeScope>,
+        plan: &mut SyncPlan,
+    ) -> Result<SyncReport, String> {
+        let mut report = SyncReport::default();
 
         // Get local entries
         self.emit_progress("entries", 0, 1, "Scanning local entries...");
-        let local_entries = self.get_local_entries().await?;
+        let mut local_entries = self.get_local_entries().await?;
 
         // Get remote entries
         self.emit_progress("entries", 0, 1, "Fetching remote entries...");
-        let remote_files = drive.list_files(folder_id).await?;
-        let remote_entries: HashMap<String, _> = remote_files
+        let remote_files = match scope {
+            Some(scope) => {
+                local_entries.retain(|name, _| scope.contains("entries", name));
+                scope.remote_files(store, metadata, "entries", folder_id).await?
+            }
+            None => store.list_files(folder_id).await?,
+        };
+        let mut remote_entries: HashMap<String, _> = remote_files
             .into_iter()
-            .filter(|f| f.name.ends_with(".txt"))
+            .filter(|f| diary::is_entry_file(Path::new(&f.name)))
             .map(|f| (f.name.clone(), f))
             .collect();
 
-        // Determine sync actions using last-write-wins
-        let mut actions = Vec::new();
+        self.follow_format_changes(store, metadata, &mut local_entries, &mut remote_entries, &mut report, plan).await;
+
+        // Determine sync actions, merging entries changed on both sides
+        let mut planned = Vec::new();
+        let encrypting = self.sync_key.is_some();
 
-        // Check local files
         for (name, local_path) in &local_entries {
-            let local_modified = get_file_modified_time(local_path).await?;
-            let local_hash = calculate_file_hash(local_path).map_err(|e| e.to_string())?;
-            let local_size = fs::metadata(local_path).await.map(|m| m.len()).unwrap_or(0);
-            // Consider file "empty" if it's very small (just date line or whitespace)
-            let local_is_empty = local_size < 20;
-
-            if let Some(remote) = remote_entries.get(name) {
-                // File exists both locally and remotely
-                let remote_modified = remote.modified_time.as_ref()
-                    .map(|t| t.clone())
-                    .unwrap_or_default();
-                let remote_size = remote.size.as_ref()
-                    .and_then(|s| s.parse::<u64>().ok())
-                    .unwrap_or(0);
-                let remote_is_empty = remote_size < 20;
-
-                // Special handling for empty vs non-empty files
-                if local_is_empty && !remote_is_empty {
-                    // Local is empty, remote has content - always download
-                    actions.push(SyncAction::Download {
-                        remote_id: remote.id.clone(),
-                        remote_name: name.clone(),
-                        local_path: local_path.clone(),
-                    });
-                    continue;
-                } else if !local_is_empty && remote_is_empty {
-                    // Local has content, remote is empty - always upload
-                    actions.push(SyncAction::Upload {
-                        local_path: local_path.clone(),
-                        remote_name: name.clone(),
-                        remote_id: Some(remote.id.clone()),
-                    });
-                    continue;
-                }
+            let local_content = self.vault.read(local_path)?;
+            let local_hash = calculate_content_hash(&local_content);
+            let sides = EntrySides {
+                name,
+                local: Some((local_path, &local_hash, local_content.len() as u64)),
+                remote: remote_entries.get(name),
+                synced: metadata.get_file_metadata(&format!("entries/{}", name)),
+            };
+            planned.extend(plan_entry(&sides, &self.diary_dir, encrypting, metadata.reencrypted));
+        }
 
-                // Both have content (or both empty) - use normal sync logic
-                // Check if we've synced this before
-                let meta_key = format!("entries/{}", name);
-                if let Some(file_meta) = metadata.get_file_metadata(&meta_key) {
-                    // Compare with last synced version
-                    let local_changed = file_meta.synced_hash != local_hash;
-                    let remote_changed = file_meta.remote_modified.as_ref() != Some(&remote_modified);
-
-                    if local_changed && remote_changed {
-                        // Conflict! Use last-write-wins
-                        if local_modified > remote_modified {
-                            actions.push(SyncAction::Upload {
-                                local_path: local_path.clone(),
-                                remote_name: name.clone(),
-                                remote_id: Some(remote.id.clone()),
-                            });
-                        } else {
-                            actions.push(SyncAction::Download {
-                                remote_id: remote.id.clone(),
-                                remote_name: name.clone(),
-                                local_path: local_path.clone(),
-                            });
-                        }
-                    } else if local_changed {
-                        actions.push(SyncAction::Upload {
-                            local_path: local_path.clone(),
-                            remote_name: name.clone(),
-                            remote_id: Some(remote.id.clone()),
-                        });
-                    } else if remote_changed {
-                        actions.push(SyncAction::Download {
-                            remote_id: remote.id.clone(),
-                            remote_name: name.clone(),
-                            local_path: local_path.clone(),
-                        });
-                    }
+        // Remote-only files need downloading, or were deleted locally
+        for (name, remote) in &remote_entries {
+            if local_entries.contains_key(name) {
+                continue;
+            }
+            let meta_key = format!("entries/{}", name);
+            if !self.settings.in_download_window(name) {
+                // Left on the sync target, whether or not this device ever had it
+                metadata.remove_file_metadata(&meta_key);
+                continue;
+            }
+            let sides = EntrySides {
+                name,
+                local: None,
+                remote: Some(remote),
+                synced: metadata.get_file_metadata(&meta_key),
+            };
+            planned.extend(plan_entry(&sides, &self.diary_dir, encrypting, metadata.reencrypted));
+        }
+
+        // Forget entries that are gone on both sides
+        let stale_keys: Vec<String> = metadata.files.keys()
+            .filter(|key| scope.map(|scope| scope.keys.contains(*key)).unwrap_or(true))
+            .filter_map(|key| {
+                let name = key.strip_prefix("entries/")?;
+                if local_entries.contains_key(name) || remote_entries.contains_key(name) {
+                    None
                 } else {
-                    // First time syncing this file - compare timestamps
-                    if local_modified > remote_modified {
-                        actions.push(SyncAction::Upload {
-                            local_path: local_path.clone(),
-                            remote_name: name.clone(),
-                            remote_id: Some(remote.id.clone()),
-                        });
-                    } else {
-                        actions.push(SyncAction::Download {
-                            remote_id: remote.id.clone(),
-                            remote_name: name.clone(),
-                            local_path: local_path.clone(),
-                        });
-                    }
+                    Some(key.clone())
                 }
-            } else {
-                // Only exists locally - upload only if has content
-                if !local_is_empty {
-                    actions.push(SyncAction::Upload {
-                        local_path: local_path.clone(),
-                        remote_name: name.clone(),
-                        remote_id: None,
-                    });
-                }
-            }
+            })
+            .collect();
+        for key in stale_keys {
+            metadata.remove_file_metadata(&key);
         }
 
-        // Check for remote-only files (need to download)
-        for (name, remote) in &remote_entries {
-            if !local_entries.contains_key(name) {
-                let local_path = self.diary_dir.join(name);
-                actions.push(SyncAction::Download {
-                    remote_id: remote.id.clone(),
-                    remote_name: name.clone(),
-                    local_path,
-                });
+        let actions: Vec<SyncAction> = planned.into_iter()
+            .filter(|(action, reason)| plan.admit(&format!("entries/{}", action.file_name()), action.kind(), reason))
+            .map(|(action, _)| action)
+            .collect();
+
+        // Execute sync actions, several at a time
+        let total_actions = actions.len();
+        let progress = StageProgress::new(self, "entries", total_actions);
+        let shared_metadata = Mutex::new(std::mem::take(metadata));
+        let results: Vec<SyncReport> = stream::iter(actions)
+            .map(|action| self.execute_entry_action(store, folder_id, action, &shared_metadata, &progress))
+            .buffer_unordered(self.concurrency())
+            .collect()
+            .await;
+        *metadata = shared_metadata.into_inner().unwrap();
+        for result in results {
+            report.merge(result);
+        }
+        for name in &report.downloaded {
+            if let Some(device_name) = remote_entries.get(name).and_then(|r| r.device_name.clone()) {
+                report.origins.insert(name.clone(), device_name);
             }
         }
 
-        // Execute sync actions
-        let total_actions = actions.len() as u32;
-        for (i, action) in actions.into_iter().enumerate() {
-            let current = (i + 1) as u32;
-            match action {
-                SyncAction::Upload { local_path, remote_name, remote_id } => {
-                    self.emit_progress("entries", current, total_actions, &format!("Uploading {}...", remote_name));
-                    match self.upload_entry(drive, &local_path, &remote_name, folder_id, remote_id.as_deref(), metadata).await {
-                        Ok(_) => uploaded.push(remote_name),
-                        Err(e) => errors.push(format!("Upload {} failed: {}", remote_name, e)),
+        if total_actions == 0 {
+            self.emit_progress("entries", 1, 1, "Entries up to date");
+        }
+
+        let pruned = shadow::shadow_dir_path(&self.app)
+            .and_then(|dir| prune_merge_bases(&dir, metadata, plan.is_preview()));
+        if let Err(e) = pruned {
+            println!("[Sync] Failed to prune synced versions: {}", e);
+        }
+
+        Ok(report)
+    }
+
+    async fn execute_entry_action(
+        &self,
+        store: &dyn RemoteStore,
+        folder_id: &str,
+        action: SyncAction,
+        metadata: &Mutex<SyncMetadata>,
+        progress: &StageProgress<'_>,
+    ) -> SyncReport {
+        let mut report = SyncReport::default();
+        let message = action.progress_message();
+        progress.start(&message);
+
+        match action {
+            SyncAction::Upload { local_path, remote_name, remote_id } => {
+                match self.upload_entry(store, &local_path, &remote_name, folder_id, remote_id.as_deref(), metadata).await {
+                    Ok(_) => report.uploaded.push(remote_name),
+                    Err(e) => report.errors.push(format!("Upload {} failed: {}", remote_name, e)),
+                }
+            }
+            SyncAction::Download { remote_id, remote_name, local_path } => {
+                match self.download_entry(store, &remote_id, &remote_name, &local_path, metadata).await {
+                    Ok(_) => report.downloaded.push(remote_name),
+                    Err(e) => report.errors.push(format!("Download {} failed: {}", remote_name, e)),
+                }
+            }
+            SyncAction::DeleteLocal { local_path } => {
+                let name = file_name_of(&local_path);
+                // Deleted remotely, but still restorable from history
+                if let Err(e) = history::preserve(&self.vault, &self.diary_dir, &name) {
+                    println!("[Sync] Failed to keep revision of {}: {}", name, e);
+                }
+                match self.delete_local_file(&local_path).await {
+                    Ok(_) => {
+                        metadata.lock().unwrap().remove_file_metadata(&format!("entries/{}", name));
+                        report.deleted_local.push(name);
                     }
+                    Err(e) => report.errors.push(format!("Delete local {} failed: {}", name, e)),
                 }
-                SyncAction::Download { remote_id, remote_name, local_path } => {
-                    self.emit_progress("entries", current, total_actions, &format!("Downloading {}...", remote_name));
-                    match self.download_entry(drive, &remote_id, &remote_name, &local_path, metadata).await {
-                        Ok(_) => downloaded.push(remote_name),
-                        Err(e) => errors.push(format!("Download {} failed: {}", remote_name, e)),
+            }
+            SyncAction::DeleteRemote { remote_id, remote_name } => {
+                match self.delete_remote_file(store, &remote_id).await {
+                    Ok(_) => {
+                        metadata.lock().unwrap().remove_file_metadata(&format!("entries/{}", remote_name));
+                        report.deleted_remote.push(remote_name);
                     }
+                    Err(e) => report.errors.push(format!("Delete remote {} failed: {}", remote_name, e)),
+                }
+            }
+            SyncAction::Merge { local_path, remote, base_hash } => {
+                match self.merge_entry(store, folder_id, &local_path, &remote, base_hash.as_deref(), metadata).await {
+                    Ok(Some(resolved)) => report.conflicts_resolved.push(resolved),
+                    Ok(None) => {}
+                    Err(e) => report.errors.push(format!("Merge {} failed: {}", remote.name, e)),
                 }
-                _ => {}
             }
         }
 
-        if total_actions == 0 {
-            self.emit_progress("entries", 1, 1, "Entries up to date");
-        }
-
-        Ok((uploaded, downloaded, errors))
+        progress.finish(&message);
+        report
     }
 
     async fn get_local_entries(&self) -> Result<HashMap<String, PathBuf>, String> {
@@ -314,7 +723,7 @@ impl SyncEngine {
         let mut reader = read_dir;
         while let Ok(Some(entry)) = reader.next_entry().await {
             let path = entry.path();
-            if path.is_file() && path.extension().map(|e| e == "txt").unwrap_or(false) {
+            if path.is_file() && diary::is_entry_file(&path) {
                 if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
                     entries.insert(name.to_string(), path);
                 }
@@ -324,49 +733,155 @@ impl SyncEngine {
         Ok(entries)
     }
 
+    /// An entry converted to another format (e.g. `.txt` to `.md`) on one side shows up under
+    /// different names locally and remotely. Rename the other side to match, rather than
+    /// deleting one file and creating the other. Dates with several formats on either side
+    /// are left alone, and each of those files syncs on its own.
+    async fn follow_format_changes(
+        &self,
+        store: &dyn RemoteStore,
+        metadata: &mut SyncMetadata,
+        local_entries: &mut HashMap<String, PathBuf>,
+        remote_entries: &mut HashMap<String, RemoteFile>,
+        report: &mut SyncReport,
+        plan: &mut SyncPlan,
+    ) {
+        fn by_date<'a>(names: impl Iterator<Item = &'a String>) -> HashMap<String, Vec<String>> {
+            let mut dates: HashMap<String, Vec<String>> = HashMap::new();
+            for name in names {
+                let date = Path::new(name).file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                dates.entry(date).or_default().push(name.clone());
+            }
+            dates
+        }
+
+        let local_dates = by_date(local_entries.keys());
+        let remote_dates = by_date(remote_entries.keys());
+        let renames: Vec<(String, String)> = local_dates.iter()
+            .filter_map(|(date, local_names)| match (local_names.as_slice(), remote_dates.get(date)?.as_slice()) {
+                ([local_name], [remote_name]) if local_name != remote_name => {
+                    Some((local_name.clone(), remote_name.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (local_name, remote_name) in renames {
+            let local_key = format!("entries/{}", local_name);
+            let remote_key = format!("entries/{}", remote_name);
+            let (Some(local_path), Some(remote)) = (local_entries.get(&local_name).cloned(), remote_entries.get(&remote_name).cloned()) else {
+                continue;
+            };
+
+            // Converted here: the remote file is still the one last synced under the old name
+            let converted_locally = metadata.get_file_metadata(&local_key).is_none()
+                && metadata.get_file_metadata(&remote_key)
+                    .map(|file_meta| file_meta.remote_modified == remote.modified_time)
+                    .unwrap_or(false);
+            // Converted on another device: the local file is unchanged since it was last synced
+            let converted_remotely = metadata.get_file_metadata(&remote_key).is_none()
+                && metadata.get_file_metadata(&local_key)
+                    .map(|file_meta| self.local_hash(&local_path).ok().as_ref() == Some(&file_meta.synced_hash))
+                    .unwrap_or(false);
+
+            let renamed_key = if converted_locally { &remote_key } else { &local_key };
+            if (converted_locally || converted_remotely) && !plan.admit(renamed_key, plan::KIND_RENAME, plan::REASON_FORMAT_CHANGED) {
+                if plan.is_preview() {
+                    // Plan the rest as if renamed, like the real sync would
+                    if converted_locally {
+                        metadata.rename_file_metadata(&remote_key, &local_key);
+                        remote_entries.remove(&remote_name);
+                        remote_entries.insert(local_name.clone(), RemoteFile { name: local_name.clone(), ..remote });
+                    } else {
+                        metadata.rename_file_metadata(&local_key, &remote_key);
+                        local_entries.remove(&local_name);
+                        local_entries.insert(remote_name.clone(), local_path);
+                    }
+                } else {
+                    // Skipped: leave both files as they are rather than sync them under different names
+                    local_entries.remove(&local_name);
+                    remote_entries.remove(&remote_name);
+                }
+                continue;
+            }
+
+            if converted_locally {
+                match store.rename_file(&remote.id, &local_name).await {
+                    Ok(renamed) => {
+                        metadata.rename_file_metadata(&remote_key, &local_key);
+                        if let Some(file_meta) = metadata.files.get_mut(&local_key) {
+                            file_meta.remote_id = Some(renamed.id.clone());
+                            file_meta.remote_modified = renamed.modified_time.clone();
+                        }
+                        remote_entries.remove(&remote_name);
+                        remote_entries.insert(local_name.clone(), renamed);
+                        report.renamed.push(format!("{} -> {}", remote_name, local_name));
+                    }
+                    Err(e) => report.errors.push(format!("Rename {} failed: {}", remote_name, e)),
+                }
+            } else if converted_remotely {
+                let new_path = self.diary_dir.join(&remote_name);
+                match fs::rename(&local_path, &new_path).await {
+                    Ok(()) => {
+                        if let Err(e) = history::rename(&self.diary_dir, &local_name, &remote_name) {
+                            println!("[Sync] Failed to move history of {}: {}", local_name, e);
+                        }
+                        metadata.rename_file_metadata(&local_key, &remote_key);
+                        local_entries.remove(&local_name);
+                        local_entries.insert(remote_name.clone(), new_path);
+                        report.renamed.push(format!("{} -> {}", local_name, remote_name));
+                    }
+                    Err(e) => report.errors.push(format!("Rename {} failed: {}", local_name, e)),
+                }
+            }
+        }
+    }
+
     async fn upload_entry(
         &self,
-        drive: &DriveClient,
-        local_path: &PathBuf,
+        store: &dyn RemoteStore,
+        local_path: &Path,
         remote_name: &str,
         folder_id: &str,
         existing_id: Option<&str>,
-        metadata: &mut SyncMetadata,
+        metadata: &Mutex<SyncMetadata>,
     ) -> Result<(), String> {
-        let content = fs::read(local_path).await
-            .map_err(|e| format!("Failed to read file: {}", e))?;
+        let content = self.vault.read(local_path)?;
 
-        let result = drive.upload_content(
-            &content,
+        let result = store.upload_content(
+            &self.seal_upload(&content)?,
             remote_name,
             folder_id,
             "text/plain",
             existing_id,
         ).await?;
 
-        let hash = super::metadata::calculate_content_hash(&content);
+        let hash = calculate_content_hash(&content);
         let modified = get_file_modified_time(local_path).await?;
 
-        metadata.update_file_metadata(
+        metadata.lock().unwrap().update_file_metadata(
             &format!("entries/{}", remote_name),
             &modified,
             Some(result.id),
             result.modified_time,
             &hash,
         );
+        self.store_shadow(&hash, &content);
 
         Ok(())
     }
 
     async fn download_entry(
         &self,
-        drive: &DriveClient,
+        store: &dyn RemoteStore,
         remote_id: &str,
         remote_name: &str,
-        local_path: &PathBuf,
-        metadata: &mut SyncMetadata,
+        local_path: &Path,
+        metadata: &Mutex<SyncMetadata>,
     ) -> Result<(), String> {
-        let content = drive.download_file(remote_id).await?;
+        let content = self.open_download(store.download_file(remote_id).await?)?;
 
         // Create parent directory if needed
         if let Some(parent) = local_path.parent() {
@@ -374,33 +889,375 @@ impl SyncEngine {
                 .map_err(|e| format!("Failed to create directory: {}", e))?;
         }
 
-        fs::write(local_path, &content).await
-            .map_err(|e| format!("Failed to write file: {}", e))?;
-
-        let hash = super::metadata::calculate_content_hash(&content);
+        self.keep_revision(remote_name, &content);
+        self.vault.write(local_path, &content)?;
+
+        let hash = calculate_content_hash(&content);
+        let modified = get_file_modified_time(local_path).await?;
+
+        let remote_meta = store.get_file_metadata(remote_id).await?;
+
+        metadata.lock().unwrap().update_file_metadata(
+            &format!("entries/{}", remote_name),
+            &modified,
+            Some(remote_id.to_string()),
+            remote_meta.modified_time,
+            &hash,
+        );
+        self.store_shadow(&hash, &content);
+
+        Ok(())
+    }
+
+    /// Reconcile an entry changed on both sides. Edits are merged line by line against the
+    /// last synced version; if that fails one side wins as decided by `pick_winner` and the
+    /// other is kept as a conflict copy. Returns the merged entry or conflict copy name, or None if both sides
+    /// turned out to be identical.
+    async fn merge_entry(
+        &self,
+        store: &dyn RemoteStore,
+        folder_id: &str,
+        local_path: &Path,
+        remote_file: &RemoteFile,
+        base_hash: Option<&str>,
+        metadata: &Mutex<SyncMetadata>,
+    ) -> Result<Option<String>, String> {
+        let remote_id = remote_file.id.as_str();
+        let remote_name = remote_file.name.as_str();
+        let local_path = local_path.to_path_buf();
+
+        let local = self.vault.read(&local_path)?;
+        let remote = self.open_download(store.download_file(remote_id).await?)?;
+
+        let (content, resolved) = if local == remote {
+            (local.clone(), None)
+        } else {
+            let base = base_hash.and_then(|hash| shadow::load(&self.app, &self.vault, hash));
+            let merged = match (base, std::str::from_utf8(&local), std::str::from_utf8(&remote)) {
+                (Some(base), Ok(local_text), Ok(remote_text)) => match String::from_utf8(base) {
+                    Ok(base_text) => merge3(&base_text, local_text, remote_text),
+                    Err(_) => MergeResult::Conflict,
+                },
+                _ => MergeResult::Conflict,
+            };
+
+            match merged {
+                MergeResult::Clean(text) => {
+                    println!("[Sync] Merged concurrent edits of {}", remote_name);
+                    (text.into_bytes(), Some(remote_name.to_string()))
+                }
+                MergeResult::Conflict => {
+                    let winner = self.pick_winner(&local, &remote, base_hash, remote_file);
+                    let (winner, loser, loser_origin) = match winner {
+                        Side::Local => (local.clone(), remote.clone(), remote_label(remote_file)),
+                        Side::Remote => (remote.clone(), local.clone(), self.local_label()),
+                    };
+                    let copy = self.write_conflict_copy(remote_name, &loser_origin, &loser).await?;
+                    println!("[Sync] Conflict in {}, saved {} version as {}", remote_name, loser_origin, copy);
+                    (winner, Some(copy))
+                }
+            }
+        };
+
+        if content != local {
+            self.keep_revision(remote_name, &content);
+            self.vault.write(&local_path, &content)?;
+        }
+        let remote_modified = if content != remote {
+            let result = store.upload_content(
+                &self.seal_upload(&content)?,
+                remote_name,
+                folder_id,
+                "text/plain",
+                Some(remote_id),
+            ).await?;
+            result.modified_time
+        } else {
+            remote_file.modified_time.clone()
+        };
+
+        let hash = calculate_content_hash(&content);
+        let modified = get_file_modified_time(&local_path).await?;
+
+        metadata.lock().unwrap().update_file_metadata(
+            &format!("entries/{}", remote_name),
+            &modified,
+            Some(remote_id.to_string()),
+            remote_modified,
+            &hash,
+        );
+        self.store_shadow(&hash, &content);
+
+        Ok(resolved)
+    }
+
+    /// Save the losing side of a conflict under the conflicts folder, returning its relative path.
+    /// `origin` names the device the version came from.
+    async fn write_conflict_copy(&self, name: &str, origin: &str, content: &[u8]) -> Result<String, String> {
+        let dir = self.diary_dir.join(CONFLICTS_DIR);
+        fs::create_dir_all(&dir).await
+            .map_err(|e| format!("Failed to create conflicts directory: {}", e))?;
+
+        let stem = Path::new(name).file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| name.to_string());
+        let ext = Path::new(name).extension()
+            .map(|e| e.to_string_lossy().to_string())
+            .unwrap_or_else(|| "txt".to_string());
+        let copy_name = format!("{}.{}-{}.{}", stem, origin, chrono::Local::now().format("%Y%m%d-%H%M%S"), ext);
+
+        self.vault.write(&dir.join(&copy_name), content)?;
+        Ok(format!("{}/{}", CONFLICTS_DIR, copy_name))
+    }
+
+    /// Snapshot an entry about to be replaced by sync; failures only cost the undo
+    fn keep_revision(&self, name: &str, content: &[u8]) {
+        if let Err(e) = history::record(&self.vault, &self.diary_dir, name, content, history::SOURCE_SYNC) {
+            println!("[Sync] Failed to keep revision of {}: {}", name, e);
+        }
+    }
+
+    /// Keep the synced content as the base for future merges; failures only cost a merge later
+    fn store_shadow(&self, hash: &str, content: &[u8]) {
+        if let Err(e) = shadow::store(&self.app, &self.vault, hash, content) {
+            println!("[Sync] Failed to keep synced version: {}", e);
+        }
+    }
+
+    /// Hash of a local file's plaintext content, as recorded in the sync metadata
+    fn local_hash(&self, path: &Path) -> Result<String, String> {
+        if self.vault.is_enabled() {
+            self.vault.hash_file(path)
+        } else {
+            calculate_file_hash(&path.to_path_buf()).map_err(|e| e.to_string())
+        }
+    }
+
+    /// Upload an image, returning the remote file and the hash of the uploaded content
+    async fn upload_image(
+        &self,
+        store: &dyn RemoteStore,
+        local_path: &Path,
+        name: &str,
+        folder_id: &str,
+        existing_id: Option<&str>,
+        metadata: &Mutex<SyncMetadata>,
+    ) -> Result<(RemoteFile, String), String> {
+        let size = fs::metadata(local_path).await
+            .map(|m| m.len())
+            .map_err(|e| format!("Failed to read image: {}", e))?;
+        if size >= RESUMABLE_UPLOAD_THRESHOLD {
+            if let Some(uploaded) = self.upload_resumable(store, local_path, name, folder_id, existing_id, metadata).await? {
+                return Ok(uploaded);
+            }
+        }
+
+        if !self.vault.is_enabled() && self.sync_key.is_none() {
+            let result = store.upload_file(local_path, name, folder_id, existing_id).await?;
+            return Ok((result, self.local_hash(local_path)?));
+        }
+
+        // Encrypted images are decrypted (and re-encrypted for the sync target) in memory
+        let content = self.vault.read(local_path)?;
+        let mime_type = mime_guess::from_path(local_path)
+            .first_or_octet_stream()
+            .to_string();
+        let result = store.upload_content(&self.seal_upload(&content)?, name, folder_id, &mime_type, existing_id).await?;
+        Ok((result, calculate_content_hash(&content)))
+    }
+
+    /// Upload a large image in chunks, continuing the session an interrupted sync left in
+    /// `metadata.uploads`. Returns None if the backend takes whole files only.
+    async fn upload_resumable(
+        &self,
+        store: &dyn RemoteStore,
+        local_path: &Path,
+        name: &str,
+        folder_id: &str,
+        existing_id: Option<&str>,
+        metadata: &Mutex<SyncMetadata>,
+    ) -> Result<Option<(RemoteFile, String)>, String> {
+        let key = format!("images/{}", name);
+        let mime_type = mime_guess::from_path(local_path)
+            .first_or_octet_stream()
+            .to_string();
+
+        // Images are read a chunk at a time: hashing also authenticates encrypted ones,
+        // which are then decrypted per chunk, or sealed for the sync target into a staged file
+        let hash = self.local_hash(local_path)?;
+        let staged_path = self.staged_upload_path(&key)?;
+
+        // A new session for the same image and target is needed only once per attempt
+        for _ in 0..2 {
+            let resumable = metadata.lock().unwrap().uploads.get(&key)
+                .filter(|pending| pending.content_hash == hash && pending.remote_id.as_deref() == existing_id)
+                .filter(|pending| {
+                    chrono::DateTime::parse_from_rfc3339(&pending.started)
+                        .map(|started| chrono::Utc::now().signed_duration_since(started) < chrono::Duration::days(UPLOAD_SESSION_DAYS))
+                        .unwrap_or(false)
+                })
+                .filter(|_| self.sync_key.is_none() || staged_path.exists())
+                .map(|pending| pending.session_uri.clone());
+
+            let session_uri = match resumable {
+                Some(session_uri) => {
+                    println!("[Sync] Resuming upload of {}", name);
+                    session_uri
+                }
+                None => {
+                    self.forget_upload(&mut metadata.lock().unwrap(), &key);
+                    let mut size = self.vault.plaintext_len(local_path)?;
+                    if self.sync_key.is_some() {
+                        size += vault::SEAL_OVERHEAD as u64;
+                    }
+
+                    let Some(session_uri) = store.start_resumable_upload(name, folder_id, &mime_type, size, existing_id).await? else {
+                        return Ok(None);
+                    };
+                    if let Some(sync_key) = &self.sync_key {
+                        let staged_hash = self.vault.reseal_file(local_path, &sync_key.key, &staged_path)
+                            .map_err(|e| format!("Failed to stage upload: {}", e))?;
+                        if staged_hash != hash {
+                            let _ = std::fs::remove_file(&staged_path);
+                            return Err(format!("{} changed during the upload, retrying next sync", name));
+                        }
+                    }
+                    let mut metadata = metadata.lock().unwrap();
+                    metadata.uploads.insert(key.clone(), PendingUpload {
+                        session_uri: session_uri.clone(),
+                        content_hash: hash.clone(),
+                        remote_id: existing_id.map(|id| id.to_string()),
+                        started: chrono::Utc::now().to_rfc3339(),
+                    });
+                    // Saved right away so that a sync killed mid-upload can resume it
+                    metadata.save(&self.app).map_err(|e| e.to_string())?;
+                    session_uri
+                }
+            };
+
+            let source = match &self.sync_key {
+                Some(_) => UploadSource::File(&staged_path),
+                None => UploadSource::Vault(local_path, &self.vault),
+            };
+            if let Some(uploaded) = store.resume_upload(&session_uri, &source).await? {
+                self.forget_upload(&mut metadata.lock().unwrap(), &key);
+                return Ok(Some((uploaded, hash)));
+            }
+            println!("[Sync] Upload session of {} expired, starting over", name);
+            self.forget_upload(&mut metadata.lock().unwrap(), &key);
+        }
+
+        Err("Upload session expired repeatedly".to_string())
+    }
+
+    /// Sealed bytes of the pending upload under `key` in `SyncMetadata::uploads`. Named
+    /// after the key, so identical images uploaded at the same time don't share a file.
+    fn staged_upload_path(&self, key: &str) -> Result<PathBuf, String> {
+        let dir = config::get_state_dir(&self.app)?.join(UPLOAD_STAGING_DIR);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create upload staging directory: {}", e))?;
+        Ok(dir.join(calculate_content_hash(key.as_bytes())))
+    }
+
+    /// Drop a pending upload and its staged bytes
+    fn forget_upload(&self, metadata: &mut SyncMetadata, key: &str) {
+        metadata.uploads.remove(key);
+        if let Ok(path) = self.staged_upload_path(key) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Whether a local image differs from its last synced version. Only images whose
+    /// modification time moved are hashed.
+    async fn local_image_changed(&self, local_path: &Path, file_meta: &FileMetadata) -> bool {
+        let modified = get_file_modified_time(local_path).await.ok();
+        if modified.as_ref() == Some(&file_meta.local_modified) {
+            return false;
+        }
+        self.local_hash(local_path).ok().as_ref() != Some(&file_meta.synced_hash)
+    }
+
+    /// Reconcile an image changed on both sides, or found on both sides before it was ever
+    /// synced. Images can't be merged, so unless both are identical one side wins as decided
+    /// by `pick_winner` and the other is kept as a conflict copy, whose name is returned.
+    async fn resolve_image(
+        &self,
+        store: &dyn RemoteStore,
+        folder_id: &str,
+        local_path: &Path,
+        remote: &RemoteFile,
+        metadata: &Mutex<SyncMetadata>,
+    ) -> Result<Option<String>, String> {
+        let name = remote.name.as_str();
+        let local = self.vault.read(local_path)?;
+        let remote_content = self.open_download(store.download_file(&remote.id).await?)?;
+
+        let meta_key = format!("images/{}", name);
+        let base_hash = metadata.lock().unwrap()
+            .get_file_metadata(&meta_key)
+            .map(|file_meta| file_meta.synced_hash.clone());
+
+        let (content, synced, copy) = if local == remote_content {
+            (local, remote.clone(), None)
+        } else if self.pick_winner(&local, &remote_content, base_hash.as_deref(), remote) == Side::Local {
+            let copy = self.write_conflict_copy(name, &remote_label(remote), &remote_content).await?;
+            let mime_type = mime_guess::from_path(local_path)
+                .first_or_octet_stream()
+                .to_string();
+            let uploaded = store.upload_content(
+                &self.seal_upload(&local)?,
+                name,
+                folder_id,
+                &mime_type,
+                Some(&remote.id),
+            ).await?;
+            (local, uploaded, Some(copy))
+        } else {
+            let copy = self.write_conflict_copy(name, &self.local_label(), &local).await?;
+            self.vault.write(local_path, &remote_content)?;
+            (remote_content, remote.clone(), Some(copy))
+        };
+        if let Some(copy) = &copy {
+            println!("[Sync] Conflict in image {}, saved the other version as {}", name, copy);
+        }
+
         let modified = get_file_modified_time(local_path).await?;
-
-        let remote_meta = drive.get_file_metadata(remote_id).await?;
-
+        let mut metadata = metadata.lock().unwrap();
         metadata.update_file_metadata(
-            &format!("entries/{}", remote_name),
+            &meta_key,
             &modified,
-            Some(remote_id.to_string()),
-            remote_meta.modified_time,
-            &hash,
+            Some(synced.id),
+            synced.modified_time,
+            &calculate_content_hash(&content),
         );
+        metadata.set_remote_checksum(&meta_key, synced.checksum);
+
+        Ok(copy)
+    }
 
+    /// Delete a local file that was removed on the remote side
+    async fn delete_local_file(&self, local_path: &Path) -> Result<(), String> {
+        if local_path.exists() {
+            fs::remove_file(local_path).await
+                .map_err(|e| format!("Failed to delete file: {}", e))?;
+        }
         Ok(())
     }
 
+    /// Delete a remote file that was removed on the local side
+    async fn delete_remote_file(&self, store: &dyn RemoteStore, remote_id: &str) -> Result<(), String> {
+        store.delete_file(remote_id).await
+    }
+
     async fn sync_tags(
         &self,
-        drive: &DriveClient,
+        store: &dyn RemoteStore,
         metadata: &mut SyncMetadata,
         folder_id: &str,
+        plan: &mut SyncPlan,
     ) -> Result<String, String> {
         let local_path = self.diary_dir.join(TAGS_FILE);
-        let remote_file = drive.find_file(TAGS_FILE, folder_id).await?;
+        let remote_file = store.find_file(TAGS_FILE, folder_id).await?;
 
         let local_exists = local_path.exists();
         let remote_exists = remote_file.is_some();
@@ -410,19 +1267,35 @@ impl SyncEngine {
         }
 
         if local_exists && !remote_exists {
+            // Previously synced and unchanged locally - it was deleted remotely
+            if let Some(file_meta) = metadata.get_file_metadata(TAGS_FILE) {
+                let local_hash = self.local_hash(&local_path)?;
+                if file_meta.synced_hash == local_hash {
+                    if !plan.admit(TAGS_FILE, plan::KIND_DELETE_LOCAL, plan::REASON_DELETED_REMOTELY) {
+                        return Ok(String::new());
+                    }
+                    self.delete_local_file(&local_path).await?;
+                    metadata.remove_file_metadata(TAGS_FILE);
+                    return Ok("deleted_local".to_string());
+                }
+            }
+
             // Upload local
-            let content = fs::read(&local_path).await
+            if !plan.admit(TAGS_FILE, plan::KIND_UPLOAD, plan::REASON_NEW_LOCAL) {
+                return Ok(String::new());
+            }
+            let content = self.vault.read(&local_path)
                 .map_err(|e| format!("Failed to read tags: {}", e))?;
 
-            let result = drive.upload_content(
-                &content,
+            let result = store.upload_content(
+                &self.seal_upload(&content)?,
                 TAGS_FILE,
                 folder_id,
                 "application/json",
                 None,
             ).await?;
 
-            let hash = super::metadata::calculate_content_hash(&content);
+            let hash = calculate_content_hash(&content);
             let modified = get_file_modified_time(&local_path).await?;
 
             metadata.update_file_metadata(
@@ -437,14 +1310,30 @@ impl SyncEngine {
         }
 
         if !local_exists && remote_exists {
-            // Download remote
             let remote = remote_file.unwrap();
-            let content = drive.download_file(&remote.id).await?;
 
-            fs::write(&local_path, &content).await
+            // Previously synced and unchanged remotely - it was deleted locally
+            if let Some(file_meta) = metadata.get_file_metadata(TAGS_FILE) {
+                if file_meta.remote_modified == remote.modified_time {
+                    if !plan.admit(TAGS_FILE, plan::KIND_DELETE_REMOTE, plan::REASON_DELETED_LOCALLY) {
+                        return Ok(String::new());
+                    }
+                    self.delete_remote_file(store, &remote.id).await?;
+                    metadata.remove_file_metadata(TAGS_FILE);
+                    return Ok("deleted_remote".to_string());
+                }
+            }
+
+            // Download remote
+            if !plan.admit(TAGS_FILE, plan::KIND_DOWNLOAD, plan::REASON_NEW_REMOTE) {
+                return Ok(String::new());
+            }
+            let content = self.open_download(store.download_file(&remote.id).await?)?;
+
+            self.vault.write(&local_path, &content)
                 .map_err(|e| format!("Failed to write tags: {}", e))?;
 
-            let hash = super::metadata::calculate_content_hash(&content);
+            let hash = calculate_content_hash(&content);
             let modified = get_file_modified_time(&local_path).await?;
 
             metadata.update_file_metadata(
@@ -460,38 +1349,47 @@ impl SyncEngine {
 
         // Both exist - compare and sync
         let remote = remote_file.unwrap();
-        let local_modified = get_file_modified_time(&local_path).await?;
         let remote_modified = remote.modified_time.clone().unwrap_or_default();
 
-        let local_hash = calculate_file_hash(&local_path).map_err(|e| e.to_string())?;
+        let local_hash = self.local_hash(&local_path)?;
 
         let meta_key = TAGS_FILE;
-        let should_upload = if let Some(file_meta) = metadata.get_file_metadata(meta_key) {
-            let local_changed = file_meta.synced_hash != local_hash;
-            let remote_changed = file_meta.remote_modified.as_ref() != Some(&remote_modified);
+        let synced = metadata.get_file_metadata(meta_key).is_some();
+        let (local_changed, remote_changed) = match metadata.get_file_metadata(meta_key) {
+            Some(file_meta) => (
+                file_meta.synced_hash != local_hash,
+                file_meta.remote_modified.as_ref() != Some(&remote_modified),
+            ),
+            // Never synced - both sides may hold changes
+            None => (true, true),
+        };
 
-            if local_changed && remote_changed {
-                local_modified > remote_modified
-            } else {
-                local_changed
+        if local_changed && remote_changed {
+            let reason = if synced { plan::REASON_CONFLICT } else { plan::REASON_FIRST_SYNC };
+            if !plan.admit(TAGS_FILE, plan::KIND_MERGE, reason) {
+                return Ok(String::new());
             }
-        } else {
-            local_modified > remote_modified
-        };
+            return self.merge_tags(store, folder_id, &local_path, &remote, metadata).await;
+        }
 
-        if should_upload {
-            let content = fs::read(&local_path).await
+        if local_changed || (!remote_changed && self.needs_reencryption(&remote, metadata)) {
+            let reason = if local_changed { plan::REASON_LOCAL_CHANGED } else { plan::REASON_NOT_ENCRYPTED };
+            if !plan.admit(TAGS_FILE, plan::KIND_UPLOAD, reason) {
+                return Ok(String::new());
+            }
+            let content = self.vault.read(&local_path)
                 .map_err(|e| format!("Failed to read tags: {}", e))?;
 
-            let result = drive.upload_content(
-                &content,
+            let result = store.upload_content(
+                &self.seal_upload(&content)?,
                 TAGS_FILE,
                 folder_id,
                 "application/json",
                 Some(&remote.id),
             ).await?;
 
-            let hash = super::metadata::calculate_content_hash(&content);
+            let hash = calculate_content_hash(&content);
+            let local_modified = get_file_modified_time(&local_path).await?;
 
             metadata.update_file_metadata(
                 TAGS_FILE,
@@ -502,14 +1400,17 @@ impl SyncEngine {
             );
 
             Ok("uploaded".to_string())
-        } else {
+        } else if remote_changed {
             // Download
-            let content = drive.download_file(&remote.id).await?;
+            if !plan.admit(TAGS_FILE, plan::KIND_DOWNLOAD, plan::REASON_REMOTE_CHANGED) {
+                return Ok(String::new());
+            }
+            let content = self.open_download(store.download_file(&remote.id).await?)?;
 
-            fs::write(&local_path, &content).await
+            self.vault.write(&local_path, &content)
                 .map_err(|e| format!("Failed to write tags: {}", e))?;
 
-            let hash = super::metadata::calculate_content_hash(&content);
+            let hash = calculate_content_hash(&content);
             let modified = get_file_modified_time(&local_path).await?;
 
             metadata.update_file_metadata(
@@ -521,25 +1422,125 @@ impl SyncEngine {
             );
 
             Ok("downloaded".to_string())
+        } else {
+            Ok(String::new())
+        }
+    }
+
+    /// Drop deleted tag tombstones that every device in the device list has synced past,
+    /// so they don't pile up in tags.json. The change is synced like any local edit.
+    async fn expire_tag_tombstones(&self, store: &dyn RemoteStore, app_folder_id: &str) {
+        let local_path = self.diary_dir.join(TAGS_FILE);
+        let result = async {
+            let content = match self.vault.read(&local_path) {
+                Ok(content) => content,
+                Err(_) if !local_path.exists() => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            let mut data: TagsData = serde_json::from_slice(&content)
+                .map_err(|e| format!("Invalid local tags: {}", e))?;
+            let now = chrono::Utc::now();
+            let retention = chrono::Duration::days(tags::TOMBSTONE_RETENTION_DAYS);
+            let expired = data.deleted_tags.values()
+                .filter_map(|deleted_at| chrono::DateTime::parse_from_rfc3339(deleted_at).ok())
+                .any(|deleted_at| deleted_at < now - retention);
+            if !expired {
+                return Ok(false);
+            }
+
+            // A device missing from the list or without a readable sync time may not
+            // have seen the deletions yet
+            let (devices, _) = self.read_devices(store, app_folder_id).await?;
+            let sync_times: Option<Vec<_>> = devices.iter()
+                .map(|d| chrono::DateTime::parse_from_rfc3339(&d.last_sync).ok())
+                .collect();
+            let Some(oldest_sync) = sync_times.and_then(|times| times.into_iter().min()) else {
+                return Ok(false);
+            };
+            if !tags::expire_tombstones(&mut data, oldest_sync.with_timezone(&chrono::Utc), now) {
+                return Ok(false);
+            }
+
+            let content = serde_json::to_vec_pretty(&data)
+                .map_err(|e| format!("Failed to serialize tags: {}", e))?;
+            self.vault.write(&local_path, &content)?;
+            Ok(true)
         }
+        .await;
+        match result {
+            Ok(true) => println!("[Sync] Dropped expired tag tombstones"),
+            Ok(false) => {}
+            Err(e) => println!("[Sync] Failed to expire tag tombstones: {}", e),
+        }
+    }
+
+    /// Merge tags.json changed on both sides and write the result to whichever side differs
+    async fn merge_tags(
+        &self,
+        store: &dyn RemoteStore,
+        folder_id: &str,
+        local_path: &Path,
+        remote: &RemoteFile,
+        metadata: &mut SyncMetadata,
+    ) -> Result<String, String> {
+        let local_path = local_path.to_path_buf();
+        let local_content = self.vault.read(&local_path)
+            .map_err(|e| format!("Failed to read tags: {}", e))?;
+        let remote_content = self.open_download(store.download_file(&remote.id).await?)?;
+
+        let local_data: TagsData = serde_json::from_slice(&local_content)
+            .map_err(|e| format!("Invalid local tags: {}", e))?;
+        let remote_data: TagsData = serde_json::from_slice(&remote_content)
+            .map_err(|e| format!("Invalid remote tags: {}", e))?;
+
+        let merged = tags::merge_tags_data(local_data.clone(), remote_data.clone());
+        let merged_content = serde_json::to_vec_pretty(&merged)
+            .map_err(|e| format!("Failed to serialize tags: {}", e))?;
+
+        let hash = if merged != local_data {
+            self.vault.write(&local_path, &merged_content)
+                .map_err(|e| format!("Failed to write tags: {}", e))?;
+            calculate_content_hash(&merged_content)
+        } else {
+            calculate_content_hash(&local_content)
+        };
+
+        let remote_modified = if merged != remote_data {
+            let result = store.upload_content(
+                &self.seal_upload(&merged_content)?,
+                TAGS_FILE,
+                folder_id,
+                "application/json",
+                Some(&remote.id),
+            ).await?;
+            result.modified_time
+        } else {
+            remote.modified_time.clone()
+        };
+
+        let modified = get_file_modified_time(&local_path).await?;
+        metadata.update_file_metadata(
+            TAGS_FILE,
+            &modified,
+            Some(remote.id.clone()),
+            remote_modified,
+            &hash,
+        );
+
+        Ok("merged".to_string())
     }
 
     async fn sync_images(
         &self,
-        drive: &DriveClient,
+        store: &dyn RemoteStore,
         metadata: &mut SyncMetadata,
         folder_id: &str,
-    ) -> Result<(Vec<String>, Vec<String>, Vec<String>), String> {
-        let mut uploaded = Vec::new();
-        let mut downloaded = Vec::new();
-        let mut errors = Vec::new();
-
-        let images_dir = self.diary_dir.join("images");
+        scope: Option<&ChangeScope>,
+        plan: &mut SyncPlan,
+    ) -> Result<SyncReport, String> {
+        let mut report = SyncReport::default();
 
-        // Create images directory if it doesn't exist
-        if !images_dir.exists() {
-            fs::create_dir_all(&images_dir).await.ok();
-        }
+        let images_dir = local_images_dir(&self.diary_dir, plan.is_preview()).await;
 
         // Get local images
         self.emit_progress("images", 0, 1, "Scanning local images...");
@@ -559,70 +1560,145 @@ impl SyncEngine {
 
         // Get remote images
         self.emit_progress("images", 0, 1, "Fetching remote images...");
-        let remote_files = drive.list_files(folder_id).await?;
+        let remote_files = match scope {
+            Some(scope) => {
+                local_images.retain(|name, _| scope.contains("images", name));
+                scope.remote_files(store, metadata, "images", folder_id).await?
+            }
+            None => store.list_files(folder_id).await?,
+        };
         let remote_images: HashMap<String, _> = remote_files
             .into_iter()
             .map(|f| (f.name.clone(), f))
             .collect();
 
-        // Collect images to upload/download
-        let images_to_upload: Vec<_> = local_images.iter()
-            .filter(|(name, _)| !remote_images.contains_key(*name))
-            .collect();
-        let images_to_download: Vec<_> = remote_images.iter()
-            .filter(|(name, _)| !local_images.contains_key(*name))
-            .collect();
-
-        let total_images = (images_to_upload.len() + images_to_download.len()) as u32;
-        let mut current = 0u32;
-
-        // Upload local-only images
-        for (name, local_path) in images_to_upload {
-            current += 1;
-            self.emit_progress("images", current, total_images, &format!("Uploading {}...", name));
-            match drive.upload_file(local_path, name, folder_id, None).await {
-                Ok(result) => {
-                    let hash = calculate_file_hash(local_path).unwrap_or_default();
-                    let modified = get_file_modified_time(local_path).await.unwrap_or_default();
-
-                    metadata.update_file_metadata(
-                        &format!("images/{}", name),
-                        &modified,
-                        Some(result.id),
-                        result.modified_time,
-                        &hash,
-                    );
-                    uploaded.push(format!("images/{}", name));
+        // Compare each image with its last synced version. A one-sided image that was
+        // synced before and is unchanged since was deleted on the other side.
+        let mut images_to_upload = Vec::new();
+        let mut images_to_download = Vec::new();
+        let mut images_to_resolve = Vec::new();
+        let mut images_to_delete_local = Vec::new();
+        for (name, local_path) in &local_images {
+            let file_meta = metadata.get_file_metadata(&format!("images/{}", name));
+            let Some(remote) = remote_images.get(name) else {
+                let deleted_remotely = match file_meta {
+                    Some(file_meta) => !self.local_image_changed(local_path, file_meta).await,
+                    None => false,
+                };
+                if deleted_remotely {
+                    images_to_delete_local.push((name, local_path));
+                } else {
+                    images_to_upload.push((name, local_path, None, plan::REASON_NEW_LOCAL));
                 }
-                Err(e) => errors.push(format!("Upload image {} failed: {}", name, e)),
+                continue;
+            };
+
+            let Some(file_meta) = file_meta else {
+                // Same name on both sides but never synced - compare the content first
+                images_to_resolve.push((local_path, remote, plan::REASON_FIRST_SYNC));
+                continue;
+            };
+
+            let local_changed = self.local_image_changed(local_path, file_meta).await;
+            let remote_changed = remote_changed(file_meta, remote);
+            if local_changed && remote_changed {
+                images_to_resolve.push((local_path, remote, plan::REASON_CONFLICT));
+            } else if local_changed {
+                images_to_upload.push((name, local_path, Some(remote.id.as_str()), plan::REASON_LOCAL_CHANGED));
+            } else if remote_changed {
+                images_to_download.push((name, remote, plan::REASON_REMOTE_CHANGED));
+            } else if self.needs_reencryption(remote, metadata) {
+                images_to_upload.push((name, local_path, Some(remote.id.as_str()), plan::REASON_NOT_ENCRYPTED));
             }
         }
 
-        // Download remote-only images
-        for (name, remote) in images_to_download {
-            current += 1;
-            self.emit_progress("images", current, total_images, &format!("Downloading {}...", name));
-            let local_path = images_dir.join(name);
-            match drive.download_file(&remote.id).await {
-                Ok(content) => {
-                    if let Err(e) = fs::write(&local_path, &content).await {
-                        errors.push(format!("Write image {} failed: {}", name, e));
-                        continue;
-                    }
+        let mut images_to_delete_remote = Vec::new();
+        for (name, remote) in remote_images.iter().filter(|(name, _)| !local_images.contains_key(*name)) {
+            let deleted_locally = metadata.get_file_metadata(&format!("images/{}", name))
+                .map(|file_meta| !remote_changed(file_meta, remote))
+                .unwrap_or(false);
+            if deleted_locally {
+                images_to_delete_remote.push((name, remote));
+            } else {
+                images_to_download.push((name, remote, plan::REASON_NEW_REMOTE));
+            }
+        }
 
-                    let hash = super::metadata::calculate_content_hash(&content);
-                    let modified = get_file_modified_time(&local_path).await.unwrap_or_default();
-
-                    metadata.update_file_metadata(
-                        &format!("images/{}", name),
-                        &modified,
-                        Some(remote.id.clone()),
-                        remote.modified_time.clone(),
-                        &hash,
-                    );
-                    downloaded.push(format!("images/{}", name));
+        // Forget images that are gone on both sides
+        let stale_keys: Vec<String> = metadata.files.keys()
+            .filter(|key| scope.map(|scope| scope.keys.contains(*key)).unwrap_or(true))
+            .filter_map(|key| {
+                let name = key.strip_prefix("images/")?;
+                if local_images.contains_key(name) || remote_images.contains_key(name) {
+                    None
+                } else {
+                    Some(key.clone())
                 }
-                Err(e) => errors.push(format!("Download image {} failed: {}", name, e)),
+            })
+            .collect();
+        for key in stale_keys {
+            metadata.remove_file_metadata(&key);
+        }
+
+        // Uploads of images deleted before they finished
+        if scope.is_none() && !plan.is_preview() {
+            let abandoned: Vec<String> = metadata.uploads.keys()
+                .filter(|key| key.strip_prefix("images/").map(|name| !local_images.contains_key(name)).unwrap_or(false))
+                .cloned()
+                .collect();
+            for key in abandoned {
+                self.forget_upload(metadata, &key);
+            }
+        }
+
+        let key = |name: &str| format!("images/{}", name);
+        images_to_upload.retain(|(name, _, _, reason)| plan.admit(&key(name), plan::KIND_UPLOAD, reason));
+        images_to_download.retain(|(name, _, reason)| plan.admit(&key(name), plan::KIND_DOWNLOAD, reason));
+        images_to_resolve.retain(|(_, remote, reason)| plan.admit(&key(&remote.name), plan::KIND_MERGE, reason));
+        images_to_delete_local.retain(|(name, _)| plan.admit(&key(name), plan::KIND_DELETE_LOCAL, plan::REASON_DELETED_REMOTELY));
+        images_to_delete_remote.retain(|(name, _)| plan.admit(&key(name), plan::KIND_DELETE_REMOTE, plan::REASON_DELETED_LOCALLY));
+
+        let mut actions = Vec::new();
+        actions.extend(images_to_upload.into_iter().map(|(name, local_path, remote_id, _)| ImageAction::Upload {
+            name: name.clone(),
+            local_path: local_path.clone(),
+            remote_id: remote_id.map(|id| id.to_string()),
+        }));
+        actions.extend(images_to_download.into_iter().map(|(name, remote, _)| ImageAction::Download {
+            name: name.clone(),
+            remote: remote.clone(),
+        }));
+        actions.extend(images_to_resolve.into_iter().map(|(local_path, remote, _)| ImageAction::Resolve {
+            local_path: local_path.clone(),
+            remote: remote.clone(),
+        }));
+        actions.extend(images_to_delete_local.into_iter().map(|(name, local_path)| ImageAction::DeleteLocal {
+            name: name.clone(),
+            local_path: local_path.clone(),
+        }));
+        actions.extend(images_to_delete_remote.into_iter().map(|(name, remote)| ImageAction::DeleteRemote {
+            name: name.clone(),
+            remote: remote.clone(),
+        }));
+
+        let total_images = actions.len();
+        let progress = StageProgress::new(self, "images", total_images);
+        let shared_metadata = Mutex::new(std::mem::take(metadata));
+        let results: Vec<SyncReport> = stream::iter(actions)
+            .map(|action| self.execute_image_action(store, folder_id, action, &shared_metadata, &progress))
+            .buffer_unordered(self.concurrency())
+            .collect()
+            .await;
+        *metadata = shared_metadata.into_inner().unwrap();
+        for result in results {
+            report.merge(result);
+        }
+        for path in &report.downloaded {
+            let device_name = path.strip_prefix("images/")
+                .and_then(|name| remote_images.get(name))
+                .and_then(|r| r.device_name.clone());
+            if let Some(device_name) = device_name {
+                report.origins.insert(path.clone(), device_name);
             }
         }
 
@@ -630,28 +1706,123 @@ impl SyncEngine {
             self.emit_progress("images", 1, 1, "Images up to date");
         }
 
-        Ok((uploaded, downloaded, errors))
+        Ok(report)
+    }
+
+    async fn execute_image_action(
+        &self,
+        store: &dyn RemoteStore,
+        folder_id: &str,
+        action: ImageAction,
+        metadata: &Mutex<SyncMetadata>,
+        progress: &StageProgress<'_>,
+    ) -> SyncReport {
+        let mut report = SyncReport::default();
+        let message = action.progress_message();
+        progress.start(&message);
+
+        match action {
+            ImageAction::Upload { name, local_path, remote_id } => {
+                match self.upload_image(store, &local_path, &name, folder_id, remote_id.as_deref(), metadata).await {
+                    Ok((result, hash)) => {
+                        let modified = get_file_modified_time(&local_path).await.unwrap_or_default();
+                        let meta_key = format!("images/{}", name);
+
+                        let mut metadata = metadata.lock().unwrap();
+                        metadata.update_file_metadata(
+                            &meta_key,
+                            &modified,
+                            Some(result.id),
+                            result.modified_time,
+                            &hash,
+                        );
+                        metadata.set_remote_checksum(&meta_key, result.checksum);
+                        report.uploaded.push(meta_key);
+                    }
+                    Err(e) => report.errors.push(format!("Upload image {} failed: {}", name, e)),
+                }
+            }
+            ImageAction::Download { name, remote } => {
+                let local_path = self.diary_dir.join("images").join(&name);
+                match store.download_file(&remote.id).await.and_then(|content| self.open_download(content)) {
+                    Ok(content) => match self.vault.write(&local_path, &content) {
+                        Ok(_) => {
+                            let hash = calculate_content_hash(&content);
+                            let modified = get_file_modified_time(&local_path).await.unwrap_or_default();
+                            let meta_key = format!("images/{}", name);
+
+                            let mut metadata = metadata.lock().unwrap();
+                            metadata.update_file_metadata(
+                                &meta_key,
+                                &modified,
+                                Some(remote.id.clone()),
+                                remote.modified_time.clone(),
+                                &hash,
+                            );
+                            metadata.set_remote_checksum(&meta_key, remote.checksum.clone());
+                            report.downloaded.push(meta_key);
+                        }
+                        Err(e) => report.errors.push(format!("Write image {} failed: {}", name, e)),
+                    },
+                    Err(e) => report.errors.push(format!("Download image {} failed: {}", name, e)),
+                }
+            }
+            ImageAction::Resolve { local_path, remote } => {
+                match self.resolve_image(store, folder_id, &local_path, &remote, metadata).await {
+                    Ok(Some(copy)) => report.conflicts_resolved.push(copy),
+                    Ok(None) => {}
+                    Err(e) => report.errors.push(format!("Sync image {} failed: {}", remote.name, e)),
+                }
+            }
+            ImageAction::DeleteLocal { name, local_path } => {
+                match self.delete_local_file(&local_path).await {
+                    Ok(_) => {
+                        let meta_key = format!("images/{}", name);
+                        metadata.lock().unwrap().remove_file_metadata(&meta_key);
+                        report.deleted_local.push(meta_key);
+                    }
+                    Err(e) => report.errors.push(format!("Delete local image {} failed: {}", name, e)),
+                }
+            }
+            ImageAction::DeleteRemote { name, remote } => {
+                match self.delete_remote_file(store, &remote.id).await {
+                    Ok(_) => {
+                        let meta_key = format!("images/{}", name);
+                        metadata.lock().unwrap().remove_file_metadata(&meta_key);
+                        report.deleted_remote.push(meta_key);
+                    }
+                    Err(e) => report.errors.push(format!("Delete remote image {} failed: {}", name, e)),
+                }
+            }
+        }
+
+        progress.finish(&message);
+        report
     }
 }
 
 impl SyncEngine {
     /// Force upload all local files to cloud, overwriting remote versions
-    pub async fn force_upload_sync(&self, access_token: &str) -> Result<SyncReport, String> {
+    pub async fn force_upload_sync(&self, store: &dyn RemoteStore) -> Result<SyncReport, String> {
         let start_time = std::time::Instant::now();
         let mut report = SyncReport::default();
 
+        if self.vault.is_locked() {
+            return Err("Diary is locked".to_string());
+        }
+
         println!("[Sync] Starting FORCE UPLOAD sync...");
 
         self.app.emit("sync-started", ()).ok();
         self.emit_progress("init", 0, 4, "Force uploading to cloud...");
 
-        let drive = DriveClient::new(access_token.to_string());
         let mut metadata = SyncMetadata::load(&self.app).unwrap_or_default();
 
         // Ensure folder structure
-        self.emit_progress("init", 1, 4, "Connecting to Google Drive...");
+        self.emit_progress("init", 1, 4, "Connecting...");
         let (app_folder_id, entries_folder_id, images_folder_id) =
-            drive.ensure_folder_structure().await?;
+            store.ensure_folder_structure().await?;
+        encryption::verify_remote(store, &app_folder_id, self.sync_key.as_ref(), self.settings.e2e_encryption, true).await?;
 
         metadata.drive_folder_id = Some(app_folder_id.clone());
         metadata.entries_folder_id = Some(entries_folder_id.clone());
@@ -660,42 +1831,49 @@ impl SyncEngine {
         // Force upload all diary entries
         self.emit_progress("entries", 0, 1, "Uploading diary entries...");
         let local_entries = self.get_local_entries().await?;
-        let remote_files = drive.list_files(&entries_folder_id).await?;
+        let remote_files = store.list_files(&entries_folder_id).await?;
         let remote_entries: HashMap<String, _> = remote_files
             .into_iter()
-            .filter(|f| f.name.ends_with(".txt"))
+            .filter(|f| diary::is_entry_file(Path::new(&f.name)))
             .map(|f| (f.name.clone(), f))
             .collect();
 
-        let total = local_entries.len() as u32;
-        for (i, (name, local_path)) in local_entries.iter().enumerate() {
-            let current = (i + 1) as u32;
-            self.emit_progress("entries", current, total, &format!("Uploading {}...", name));
-
-            let existing_id = remote_entries.get(name).map(|r| r.id.as_str());
-            match self.upload_entry(&drive, local_path, name, &entries_folder_id, existing_id, &mut metadata).await {
-                Ok(_) => report.uploaded.push(name.clone()),
-                Err(e) => report.errors.push(format!("Upload {} failed: {}", name, e)),
-            }
+        let actions: Vec<SyncAction> = local_entries.into_iter()
+            .map(|(name, local_path)| SyncAction::Upload {
+                remote_id: remote_entries.get(&name).map(|r| r.id.clone()),
+                local_path,
+                remote_name: name,
+            })
+            .collect();
+        let progress = StageProgress::new(self, "entries", actions.len());
+        let shared_metadata = Mutex::new(std::mem::take(&mut metadata));
+        let results: Vec<SyncReport> = stream::iter(actions)
+            .map(|action| self.execute_entry_action(store, &entries_folder_id, action, &shared_metadata, &progress))
+            .buffer_unordered(self.concurrency())
+            .collect()
+            .await;
+        metadata = shared_metadata.into_inner().unwrap();
+        for result in results {
+            report.merge(result);
         }
 
         // Force upload tags.json
         self.emit_progress("tags", 0, 1, "Uploading tags...");
         let tags_path = self.diary_dir.join("tags.json");
-        if tags_path.exists() {
-            let content = fs::read(&tags_path).await
+        if self.settings.syncs_tags() && tags_path.exists() {
+            let content = self.vault.read(&tags_path)
                 .map_err(|e| format!("Failed to read tags: {}", e))?;
 
-            let existing_tag = drive.find_file("tags.json", &app_folder_id).await?;
-            let result = drive.upload_content(
-                &content,
+            let existing_tag = store.find_file("tags.json", &app_folder_id).await?;
+            let result = store.upload_content(
+                &self.seal_upload(&content)?,
                 "tags.json",
                 &app_folder_id,
                 "application/json",
                 existing_tag.as_ref().map(|f| f.id.as_str()),
             ).await?;
 
-            let hash = super::metadata::calculate_content_hash(&content);
+            let hash = calculate_content_hash(&content);
             let modified = get_file_modified_time(&tags_path).await?;
             metadata.update_file_metadata("tags.json", &modified, Some(result.id), result.modified_time, &hash);
             report.uploaded.push("tags.json".to_string());
@@ -704,7 +1882,7 @@ impl SyncEngine {
         // Force upload images
         self.emit_progress("images", 0, 1, "Uploading images...");
         let images_dir = self.diary_dir.join("images");
-        if images_dir.exists() {
+        if self.settings.syncs_images() && images_dir.exists() {
             let mut local_images: HashMap<String, PathBuf> = HashMap::new();
             for entry in walkdir::WalkDir::new(&images_dir).max_depth(1) {
                 if let Ok(entry) = entry {
@@ -717,36 +1895,36 @@ impl SyncEngine {
                 }
             }
 
-            let remote_images_list = drive.list_files(&images_folder_id).await?;
+            let remote_images_list = store.list_files(&images_folder_id).await?;
             let remote_images: HashMap<String, _> = remote_images_list
                 .into_iter()
                 .map(|f| (f.name.clone(), f))
                 .collect();
 
-            let total = local_images.len() as u32;
-            for (i, (name, local_path)) in local_images.iter().enumerate() {
-                let current = (i + 1) as u32;
-                self.emit_progress("images", current, total, &format!("Uploading {}...", name));
-
-                let existing_id = remote_images.get(name).map(|r| r.id.as_str());
-                match drive.upload_file(local_path, name, &images_folder_id, existing_id).await {
-                    Ok(result) => {
-                        let hash = calculate_file_hash(local_path).unwrap_or_default();
-                        let modified = get_file_modified_time(local_path).await.unwrap_or_default();
-                        metadata.update_file_metadata(
-                            &format!("images/{}", name),
-                            &modified,
-                            Some(result.id),
-                            result.modified_time,
-                            &hash,
-                        );
-                        report.uploaded.push(format!("images/{}", name));
-                    }
-                    Err(e) => report.errors.push(format!("Upload image {} failed: {}", name, e)),
-                }
+            let actions: Vec<ImageAction> = local_images.into_iter()
+                .map(|(name, local_path)| ImageAction::Upload {
+                    remote_id: remote_images.get(&name).map(|r| r.id.clone()),
+                    name,
+                    local_path,
+                })
+                .collect();
+            let progress = StageProgress::new(self, "images", actions.len());
+            let shared_metadata = Mutex::new(std::mem::take(&mut metadata));
+            let results: Vec<SyncReport> = stream::iter(actions)
+                .map(|action| self.execute_image_action(store, &images_folder_id, action, &shared_metadata, &progress))
+                .buffer_unordered(self.concurrency())
+                .collect()
+                .await;
+            metadata = shared_metadata.into_inner().unwrap();
+            for result in results {
+                report.merge(result);
             }
         }
 
+        // Everything was replaced; compare all files again at the next sync
+        metadata.changes_token = None;
+        self.register_device(store, &app_folder_id).await;
+
         // Update sync time
         metadata.update_last_sync_time();
         metadata.save(&self.app).map_err(|e| e.to_string())?;
@@ -760,9 +1938,277 @@ impl SyncEngine {
 
         Ok(report)
     }
+
+    async fn read_devices(&self, store: &dyn RemoteStore, app_folder_id: &str) -> Result<(Vec<SyncedDevice>, Option<String>), String> {
+        let Some(remote) = store.find_file(device::REMOTE_DEVICES_FILE, app_folder_id).await? else {
+            return Ok((Vec::new(), None));
+        };
+        let content = self.open_download(store.download_file(&remote.id).await?)?;
+        let devices = serde_json::from_slice(&content)
+            .map_err(|e| format!("Failed to parse device list: {}", e))?;
+        Ok((devices, Some(remote.id)))
+    }
+
+    /// Add this device to the remote device list, or update its entry if that changed.
+    /// The list is informational, so failures are only logged.
+    async fn register_device(&self, store: &dyn RemoteStore, app_folder_id: &str) {
+        let Some(this_device) = &self.device else {
+            return;
+        };
+        let result = async {
+            let (mut devices, remote_id) = self.read_devices(store, app_folder_id).await?;
+            if !device::upsert(&mut devices, this_device) {
+                return Ok(None);
+            }
+            let content = serde_json::to_vec_pretty(&devices).map_err(|e| e.to_string())?;
+            store.upload_content(
+                &self.seal_upload(&content)?,
+                device::REMOTE_DEVICES_FILE,
+                app_folder_id,
+                "application/json",
+                remote_id.as_deref(),
+            ).await.map(Some)
+        }
+        .await;
+        if let Err(e) = result {
+            println!("[Sync] Failed to update device list: {}", e);
+        }
+    }
+
+    /// Devices that have synced to the sync target, most recent first
+    pub async fn list_devices(&self, store: &dyn RemoteStore) -> Result<Vec<SyncedDevice>, String> {
+        let (app_folder_id, _, _) = store.ensure_folder_structure().await?;
+        let (mut devices, _) = self.read_devices(store, &app_folder_id).await?;
+        devices.sort_by(|a, b| b.last_sync.cmp(&a.last_sync));
+        Ok(devices)
+    }
+
+    /// Download one image that is not synced to this device, e.g. when an entry showing it
+    /// is opened. It is stored like any local image but not recorded as synced.
+    pub async fn fetch_image(&self, store: &dyn RemoteStore, name: &str) -> Result<Vec<u8>, String> {
+        if self.vault.is_locked() {
+            return Err("Diary is locked".to_string());
+        }
+
+        let metadata = SyncMetadata::load(&self.app).unwrap_or_default();
+        let images_folder_id = match metadata.images_folder_id {
+            Some(id) => id,
+            None => store.ensure_folder_structure().await?.2,
+        };
+        let remote = store.find_file(name, &images_folder_id).await?
+            .ok_or_else(|| format!("Image not found on the sync target: {}", name))?;
+        let content = self.open_download(store.download_file(&remote.id).await?)?;
+
+        let images_dir = self.diary_dir.join("images");
+        fs::create_dir_all(&images_dir).await
+            .map_err(|e| format!("Failed to create images directory: {}", e))?;
+        self.vault.write(&images_dir.join(name), &content)?;
+
+        println!("[Sync] Fetched image {} on demand", name);
+        Ok(content)
+    }
+}
+
+// Entries smaller than this hold no more than the date line or whitespace
+const EMPTY_ENTRY_BYTES: u64 = 20;
+
+/// Drop stored versions that are no longer the merge base of an entry. A preview keeps
+/// them all: its metadata may have been cleared or narrowed without being saved.
+fn prune_merge_bases(shadow_dir: &Path, metadata: &SyncMetadata, preview: bool) -> Result<(), String> {
+    if preview {
+        return Ok(());
+    }
+    // Only the last synced version of each entry is needed as a merge base
+    let synced_hashes: HashSet<&str> = metadata.files.iter()
+        .filter(|(key, _)| key.starts_with("entries/"))
+        .map(|(_, file_meta)| file_meta.synced_hash.as_str())
+        .collect();
+    shadow::prune(shadow_dir, &synced_hashes)
+}
+
+/// Folder of the diary's images, created if missing unless this is a preview
+async fn local_images_dir(diary_dir: &Path, preview: bool) -> PathBuf {
+    let images_dir = diary_dir.join("images");
+    if !preview && !images_dir.exists() {
+        fs::create_dir_all(&images_dir).await.ok();
+    }
+    images_dir
+}
+
+/// One entry as found on both sides, for `plan_entry`
+struct EntrySides<'a> {
+    name: &'a str,
+    /// Path, content hash and size of the local file
+    local: Option<(&'a Path, &'a str, u64)>,
+    remote: Option<&'a RemoteFile>,
+    /// State of the last sync of the entry
+    synced: Option<&'a FileMetadata>,
+}
+
+/// Whether an unchanged remote file has to be uploaded again, encrypted. Files the backend
+/// can't tell about count as plaintext until a full encrypting sync has re-uploaded them.
+fn needs_reencryption(remote: &RemoteFile, encrypting: bool, reencrypted: bool) -> bool {
+    encrypting && !remote.encrypted.unwrap_or(reencrypted)
+}
+
+/// Action that brings one entry in sync, with its reason, or None when it already is.
+/// `encrypting` re-uploads unchanged plaintext copies once end-to-end encryption is on,
+/// see `needs_reencryption`.
+fn plan_entry(
+    sides: &EntrySides,
+    diary_dir: &Path,
+    encrypting: bool,
+    reencrypted: bool,
+) -> Option<(SyncAction, &'static str)> {
+    let name = sides.name.to_string();
+    match (sides.local, sides.remote) {
+        (None, None) => None,
+        (Some((local_path, local_hash, local_size)), Some(remote)) => {
+            let local_path = local_path.to_path_buf();
+            let remote_modified = remote.modified_time.clone().unwrap_or_default();
+            let mut remote_size = remote.size.unwrap_or(0);
+            if remote.encrypted == Some(true) {
+                remote_size = remote_size.saturating_sub(vault::SEAL_OVERHEAD as u64);
+            }
+            let local_is_empty = local_size < EMPTY_ENTRY_BYTES;
+            let remote_is_empty = remote_size < EMPTY_ENTRY_BYTES;
+
+            // An empty side never overwrites one with content
+            if local_is_empty && !remote_is_empty {
+                return Some((SyncAction::Download {
+                    remote_id: remote.id.clone(),
+                    remote_name: name,
+                    local_path,
+                }, plan::REASON_EMPTY_FILE));
+            }
+            if !local_is_empty && remote_is_empty {
+                return Some((SyncAction::Upload {
+                    local_path,
+                    remote_name: name,
+                    remote_id: Some(remote.id.clone()),
+                }, plan::REASON_EMPTY_FILE));
+            }
+
+            let Some(file_meta) = sides.synced else {
+                // First time syncing this file - no common base, so any difference is a conflict
+                return Some((SyncAction::Merge {
+                    local_path,
+                    remote: remote.clone(),
+                    base_hash: None,
+                }, plan::REASON_FIRST_SYNC));
+            };
+            let local_changed = file_meta.synced_hash != local_hash;
+            let remote_changed = file_meta.remote_modified.as_ref() != Some(&remote_modified);
+
+            // Stored in plaintext before encryption was turned on and unchanged since
+            if !remote_changed && needs_reencryption(remote, encrypting, reencrypted) {
+                return Some((SyncAction::Upload {
+                    local_path,
+                    remote_name: name,
+                    remote_id: Some(remote.id.clone()),
+                }, plan::REASON_NOT_ENCRYPTED));
+            }
+
+            match (local_changed, remote_changed) {
+                // Conflict - merge against the last synced version
+                (true, true) => Some((SyncAction::Merge {
+                    local_path,
+                    remote: remote.clone(),
+                    base_hash: Some(file_meta.synced_hash.clone()),
+                }, plan::REASON_CONFLICT)),
+                (true, false) => Some((SyncAction::Upload {
+                    local_path,
+                    remote_name: name,
+                    remote_id: Some(remote.id.clone()),
+                }, plan::REASON_LOCAL_CHANGED)),
+                (false, true) => Some((SyncAction::Download {
+                    remote_id: remote.id.clone(),
+                    remote_name: name,
+                    local_path,
+                }, plan::REASON_REMOTE_CHANGED)),
+                (false, false) => None,
+            }
+        }
+        (Some((local_path, local_hash, local_size)), None) => match sides.synced {
+            // Synced before and untouched locally since - it was deleted remotely
+            Some(file_meta) if file_meta.synced_hash == local_hash => Some((SyncAction::DeleteLocal {
+                local_path: local_path.to_path_buf(),
+            }, plan::REASON_DELETED_REMOTELY)),
+            // Only exists locally - upload only if has content
+            _ if local_size >= EMPTY_ENTRY_BYTES => Some((SyncAction::Upload {
+                local_path: local_path.to_path_buf(),
+                remote_name: name,
+                remote_id: None,
+            }, plan::REASON_NEW_LOCAL)),
+            _ => None,
+        },
+        (None, Some(remote)) => match sides.synced {
+            // Synced before and unchanged remotely since - it was deleted locally
+            Some(file_meta) if file_meta.remote_modified == remote.modified_time => Some((SyncAction::DeleteRemote {
+                remote_id: remote.id.clone(),
+                remote_name: name,
+            }, plan::REASON_DELETED_LOCALLY)),
+            _ => Some((SyncAction::Download {
+                remote_id: remote.id.clone(),
+                local_path: diary_dir.join(&name),
+                remote_name: name,
+            }, plan::REASON_NEW_REMOTE)),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Local,
+    Remote,
+}
+
+/// Winner of a conflict, decided without modification times since those come from each
+/// device's own clock. A side still at the last synced version holds no change to keep,
+/// and a remote version uploaded by this device is an earlier state of the local one.
+/// Otherwise the version already on the sync target wins, so every device agrees.
+fn pick_winner(
+    local_hash: &str,
+    remote_hash: &str,
+    base_hash: Option<&str>,
+    remote_device: Option<&str>,
+    this_device: Option<&str>,
+) -> Side {
+    if base_hash == Some(local_hash) {
+        return Side::Remote;
+    }
+    if base_hash == Some(remote_hash) {
+        return Side::Local;
+    }
+    match (remote_device, this_device) {
+        (Some(remote_device), Some(this_device)) if remote_device == this_device => Side::Local,
+        _ => Side::Remote,
+    }
 }
 
-async fn get_file_modified_time(path: &PathBuf) -> Result<String, String> {
+/// Conflict copy label for the version on the sync target
+fn remote_label(remote: &RemoteFile) -> String {
+    remote.device_name.as_deref()
+        .map(device::file_label)
+        .unwrap_or_else(|| "remote".to_string())
+}
+
+/// Whether a remote file differs from the version last synced. Checksums are preferred
+/// when both are known, since some backends also bump the modification time on metadata changes.
+fn remote_changed(file_meta: &FileMetadata, remote: &RemoteFile) -> bool {
+    match (&file_meta.remote_checksum, &remote.checksum) {
+        (Some(synced), Some(current)) => synced != current,
+        _ => file_meta.remote_modified != remote.modified_time,
+    }
+}
+
+fn file_name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+async fn get_file_modified_time(path: &Path) -> Result<String, String> {
     let metadata = fs::metadata(path).await
         .map_err(|e| format!("Failed to get file metadata: {}", e))?;
 
@@ -772,3 +2218,162 @@ async fn get_file_modified_time(path: &PathBuf) -> Result<String, String> {
     let datetime: chrono::DateTime<chrono::Utc> = modified.into();
     Ok(datetime.to_rfc3339())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::local_folder::LocalFolderStore;
+
+    const ENTRY: &str = "2024-01-31.txt";
+    const TEXT: &[u8] = b"2024-01-31\nA walk along the river.\n";
+    const EDITED: &[u8] = b"2024-01-31\nA walk along the river, then tea.\n";
+
+    /// `content` uploaded as the entry to a sync folder, as the store lists it
+    async fn remote_entry(content: &[u8]) -> (tempfile::TempDir, RemoteFile) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalFolderStore::new(dir.path().to_path_buf(), "BingoDiary".to_string());
+        let (_, entries_folder_id, _) = store.ensure_folder_structure().await.unwrap();
+        store.upload_content(content, ENTRY, &entries_folder_id, "text/plain", None).await.unwrap();
+        let remote = store.list_files(&entries_folder_id).await.unwrap().remove(0);
+        (dir, remote)
+    }
+
+    /// State recorded after syncing `content` with `remote`
+    fn synced(content: &[u8], remote: &RemoteFile) -> FileMetadata {
+        FileMetadata {
+            local_modified: String::new(),
+            remote_id: Some(remote.id.clone()),
+            remote_modified: remote.modified_time.clone(),
+            synced_hash: calculate_content_hash(content),
+            remote_checksum: None,
+        }
+    }
+
+    fn plan(
+        local: Option<&[u8]>,
+        remote: Option<&RemoteFile>,
+        synced: Option<&FileMetadata>,
+        encrypting: bool,
+    ) -> Option<(&'static str, &'static str)> {
+        let hash = local.map(calculate_content_hash);
+        let sides = EntrySides {
+            name: ENTRY,
+            local: local.zip(hash.as_deref())
+                .map(|(content, hash)| (Path::new(ENTRY), hash, content.len() as u64)),
+            remote,
+            synced,
+        };
+        plan_entry(&sides, Path::new("diary"), encrypting, false)
+            .map(|(action, reason)| (action.kind(), reason))
+    }
+
+    #[tokio::test]
+    async fn plans_the_side_that_changed() {
+        let (_dir, remote) = remote_entry(TEXT).await;
+        let meta = synced(TEXT, &remote);
+        assert_eq!(plan(Some(TEXT), Some(&remote), Some(&meta), false), None);
+        assert_eq!(
+            plan(Some(EDITED), Some(&remote), Some(&meta), false),
+            Some((plan::KIND_UPLOAD, plan::REASON_LOCAL_CHANGED)),
+        );
+
+        let earlier = FileMetadata { remote_modified: Some("2000-01-01T00:00:00+00:00".to_string()), ..meta.clone() };
+        assert_eq!(
+            plan(Some(TEXT), Some(&remote), Some(&earlier), false),
+            Some((plan::KIND_DOWNLOAD, plan::REASON_REMOTE_CHANGED)),
+        );
+        assert_eq!(
+            plan(Some(EDITED), Some(&remote), Some(&earlier), false),
+            Some((plan::KIND_MERGE, plan::REASON_CONFLICT)),
+        );
+        assert_eq!(
+            plan(Some(EDITED), Some(&remote), None, false),
+            Some((plan::KIND_MERGE, plan::REASON_FIRST_SYNC)),
+        );
+    }
+
+    #[tokio::test]
+    async fn plans_deletions_only_for_synced_entries() {
+        let (_dir, remote) = remote_entry(TEXT).await;
+        let meta = synced(TEXT, &remote);
+        assert_eq!(plan(Some(TEXT), None, Some(&meta), false), Some((plan::KIND_DELETE_LOCAL, plan::REASON_DELETED_REMOTELY)));
+        assert_eq!(plan(Some(EDITED), None, Some(&meta), false), Some((plan::KIND_UPLOAD, plan::REASON_NEW_LOCAL)));
+        assert_eq!(plan(None, Some(&remote), Some(&meta), false), Some((plan::KIND_DELETE_REMOTE, plan::REASON_DELETED_LOCALLY)));
+        assert_eq!(plan(None, Some(&remote), None, false), Some((plan::KIND_DOWNLOAD, plan::REASON_NEW_REMOTE)));
+        // A new entry without content isn't worth uploading
+        assert_eq!(plan(Some(&b"2024-01-31\n"[..]), None, None, false), None);
+    }
+
+    #[tokio::test]
+    async fn empty_entries_never_overwrite_content() {
+        let (_dir, remote) = remote_entry(TEXT).await;
+        let meta = synced(TEXT, &remote);
+        assert_eq!(
+            plan(Some(&b""[..]), Some(&remote), Some(&meta), false),
+            Some((plan::KIND_DOWNLOAD, plan::REASON_EMPTY_FILE)),
+        );
+
+        let (_dir, empty_remote) = remote_entry(b"").await;
+        assert_eq!(
+            plan(Some(TEXT), Some(&empty_remote), None, false),
+            Some((plan::KIND_UPLOAD, plan::REASON_EMPTY_FILE)),
+        );
+    }
+
+    #[tokio::test]
+    async fn plaintext_copies_are_reencrypted() {
+        let (_dir, remote) = remote_entry(TEXT).await;
+        assert_eq!(remote.encrypted, Some(false));
+        let meta = synced(TEXT, &remote);
+        assert_eq!(
+            plan(Some(TEXT), Some(&remote), Some(&meta), true),
+            Some((plan::KIND_UPLOAD, plan::REASON_NOT_ENCRYPTED)),
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_encryption_is_reuploaded_once() {
+        let (_dir, mut remote) = remote_entry(TEXT).await;
+        remote.encrypted = None;
+        let meta = synced(TEXT, &remote);
+        assert_eq!(
+            plan(Some(TEXT), Some(&remote), Some(&meta), true),
+            Some((plan::KIND_UPLOAD, plan::REASON_NOT_ENCRYPTED)),
+        );
+        assert_eq!(plan(Some(TEXT), Some(&remote), Some(&meta), false), None);
+        assert!(!needs_reencryption(&remote, true, true));
+    }
+
+    #[tokio::test]
+    async fn preview_leaves_local_state_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let shadow_dir = dir.path().join(shadow::SHADOW_DIR);
+        let diary_dir = dir.path().join("diary");
+        fs::create_dir_all(&shadow_dir).await.unwrap();
+        fs::create_dir_all(&diary_dir).await.unwrap();
+        fs::write(shadow_dir.join(calculate_content_hash(TEXT)), TEXT).await.unwrap();
+
+        // Metadata cleared because the remote folders changed refers to no merge base
+        let metadata = SyncMetadata::default();
+        prune_merge_bases(&shadow_dir, &metadata, true).unwrap();
+        local_images_dir(&diary_dir, true).await;
+        assert_eq!(std::fs::read_dir(&shadow_dir).unwrap().count(), 1);
+        assert_eq!(std::fs::read_dir(&diary_dir).unwrap().count(), 0);
+
+        prune_merge_bases(&shadow_dir, &metadata, false).unwrap();
+        local_images_dir(&diary_dir, false).await;
+        assert_eq!(std::fs::read_dir(&shadow_dir).unwrap().count(), 0);
+        assert!(diary_dir.join("images").is_dir());
+    }
+
+    #[test]
+    fn conflicts_pick_a_winner_every_device_agrees_on() {
+        // A side still at the last synced version has nothing to keep
+        assert_eq!(pick_winner("l", "r", Some("l"), None, None), Side::Remote);
+        assert_eq!(pick_winner("l", "r", Some("r"), None, None), Side::Local);
+        // This device's own upload is an earlier state of the local version
+        assert_eq!(pick_winner("l", "r", Some("b"), Some("me"), Some("me")), Side::Local);
+        assert_eq!(pick_winner("l", "r", Some("b"), Some("other"), Some("me")), Side::Remote);
+        assert_eq!(pick_winner("l", "r", None, None, Some("me")), Side::Remote);
+    }
+}
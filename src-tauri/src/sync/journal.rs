@@ -0,0 +1,171 @@
+use super::SyncReport;
+use crate::{config, storage};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::AppHandle;
+
+/// One line of JSON per sync run, oldest first
+const JOURNAL_FILE: &str = "sync_history.jsonl";
+
+// Older runs are dropped once the journal holds this many
+const MAX_RUNS: usize = 500;
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+// Values of `SyncRun::kind`
+pub const KIND_SYNC: &str = "sync";
+pub const KIND_FORCE_UPLOAD: &str = "force_upload";
+
+static JOURNAL_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncCounts {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub deleted_local: usize,
+    pub deleted_remote: usize,
+    pub conflicts_resolved: usize,
+    pub renamed: usize,
+    pub errors: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileAction {
+    /// Entry file name, `images/{name}` or `tags.json`
+    pub path: String,
+    /// "uploaded", "downloaded", "deleted_local", "deleted_remote", "conflict_resolved" or "renamed"
+    pub action: String,
+    /// Device that made a downloaded version, when the sync target records it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRun {
+    /// RFC3339 time the run started
+    pub started: String,
+    pub kind: String,
+    /// Sync target the run talked to, see `SyncSettings::backend`
+    pub backend: String,
+    /// Name of this device at the time of the run
+    #[serde(default)]
+    pub device: String,
+    pub duration_ms: u64,
+    pub counts: SyncCounts,
+    pub files: Vec<FileAction>,
+    /// Per-file errors, or the error that ended the run
+    pub errors: Vec<String>,
+}
+
+impl SyncRun {
+    pub fn new(
+        started: String,
+        kind: &str,
+        backend: &str,
+        device: &str,
+        duration_ms: u64,
+        result: &Result<SyncReport, String>,
+    ) -> Self {
+        let (counts, files, errors) = match result {
+            Ok(report) => {
+                let mut files = Vec::new();
+                let groups = [
+                    ("uploaded", &report.uploaded),
+                    ("downloaded", &report.downloaded),
+                    ("deleted_local", &report.deleted_local),
+                    ("deleted_remote", &report.deleted_remote),
+                    ("conflict_resolved", &report.conflicts_resolved),
+                    ("renamed", &report.renamed),
+                ];
+                for (action, paths) in groups {
+                    files.extend(paths.iter().map(|path| FileAction {
+                        path: path.clone(),
+                        action: action.to_string(),
+                        device: report.origins.get(path).cloned(),
+                    }));
+                }
+                let counts = SyncCounts {
+                    uploaded: report.uploaded.len(),
+                    downloaded: report.downloaded.len(),
+                    deleted_local: report.deleted_local.len(),
+                    deleted_remote: report.deleted_remote.len(),
+                    conflicts_resolved: report.conflicts_resolved.len(),
+                    renamed: report.renamed.len(),
+                    errors: report.errors.len(),
+                };
+                (counts, files, report.errors.clone())
+            }
+            Err(e) => (SyncCounts { errors: 1, ..SyncCounts::default() }, Vec::new(), vec![e.clone()]),
+        };
+
+        Self {
+            started,
+            kind: kind.to_string(),
+            backend: backend.to_string(),
+            device: device.to_string(),
+            duration_ms,
+            counts,
+            files,
+            errors,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncHistoryPage {
+    /// Newest first
+    pub runs: Vec<SyncRun>,
+    /// Runs in the whole journal
+    pub total: usize,
+}
+
+fn get_journal_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(config::get_state_dir(app)?.join(JOURNAL_FILE))
+}
+
+/// Runs in the journal, oldest first; lines that don't parse are skipped
+fn load(path: &Path) -> Vec<SyncRun> {
+    fs::read_to_string(path)
+        .map(|content| content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Append a finished run; a failure is logged, never passed on to the sync itself
+pub fn append(app: &AppHandle, run: SyncRun) {
+    let _lock = JOURNAL_LOCK.lock().unwrap();
+    let result = get_journal_path(app).and_then(|path| {
+        let mut runs = load(&path);
+        runs.push(run);
+        let excess = runs.len().saturating_sub(MAX_RUNS);
+        runs.drain(..excess);
+
+        let mut content = String::new();
+        for run in &runs {
+            content.push_str(&serde_json::to_string(run).map_err(|e| e.to_string())?);
+            content.push('\n');
+        }
+        storage::write_atomic(&path, content.as_bytes()).map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        println!("[Sync] Failed to record sync history: {}", e);
+    }
+}
+
+/// One page of the journal, newest run first
+pub fn page(app: &AppHandle, offset: usize, limit: Option<usize>) -> Result<SyncHistoryPage, String> {
+    let _lock = JOURNAL_LOCK.lock().unwrap();
+    let runs = load(&get_journal_path(app)?);
+    let total = runs.len();
+    let runs = runs.into_iter()
+        .rev()
+        .skip(offset)
+        .take(limit.unwrap_or(DEFAULT_PAGE_SIZE))
+        .collect();
+    Ok(SyncHistoryPage { runs, total })
+}
@@ -0,0 +1,200 @@
+use super::drive::{ENTRIES_FOLDER_NAME, IMAGES_FOLDER_NAME};
+use super::store::{RemoteFile, RemoteStore};
+use crate::{storage, vault};
+use async_trait::async_trait;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Sync target in a plain folder, e.g. one shared by Syncthing, a NAS mount or a USB drive.
+/// Ids are absolute paths, so pointing at another folder resets the sync state like a new Drive folder.
+pub struct LocalFolderStore {
+    root: PathBuf,
+    /// Name of the app folder inside `root`
+    app_folder: String,
+}
+
+impl LocalFolderStore {
+    pub fn new(root: PathBuf, app_folder: String) -> Self {
+        Self { root, app_folder }
+    }
+
+    /// Map an id back to a path, refusing anything outside the sync folder
+    fn resolve(&self, id: &str) -> Result<PathBuf, String> {
+        let path = PathBuf::from(id);
+        if !path.starts_with(&self.root) || path.components().any(|c| c == std::path::Component::ParentDir) {
+            return Err(format!("Path outside the sync folder: {}", id));
+        }
+        Ok(path)
+    }
+
+    async fn file_info(&self, path: &Path) -> Result<RemoteFile, String> {
+        let meta = fs::metadata(path).await
+            .map_err(|e| format!("Failed to read file info: {}", e))?;
+        let modified = meta.modified()
+            .map_err(|e| format!("Failed to get modified time: {}", e))?;
+        let modified: chrono::DateTime<chrono::Utc> = modified.into();
+
+        Ok(RemoteFile {
+            id: path.to_string_lossy().to_string(),
+            name: path.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            modified_time: Some(modified.to_rfc3339()),
+            size: Some(meta.len()),
+            encrypted: Some(starts_encrypted(path)),
+            checksum: None,
+            device_id: None,
+            device_name: None,
+        })
+    }
+}
+
+/// Check the encryption header without reading the whole file
+fn starts_encrypted(path: &Path) -> bool {
+    let mut head = [0u8; 8];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut head))
+        .map(|_| vault::is_encrypted(&head))
+        .unwrap_or(false)
+}
+
+#[async_trait]
+impl RemoteStore for LocalFolderStore {
+    async fn ensure_folder_structure(&self) -> Result<(String, String, String), String> {
+        // A missing root usually means an unmounted drive - never mistake it for an empty target
+        if !self.root.is_dir() {
+            return Err(format!("Sync folder not found: {}", self.root.display()));
+        }
+
+        let app_folder = self.root.join(&self.app_folder);
+        let entries_folder = app_folder.join(ENTRIES_FOLDER_NAME);
+        let images_folder = app_folder.join(IMAGES_FOLDER_NAME);
+        for folder in [&entries_folder, &images_folder] {
+            fs::create_dir_all(folder).await
+                .map_err(|e| format!("Failed to create folder: {}", e))?;
+        }
+
+        Ok((
+            app_folder.to_string_lossy().to_string(),
+            entries_folder.to_string_lossy().to_string(),
+            images_folder.to_string_lossy().to_string(),
+        ))
+    }
+
+    async fn find_folder_structure(&self) -> Result<(Option<String>, Option<String>, Option<String>), String> {
+        if !self.root.is_dir() {
+            return Err(format!("Sync folder not found: {}", self.root.display()));
+        }
+        let app_folder = self.root.join(&self.app_folder);
+        let existing = |folder: PathBuf| folder.is_dir().then(|| folder.to_string_lossy().to_string());
+        Ok((
+            existing(app_folder.clone()),
+            existing(app_folder.join(ENTRIES_FOLDER_NAME)),
+            existing(app_folder.join(IMAGES_FOLDER_NAME)),
+        ))
+    }
+
+    async fn list_files(&self, folder_id: &str) -> Result<Vec<RemoteFile>, String> {
+        let folder = self.resolve(folder_id)?;
+        let mut files = Vec::new();
+
+        let mut reader = fs::read_dir(&folder).await
+            .map_err(|e| format!("Failed to list files: {}", e))?;
+        while let Some(entry) = reader.next_entry().await
+            .map_err(|e| format!("Failed to list files: {}", e))?
+        {
+            let path = entry.path();
+            let hidden = path.file_name()
+                .map(|n| n.to_string_lossy().starts_with('.'))
+                .unwrap_or(true);
+            // Skip folders and temporary files of other sync tools (.syncthing.*, .~lock etc.)
+            if path.is_file() && !hidden {
+                files.push(self.file_info(&path).await?);
+            }
+        }
+
+        Ok(files)
+    }
+
+    async fn find_file(&self, name: &str, folder_id: &str) -> Result<Option<RemoteFile>, String> {
+        let path = self.resolve(folder_id)?.join(name);
+        if path.is_file() {
+            Ok(Some(self.file_info(&path).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn get_file_metadata(&self, file_id: &str) -> Result<RemoteFile, String> {
+        self.file_info(&self.resolve(file_id)?).await
+    }
+
+    async fn upload_content(
+        &self,
+        content: &[u8],
+        file_name: &str,
+        folder_id: &str,
+        _mime_type: &str,
+        existing_file_id: Option<&str>,
+    ) -> Result<RemoteFile, String> {
+        let path = match existing_file_id {
+            Some(file_id) => self.resolve(file_id)?,
+            None => self.resolve(folder_id)?.join(file_name),
+        };
+
+        // Other devices may pick the file up at any moment; never let them see half of it
+        storage::write_atomic(&path, content)
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+
+        self.file_info(&path).await
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, String> {
+        fs::read(self.resolve(file_id)?).await
+            .map_err(|e| format!("Failed to read file: {}", e))
+    }
+
+    async fn rename_file(&self, file_id: &str, new_name: &str) -> Result<RemoteFile, String> {
+        if Path::new(new_name).file_name() != Some(new_name.as_ref()) {
+            return Err(format!("Invalid file name: {}", new_name));
+        }
+        let path = self.resolve(file_id)?;
+        let new_path = path.with_file_name(new_name);
+
+        fs::rename(&path, &new_path).await
+            .map_err(|e| format!("Failed to rename file: {}", e))?;
+
+        self.file_info(&new_path).await
+    }
+
+    async fn delete_file(&self, file_id: &str) -> Result<(), String> {
+        let path = self.resolve(file_id)?;
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete file: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_only_paths_inside_the_root() {
+        let root = std::env::temp_dir().join("sync-target");
+        let store = LocalFolderStore::new(root.clone(), "BingoDiary".to_string());
+
+        let entry = root.join("BingoDiary").join("entries").join("2024-01-31.txt");
+        assert_eq!(store.resolve(&entry.to_string_lossy()).unwrap(), entry);
+        assert_eq!(store.resolve(&root.to_string_lossy()).unwrap(), root);
+
+        let escaped = root.join("BingoDiary").join("..").join("..").join("secrets.txt");
+        assert!(store.resolve(&escaped.to_string_lossy()).is_err());
+        let sibling = std::env::temp_dir().join("sync-target-2").join("entry.txt");
+        assert!(store.resolve(&sibling.to_string_lossy()).is_err());
+        assert!(store.resolve("entries/2024-01-31.txt").is_err());
+    }
+}
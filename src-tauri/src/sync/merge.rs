@@ -0,0 +1,190 @@
+/// Outcome of merging two edits of the same entry
+#[derive(Debug, PartialEq)]
+pub enum MergeResult {
+    Clean(String),
+    /// Both sides changed the same lines differently
+    Conflict,
+}
+
+// Above this many line comparisons the entry is treated as a conflict
+// rather than spending time and memory on the LCS table
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// Line-based three-way merge of `local` and `remote`, both edited from `base`
+pub fn merge3(base: &str, local: &str, remote: &str) -> MergeResult {
+    if local == remote || remote == base {
+        return MergeResult::Clean(local.to_string());
+    }
+    if local == base {
+        return MergeResult::Clean(remote.to_string());
+    }
+
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let local_lines: Vec<&str> = local.split_inclusive('\n').collect();
+    let remote_lines: Vec<&str> = remote.split_inclusive('\n').collect();
+
+    let (Some(to_local), Some(to_remote)) = (
+        match_lines(&base_lines, &local_lines),
+        match_lines(&base_lines, &remote_lines),
+    ) else {
+        return MergeResult::Conflict;
+    };
+
+    let mut merged: Vec<&str> = Vec::new();
+    let (mut o, mut a, mut b) = (0, 0, 0);
+
+    loop {
+        // Next base line kept unchanged on both sides
+        let stable = (o..base_lines.len())
+            .find_map(|i| Some((i, to_local[i]?, to_remote[i]?)));
+
+        let (end_o, end_a, end_b) = stable
+            .unwrap_or((base_lines.len(), local_lines.len(), remote_lines.len()));
+
+        if (end_o, end_a, end_b) != (o, a, b) {
+            let chunk = resolve_chunk(
+                &base_lines[o..end_o],
+                &local_lines[a..end_a],
+                &remote_lines[b..end_b],
+            );
+            match chunk {
+                Some(lines) => merged.extend(lines),
+                None => return MergeResult::Conflict,
+            }
+        }
+
+        match stable {
+            Some((i, j, k)) => {
+                merged.push(base_lines[i]);
+                o = i + 1;
+                a = j + 1;
+                b = k + 1;
+            }
+            None => break,
+        }
+    }
+
+    MergeResult::Clean(merged.concat())
+}
+
+/// Pick the side that changed a chunk, or fail if both changed it differently.
+/// Text added at the same place on both sides is a conflict too: their order is
+/// unknown, and one may be a rewrite of the other.
+fn resolve_chunk<'a>(base: &[&'a str], local: &[&'a str], remote: &[&'a str]) -> Option<Vec<&'a str>> {
+    if local == base {
+        Some(remote.to_vec())
+    } else if remote == base || local == remote {
+        Some(local.to_vec())
+    } else {
+        None
+    }
+}
+
+/// For each line of `a`, the index of the matching line of `b` in a longest common subsequence
+pub(crate) fn match_lines(a: &[&str], b: &[&str]) -> Option<Vec<Option<usize>>> {
+    let mut matches = vec![None; a.len()];
+
+    // Common prefix and suffix match trivially and keep the table small
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for (i, m) in matches.iter_mut().enumerate().take(prefix) {
+        *m = Some(i);
+    }
+    for k in 0..suffix {
+        matches[a.len() - 1 - k] = Some(b.len() - 1 - k);
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n == 0 || m == 0 {
+        return Some(matches);
+    }
+    if n * m > MAX_LCS_CELLS {
+        return None;
+    }
+
+    // lengths[i][j] = LCS length of a_mid[i..] and b_mid[j..]
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if a_mid[i] == b_mid[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a_mid[i] == b_mid[j] {
+            matches[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    Some(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_sided_edits_take_that_side() {
+        let base = "a\nb\nc\n";
+        assert_eq!(merge3(base, "a\nB\nc\n", base), MergeResult::Clean("a\nB\nc\n".to_string()));
+        assert_eq!(merge3(base, base, "a\nb\nC\n"), MergeResult::Clean("a\nb\nC\n".to_string()));
+        assert_eq!(merge3(base, "x\n", "x\n"), MergeResult::Clean("x\n".to_string()));
+    }
+
+    #[test]
+    fn edits_to_different_lines_are_combined() {
+        let base = "one\ntwo\nthree\nfour\n";
+        let local = "ONE\ntwo\nthree\nfour\n";
+        let remote = "one\ntwo\nthree\nFOUR\nfive\n";
+        assert_eq!(merge3(base, local, remote), MergeResult::Clean("ONE\ntwo\nthree\nFOUR\nfive\n".to_string()));
+    }
+
+    #[test]
+    fn different_edits_to_the_same_line_conflict() {
+        let base = "one\ntwo\nthree\n";
+        assert_eq!(merge3(base, "one\nlocal\nthree\n", "one\nremote\nthree\n"), MergeResult::Conflict);
+    }
+
+    #[test]
+    fn inserts_at_the_same_place_conflict() {
+        let base = "one\ntwo\n";
+        assert_eq!(merge3(base, "one\nlocal\ntwo\n", "one\nremote\ntwo\n"), MergeResult::Conflict);
+        // Appending on both sides is an insert at the same place too
+        assert_eq!(merge3(base, "one\ntwo\nlocal\n", "one\ntwo\nremote\n"), MergeResult::Conflict);
+    }
+
+    #[test]
+    fn resolve_chunk_keeps_the_changed_side() {
+        let base = ["b\n"];
+        assert_eq!(resolve_chunk(&base, &base, &["r\n"]), Some(vec!["r\n"]));
+        assert_eq!(resolve_chunk(&base, &["l\n"], &base), Some(vec!["l\n"]));
+        assert_eq!(resolve_chunk(&base, &["x\n"], &["x\n"]), Some(vec!["x\n"]));
+        assert_eq!(resolve_chunk(&base, &["l\n"], &["r\n"]), None);
+        assert_eq!(resolve_chunk(&[], &["l\n"], &["r\n"]), None);
+        // A deletion on one side wins over an unchanged other side
+        assert_eq!(resolve_chunk(&base, &[], &base), Some(vec![]));
+    }
+
+    #[test]
+    fn match_lines_follows_a_longest_common_subsequence() {
+        let a = ["a", "b", "c", "d"];
+        let b = ["a", "x", "c", "d", "e"];
+        assert_eq!(match_lines(&a, &b), Some(vec![Some(0), None, Some(2), Some(3)]));
+    }
+}
@@ -3,7 +3,13 @@ use sha2::{Sha256, Digest};
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
-use tauri::{AppHandle, Manager};
+use tauri::AppHandle;
+use crate::{config, storage};
+
+// Values of `SyncSettings::sync_scope`
+pub const SCOPE_ENTRIES: &str = "entries";
+pub const SCOPE_ENTRIES_TAGS: &str = "entries_tags";
+pub const SCOPE_ALL: &str = "all";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -11,6 +17,64 @@ pub struct SyncSettings {
     pub enabled: bool,
     pub sync_mode: String, // "auto" or "manual"
     pub sync_interval_minutes: u32,
+    /// Encrypt every file client-side before it is uploaded
+    #[serde(default)]
+    pub e2e_encryption: bool,
+    /// "google_drive", "local_folder" or "webdav"
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    /// Target folder of the local_folder backend
+    #[serde(default)]
+    pub local_folder_path: Option<String>,
+    /// Folder URL of the webdav backend, e.g. https://cloud.example.com/remote.php/dav/files/me/
+    #[serde(default)]
+    pub webdav_url: Option<String>,
+    #[serde(default)]
+    pub webdav_username: Option<String>,
+    /// Uploads and downloads run at the same time during a sync
+    #[serde(default = "default_concurrent_transfers")]
+    pub max_concurrent_transfers: u32,
+    /// What is synced: "entries", "entries_tags" or "all" (entries, tags and images)
+    #[serde(default = "default_sync_scope")]
+    pub sync_scope: String,
+    /// Only download entries dated within this many days; older ones are left on the sync target
+    #[serde(default)]
+    pub download_window_days: Option<u32>,
+}
+
+fn default_backend() -> String {
+    super::store::BACKEND_GOOGLE_DRIVE.to_string()
+}
+
+fn default_concurrent_transfers() -> u32 {
+    4
+}
+
+fn default_sync_scope() -> String {
+    SCOPE_ALL.to_string()
+}
+
+impl SyncSettings {
+    pub fn syncs_tags(&self) -> bool {
+        matches!(self.sync_scope.as_str(), SCOPE_ENTRIES_TAGS | SCOPE_ALL)
+    }
+
+    pub fn syncs_images(&self) -> bool {
+        self.sync_scope == SCOPE_ALL
+    }
+
+    /// Whether an entry missing locally should be downloaded. Entries named by
+    /// something other than their date are always in the window.
+    pub fn in_download_window(&self, file_name: &str) -> bool {
+        let Some(days) = self.download_window_days else {
+            return true;
+        };
+        let stem = file_name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(file_name);
+        match chrono::NaiveDate::parse_from_str(stem, "%Y-%m-%d") {
+            Ok(date) => date >= chrono::Local::now().date_naive() - chrono::Duration::days(days as i64),
+            Err(_) => true,
+        }
+    }
 }
 
 impl Default for SyncSettings {
@@ -19,6 +83,14 @@ impl Default for SyncSettings {
             enabled: false,
             sync_mode: "manual".to_string(),
             sync_interval_minutes: 15,
+            e2e_encryption: false,
+            backend: default_backend(),
+            local_folder_path: None,
+            webdav_url: None,
+            webdav_username: None,
+            max_concurrent_transfers: default_concurrent_transfers(),
+            sync_scope: default_sync_scope(),
+            download_window_days: None,
         }
     }
 }
@@ -30,6 +102,22 @@ pub struct FileMetadata {
     pub remote_id: Option<String>,
     pub remote_modified: Option<String>,
     pub synced_hash: String,
+    /// Backend checksum of the remote file when it was synced, see `RemoteFile::checksum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_checksum: Option<String>,
+}
+
+/// Resumable upload started by an earlier sync and not finished yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingUpload {
+    pub session_uri: String,
+    /// Hash of the local content being uploaded; a changed file starts a new upload
+    pub content_hash: String,
+    /// File whose content the upload replaces, None when it creates one
+    pub remote_id: Option<String>,
+    /// RFC3339 time the session was opened
+    pub started: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -41,22 +129,35 @@ pub struct SyncMetadata {
     pub entries_folder_id: Option<String>,
     pub images_folder_id: Option<String>,
     pub files: HashMap<String, FileMetadata>,
+    /// Position in the backend's change feed after the last complete sync
+    #[serde(default)]
+    pub changes_token: Option<String>,
+    /// RFC3339 time of the last sync that compared every file
+    #[serde(default)]
+    pub last_full_sync: Option<String>,
+    /// Unfinished resumable uploads by file key, continued by the next sync
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub uploads: HashMap<String, PendingUpload>,
+    /// Whether a full sync re-uploaded every file encrypted since encryption was turned on,
+    /// for backends that can't tell which files are
+    #[serde(default)]
+    pub reencrypted: bool,
 }
 
 impl SyncMetadata {
     fn get_path(app: &AppHandle) -> Result<PathBuf, std::io::Error> {
-        let app_data_dir = app.path().app_data_dir()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
-        fs::create_dir_all(&app_data_dir)?;
-        Ok(app_data_dir.join("sync_meta.json"))
+        let state_dir = config::get_state_dir(app)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e))?;
+        Ok(state_dir.join("sync_meta.json"))
     }
 
     pub fn load(app: &AppHandle) -> Result<Self, std::io::Error> {
         let path = Self::get_path(app)?;
         if path.exists() {
-            let content = fs::read_to_string(&path)?;
-            serde_json::from_str(&content)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+            storage::read_with_recovery(&path, |data| {
+                serde_json::from_slice(data).map_err(|e| e.to_string())
+            })
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
         } else {
             Ok(Self::default())
         }
@@ -66,7 +167,7 @@ impl SyncMetadata {
         let path = Self::get_path(app)?;
         let content = serde_json::to_string_pretty(self)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
-        fs::write(&path, content)
+        storage::write_with_backup(&path, content.as_bytes())
     }
 
     pub fn update_file_metadata(
@@ -84,6 +185,7 @@ impl SyncMetadata {
                 remote_id,
                 remote_modified,
                 synced_hash: content_hash.to_string(),
+                remote_checksum: None,
             },
         );
     }
@@ -92,6 +194,19 @@ impl SyncMetadata {
         self.files.remove(relative_path);
     }
 
+    pub fn set_remote_checksum(&mut self, relative_path: &str, checksum: Option<String>) {
+        if let Some(file_meta) = self.files.get_mut(relative_path) {
+            file_meta.remote_checksum = checksum;
+        }
+    }
+
+    /// Move the sync state of a file renamed without changing its content
+    pub fn rename_file_metadata(&mut self, from: &str, to: &str) {
+        if let Some(file_meta) = self.files.remove(from) {
+            self.files.insert(to.to_string(), file_meta);
+        }
+    }
+
     pub fn get_file_metadata(&self, relative_path: &str) -> Option<&FileMetadata> {
         self.files.get(relative_path)
     }
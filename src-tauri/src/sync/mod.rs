@@ -1,15 +1,33 @@
 pub mod auth;
+pub mod device;
+pub mod dirty;
+pub mod disconnect;
 pub mod drive;
+pub mod encryption;
 pub mod engine;
+pub mod journal;
+pub mod local_folder;
+pub mod merge;
 pub mod metadata;
+pub mod plan;
+pub mod scheduler;
+pub mod shadow;
+pub mod store;
+pub mod webdav;
 
 use serde::{Deserialize, Serialize};
-use tauri::AppHandle;
+use std::collections::HashMap;
+use tauri::{AppHandle, Manager};
 
 pub use auth::GoogleAuth;
+pub use device::{Device, SyncedDevice};
+pub use disconnect::{DisconnectReport, RemoteDeletion};
 pub use drive::DriveClient;
 pub use engine::SyncEngine;
+pub use journal::SyncHistoryPage;
 pub use metadata::{SyncMetadata, SyncSettings};
+pub use plan::PlannedAction;
+pub use store::RemoteStore;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -40,9 +58,15 @@ pub struct SyncReport {
     pub downloaded: Vec<String>,
     pub deleted_local: Vec<String>,
     pub deleted_remote: Vec<String>,
+    /// Entries merged from edits on both sides, and conflict copies of edits that could not be merged
     pub conflicts_resolved: Vec<String>,
+    /// Entries converted to another format on one side and renamed on the other, as `old -> new`
+    pub renamed: Vec<String>,
     pub errors: Vec<String>,
     pub duration_ms: u64,
+    /// Device that made each downloaded version, by path, where the sync target records it
+    #[serde(default)]
+    pub origins: HashMap<String, String>,
 }
 
 impl Default for SyncReport {
@@ -53,22 +77,55 @@ impl Default for SyncReport {
             deleted_local: Vec::new(),
             deleted_remote: Vec::new(),
             conflicts_resolved: Vec::new(),
+            renamed: Vec::new(),
             errors: Vec::new(),
             duration_ms: 0,
+            origins: HashMap::new(),
         }
     }
 }
 
+impl SyncReport {
+    /// Append the results of a sync stage to this report
+    pub fn merge(&mut self, mut other: SyncReport) {
+        self.uploaded.append(&mut other.uploaded);
+        self.downloaded.append(&mut other.downloaded);
+        self.deleted_local.append(&mut other.deleted_local);
+        self.deleted_remote.append(&mut other.deleted_remote);
+        self.conflicts_resolved.append(&mut other.conflicts_resolved);
+        self.renamed.append(&mut other.renamed);
+        self.errors.append(&mut other.errors);
+        self.origins.extend(other.origins);
+    }
+}
+
 pub async fn get_status(app: &AppHandle) -> Result<SyncStatus, String> {
     let auth = GoogleAuth::load(app).map_err(|e| e.to_string())?;
     let metadata = SyncMetadata::load(app).unwrap_or_default();
+    let settings = &metadata.settings;
+
+    let (connected, account_email) = match settings.backend.as_str() {
+        store::BACKEND_LOCAL_FOLDER => (
+            settings.local_folder_path.as_ref()
+                .map(|path| std::path::Path::new(path).is_dir())
+                .unwrap_or(false),
+            None,
+        ),
+        store::BACKEND_WEBDAV => (
+            settings.webdav_url.is_some() && webdav::load_password(app).is_some(),
+            settings.webdav_username.clone(),
+        ),
+        _ => (auth.is_authenticated(), auth.get_email()),
+    };
+
+    let scheduler = app.try_state::<scheduler::SyncScheduler>();
 
     Ok(SyncStatus {
-        connected: auth.is_authenticated(),
-        syncing: false,
+        connected,
+        syncing: scheduler.as_ref().map(|s| s.is_running()).unwrap_or(false),
         last_sync: metadata.last_sync_time,
-        error: None,
-        account_email: auth.get_email(),
+        error: scheduler.as_ref().and_then(|s| s.last_error()),
+        account_email,
     })
 }
 
@@ -78,8 +135,77 @@ pub async fn get_settings(app: &AppHandle) -> Result<SyncSettings, String> {
         .map_err(|e| e.to_string())
 }
 
+/// Unlock (or create) the end-to-end encryption key and turn encryption on
+pub async fn enable_encryption(app: &AppHandle, passphrase: &str) -> Result<(), String> {
+    let scheduler = app.state::<scheduler::SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let mut metadata = SyncMetadata::load(app).unwrap_or_default();
+    let store = store::connect(app, &metadata.settings, false).await?;
+    let (app_folder_id, _, _) = store.ensure_folder_structure().await?;
+    encryption::setup(app, store.as_ref(), &app_folder_id, passphrase).await?;
+
+    metadata.settings.e2e_encryption = true;
+    metadata.reencrypted = false;
+    metadata.save(app).map_err(|e| e.to_string())
+}
+
+/// Turn end-to-end encryption off here and on the sync target
+pub async fn disable_encryption(app: &AppHandle) -> Result<(), String> {
+    let scheduler = app.state::<scheduler::SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let mut metadata = SyncMetadata::load(app).unwrap_or_default();
+    let sync_key = encryption::load_key(app);
+    let store = store::connect(app, &metadata.settings, false).await?;
+    let (app_folder_id, entries_folder_id, images_folder_id) = store.ensure_folder_structure().await?;
+    let folder_ids = [app_folder_id.as_str(), entries_folder_id.as_str(), images_folder_id.as_str()];
+    let decrypted = encryption::disable(store.as_ref(), &app_folder_id, &folder_ids, sync_key.as_ref()).await?;
+    println!("[Sync] Turned off encryption, decrypted {} synced files", decrypted);
+
+    metadata.settings.e2e_encryption = false;
+    metadata.save(app).map_err(|e| e.to_string())?;
+    encryption::clear_key(app)
+}
+
 pub async fn save_settings(app: &AppHandle, settings: SyncSettings) -> Result<(), String> {
+    let scopes = [metadata::SCOPE_ENTRIES, metadata::SCOPE_ENTRIES_TAGS, metadata::SCOPE_ALL];
+    if !scopes.contains(&settings.sync_scope.as_str()) {
+        return Err(format!("Unknown sync scope: {}", settings.sync_scope));
+    }
+
+    // A running sync writes its copy of the settings back when it finishes
+    let scheduler = app.state::<scheduler::SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
     let mut metadata = SyncMetadata::load(app).unwrap_or_default();
+    // Files coming into scope were skipped by earlier syncs; only a full comparison finds them
+    let scope_changed = metadata.settings.sync_scope != settings.sync_scope
+        || metadata.settings.download_window_days != settings.download_window_days;
+    if scope_changed {
+        metadata.changes_token = None;
+    }
     metadata.settings = settings;
     metadata.save(app).map_err(|e| e.to_string())
 }
+
+/// Download an image left out by the sync scope, for an entry that shows it
+pub async fn fetch_image(app: &AppHandle, name: &str) -> Result<Vec<u8>, String> {
+    let settings = get_settings(app).await?;
+    if !settings.enabled || settings.syncs_images() {
+        return Err(format!("Image not found: {}", name));
+    }
+
+    let diary_dir = crate::config::get_diary_dir(app).await?;
+    let engine = SyncEngine::new(app.clone(), diary_dir);
+    let store = engine.connect().await?;
+    engine.fetch_image(store.as_ref(), name).await
+}
+
+/// Devices that have synced to the current sync target
+pub async fn list_devices(app: &AppHandle) -> Result<Vec<SyncedDevice>, String> {
+    let diary_dir = crate::config::get_diary_dir(app).await?;
+    let engine = SyncEngine::new(app.clone(), diary_dir);
+    let store = engine.connect().await?;
+    engine.list_devices(store.as_ref()).await
+}
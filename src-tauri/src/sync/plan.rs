@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// Values of `PlannedAction::kind`
+pub const KIND_UPLOAD: &str = "upload";
+pub const KIND_DOWNLOAD: &str = "download";
+pub const KIND_DELETE_LOCAL: &str = "delete_local";
+pub const KIND_DELETE_REMOTE: &str = "delete_remote";
+/// Changed on both sides: merged, or the losing version kept as a conflict copy
+pub const KIND_MERGE: &str = "merge";
+/// Converted to another format on one side, renamed on the other
+pub const KIND_RENAME: &str = "rename";
+
+// Values of `PlannedAction::reason`
+pub const REASON_LOCAL_CHANGED: &str = "local_changed";
+pub const REASON_REMOTE_CHANGED: &str = "remote_changed";
+pub const REASON_CONFLICT: &str = "conflict";
+/// A nearly empty file never replaces one with content, whatever changed last
+pub const REASON_EMPTY_FILE: &str = "empty_file";
+pub const REASON_NEW_LOCAL: &str = "new_local";
+pub const REASON_NEW_REMOTE: &str = "new_remote";
+/// Present on both sides but never synced, so there is no common version to compare with
+pub const REASON_FIRST_SYNC: &str = "first_sync";
+pub const REASON_DELETED_LOCALLY: &str = "deleted_locally";
+pub const REASON_DELETED_REMOTELY: &str = "deleted_remotely";
+/// Stored in plaintext before end-to-end encryption was turned on
+pub const REASON_NOT_ENCRYPTED: &str = "not_encrypted";
+pub const REASON_FORMAT_CHANGED: &str = "format_changed";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedAction {
+    /// Path of the file in the sync folder (`entries/{name}`, `images/{name}` or `tags.json`);
+    /// pass it back to `start_sync` to exclude the action
+    pub id: String,
+    pub kind: String,
+    pub reason: String,
+}
+
+/// Collects the actions a sync decides on, and decides which of them run
+#[derive(Default)]
+pub struct SyncPlan {
+    preview: bool,
+    excluded: HashSet<String>,
+    /// Actions of the preview the user confirmed; None when there was no preview
+    previewed: Option<HashSet<PlannedAction>>,
+    actions: Vec<PlannedAction>,
+    skipped: usize,
+    /// Actions skipped because the preview didn't list them
+    unpreviewed: usize,
+}
+
+impl SyncPlan {
+    /// Run every action except those with an id in `excluded`
+    pub fn execute(excluded: HashSet<String>) -> Self {
+        Self { excluded, ..Self::default() }
+    }
+
+    /// Run only the actions listed by a previous `preview`, except those in `excluded`.
+    /// Anything that changed since is left for a new preview.
+    pub fn execute_previewed(previewed: Vec<PlannedAction>, excluded: HashSet<String>) -> Self {
+        Self {
+            excluded,
+            previewed: Some(previewed.into_iter().collect()),
+            ..Self::default()
+        }
+    }
+
+    /// Only list the actions, running none of them
+    pub fn preview() -> Self {
+        Self { preview: true, ..Self::default() }
+    }
+
+    pub fn is_preview(&self) -> bool {
+        self.preview
+    }
+
+    /// Record a planned action; returns whether to carry it out
+    pub fn admit(&mut self, id: &str, kind: &str, reason: &str) -> bool {
+        let action = PlannedAction {
+            id: id.to_string(),
+            kind: kind.to_string(),
+            reason: reason.to_string(),
+        };
+        let previewed = match &self.previewed {
+            Some(previewed) => previewed.contains(&action),
+            None => true,
+        };
+        self.actions.push(action);
+        if !previewed && !self.preview {
+            println!("[Sync] Skipping {} {}: not in the preview", kind, id);
+            self.unpreviewed += 1;
+        }
+        let run = !self.preview && previewed && !self.excluded.contains(id);
+        if !run {
+            self.skipped += 1;
+        }
+        run
+    }
+
+    /// Error to report when the sync found actions its preview didn't list
+    pub fn changed_error(&self) -> Option<String> {
+        (self.unpreviewed > 0).then(|| format!(
+            "Files changed since the preview; {} action(s) were skipped. Preview the sync again.",
+            self.unpreviewed
+        ))
+    }
+
+    /// Whether any planned action was left undone
+    pub fn skipped_any(&self) -> bool {
+        self.skipped > 0
+    }
+
+    pub fn into_actions(self) -> Vec<PlannedAction> {
+        self.actions
+    }
+}
@@ -0,0 +1,203 @@
+use super::device;
+use super::journal::{self, SyncRun};
+use super::plan::{PlannedAction, SyncPlan};
+use super::{SyncEngine, SyncMetadata, SyncReport};
+use crate::config;
+use crate::vault::Vault;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+// Quiet period after the last save before an automatic sync starts
+const SAVE_DEBOUNCE_SECS: u64 = 10;
+
+// How often settings are re-read while waiting for the next interval
+const POLL_SECS: u64 = 60;
+
+enum Job {
+    Sync(SyncPlan),
+    ForceUpload,
+}
+
+/// Shared sync state: only one sync runs at a time, whether started by the user or the scheduler
+#[derive(Default)]
+pub struct SyncScheduler {
+    running: AtomicBool,
+    last_error: Mutex<Option<String>>,
+    saved: Notify,
+    /// An entry was saved while a sync was running and may have missed it
+    saved_while_running: AtomicBool,
+}
+
+/// Clears the running flag when a sync finishes, even on early return,
+/// and schedules another sync for saves made in the meantime
+pub(crate) struct RunningGuard<'a>(&'a SyncScheduler);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+        if self.0.saved_while_running.swap(false, Ordering::SeqCst) {
+            self.0.saved.notify_one();
+        }
+    }
+}
+
+impl SyncScheduler {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Mark a sync, or anything that must not overlap one, as running
+    pub(crate) fn begin(&self) -> Result<RunningGuard<'_>, String> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return Err("Sync already in progress".to_string());
+        }
+        Ok(RunningGuard(self))
+    }
+
+    fn record(&self, result: &Result<SyncReport, String>) {
+        let error = match result {
+            Ok(report) => report.errors.first().cloned(),
+            Err(e) => Some(e.clone()),
+        };
+        *self.last_error.lock().unwrap() = error;
+    }
+}
+
+/// Schedule an automatic sync shortly after an entry was saved
+pub fn notify_saved(app: &AppHandle) {
+    if let Some(scheduler) = app.try_state::<SyncScheduler>() {
+        // The running sync may have read the entry already; the automatic sync it
+        // would trigger now can't start until it is done
+        if scheduler.is_running() {
+            scheduler.saved_while_running.store(true, Ordering::SeqCst);
+        }
+        scheduler.saved.notify_one();
+    }
+}
+
+/// Run a sync unless one is already in progress, skipping the planned actions
+/// with an id in `excluded`. Given the actions of a `preview`, runs only those.
+pub async fn sync_now(
+    app: &AppHandle,
+    previewed: Option<Vec<PlannedAction>>,
+    excluded: HashSet<String>,
+) -> Result<SyncReport, String> {
+    let plan = match previewed {
+        Some(previewed) => SyncPlan::execute_previewed(previewed, excluded),
+        None => SyncPlan::execute(excluded),
+    };
+    run_exclusive(app, Job::Sync(plan)).await
+}
+
+/// Run a force upload unless a sync is already in progress
+pub async fn force_upload_now(app: &AppHandle) -> Result<SyncReport, String> {
+    run_exclusive(app, Job::ForceUpload).await
+}
+
+/// List what a sync would do right now, without changing anything
+pub async fn preview(app: &AppHandle) -> Result<Vec<PlannedAction>, String> {
+    let scheduler = app.state::<SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let diary_dir = config::get_diary_dir(app).await?;
+    let engine = SyncEngine::new(app.clone(), diary_dir);
+    let store = engine.connect().await?;
+    let mut plan = SyncPlan::preview();
+    let report = engine.sync(store.as_ref(), &mut plan).await?;
+
+    // A partial plan would look like there is less to do than there is
+    if let Some(error) = report.errors.first() {
+        return Err(error.clone());
+    }
+    Ok(plan.into_actions())
+}
+
+async fn run_exclusive(app: &AppHandle, job: Job) -> Result<SyncReport, String> {
+    let scheduler = app.state::<SyncScheduler>();
+    let _guard = scheduler.begin()?;
+
+    let started = chrono::Utc::now().to_rfc3339();
+    let start_time = Instant::now();
+    let kind = match job {
+        Job::Sync(_) => journal::KIND_SYNC,
+        Job::ForceUpload => journal::KIND_FORCE_UPLOAD,
+    };
+
+    let result = async {
+        let diary_dir = config::get_diary_dir(app).await?;
+        let engine = SyncEngine::new(app.clone(), diary_dir);
+        let store = engine.connect().await?;
+        match job {
+            Job::Sync(mut plan) => engine.sync(store.as_ref(), &mut plan).await,
+            Job::ForceUpload => engine.force_upload_sync(store.as_ref()).await,
+        }
+    }
+    .await;
+
+    scheduler.record(&result);
+    let backend = SyncMetadata::load(app).map(|m| m.settings.backend).unwrap_or_default();
+    let device_name = device::load(app).map(|d| d.name).unwrap_or_default();
+    let duration_ms = start_time.elapsed().as_millis() as u64;
+    journal::append(app, SyncRun::new(started, kind, &backend, &device_name, duration_ms, &result));
+    result
+}
+
+/// Background loop started with the app: syncs every `sync_interval_minutes`
+/// and after saves, while auto sync is enabled
+pub async fn run(app: AppHandle) {
+    let scheduler = app.state::<SyncScheduler>();
+    let mut last_sync = Instant::now();
+
+    loop {
+        let settings = SyncMetadata::load(&app).map(|m| m.settings).unwrap_or_default();
+        let interval = Duration::from_secs(u64::from(settings.sync_interval_minutes.max(1)) * 60);
+        let wait = interval
+            .saturating_sub(last_sync.elapsed())
+            .min(Duration::from_secs(POLL_SECS));
+
+        let due = tokio::select! {
+            _ = tokio::time::sleep(wait) => last_sync.elapsed() >= interval,
+            _ = scheduler.saved.notified() => {
+                // Wait until saving pauses so typing doesn't trigger a sync per keystroke
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(SAVE_DEBOUNCE_SECS)) => break,
+                        _ = scheduler.saved.notified() => continue,
+                    }
+                }
+                true
+            }
+        };
+
+        if !due || !auto_sync_enabled(&app).await {
+            continue;
+        }
+
+        last_sync = Instant::now();
+        match sync_now(&app, None, HashSet::new()).await {
+            Ok(report) => println!("[Sync] Auto sync finished with {} errors", report.errors.len()),
+            Err(e) => println!("[Sync] Auto sync failed: {}", e),
+        }
+    }
+}
+
+async fn auto_sync_enabled(app: &AppHandle) -> bool {
+    let settings = SyncMetadata::load(app).map(|m| m.settings).unwrap_or_default();
+    if !settings.enabled || settings.sync_mode != "auto" {
+        return false;
+    }
+
+    // A locked diary can't be synced; wait for the user to unlock it instead of reporting errors
+    match config::get_diary_dir(app).await {
+        Ok(diary_dir) => !Vault::open(app, &diary_dir).is_locked(),
+        Err(_) => false,
+    }
+}
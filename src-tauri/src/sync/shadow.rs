@@ -0,0 +1,59 @@
+use crate::config;
+use crate::vault::Vault;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+/// Last synced content of each entry, named by its hash in `SyncMetadata::files`.
+/// Used as the common base when both sides changed an entry.
+pub const SHADOW_DIR: &str = "sync_shadow";
+
+/// Where synced versions are kept, without creating the folder
+pub fn shadow_dir_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(config::get_state_dir(app)?.join(SHADOW_DIR))
+}
+
+fn get_shadow_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = shadow_dir_path(app)?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+/// Keep a synced version, encrypted like the diary itself
+pub fn store(app: &AppHandle, vault: &Vault, hash: &str, content: &[u8]) -> Result<(), String> {
+    let path = get_shadow_dir(app)?.join(hash);
+    if path.exists() {
+        return Ok(());
+    }
+    vault.write(&path, content)
+}
+
+pub fn load(app: &AppHandle, vault: &Vault, hash: &str) -> Option<Vec<u8>> {
+    let path = get_shadow_dir(app).ok()?.join(hash);
+    vault.read(&path).ok()
+}
+
+/// Remove versions in `dir` no longer referenced by the sync metadata
+pub fn prune(dir: &Path, keep: &HashSet<&str>) -> Result<(), String> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let referenced = path.file_name()
+            .and_then(|n| n.to_str())
+            .map(|name| keep.contains(name))
+            .unwrap_or(false);
+        if !referenced {
+            fs::remove_file(&path).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop every stored version, e.g. after the diary encryption changed
+pub fn clear(app: &AppHandle) -> Result<(), String> {
+    let dir = get_shadow_dir(app)?;
+    fs::remove_dir_all(&dir).map_err(|e| e.to_string())
+}
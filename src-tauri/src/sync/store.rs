@@ -0,0 +1,311 @@
+use super::auth::GoogleAuth;
+use super::device::{self, DEVICE_ID_PROPERTY, DEVICE_NAME_PROPERTY};
+use super::drive::DriveClient;
+use super::encryption::ENCRYPTED_PROPERTY;
+use super::local_folder::LocalFolderStore;
+use super::metadata::SyncSettings;
+use super::webdav::{self, WebDavStore};
+use crate::config;
+use crate::vault::Vault;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+// Values of `SyncSettings::backend`
+pub const BACKEND_GOOGLE_DRIVE: &str = "google_drive";
+pub const BACKEND_LOCAL_FOLDER: &str = "local_folder";
+pub const BACKEND_WEBDAV: &str = "webdav";
+
+/// A file on the sync target, as seen by the sync engine
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    /// Backend-specific identifier, passed back to the store for later operations
+    pub id: String,
+    pub name: String,
+    /// RFC3339 time of the last change
+    pub modified_time: Option<String>,
+    pub size: Option<u64>,
+    /// Whether the content is end-to-end encrypted, if the backend can tell without downloading
+    pub encrypted: Option<bool>,
+    /// Checksum of the stored bytes, if the backend reports one (MD5 on Google Drive)
+    pub checksum: Option<String>,
+    /// Id of the device that uploaded the content, if the backend keeps file properties
+    pub device_id: Option<String>,
+    /// Name of the device that uploaded the content, if the backend keeps file properties
+    pub device_name: Option<String>,
+}
+
+/// Remote changes since a token from `RemoteStore::start_change_token`
+#[derive(Debug, Default)]
+pub struct RemoteChanges {
+    /// Files created or modified, with the ids of the folders they are in
+    pub changed: Vec<(RemoteFile, Vec<String>)>,
+    /// Ids of files deleted or moved to the trash
+    pub removed: Vec<String>,
+    /// Token to continue from next time
+    pub next_token: String,
+}
+
+/// Bytes of a resumable upload, read a chunk at a time
+pub enum UploadSource<'a> {
+    File(&'a Path),
+    /// Plaintext of a diary file, decrypted a chunk at a time if the vault sealed it
+    Vault(&'a Path, &'a Vault),
+}
+
+impl UploadSource<'_> {
+    pub async fn size(&self) -> Result<u64, String> {
+        match self {
+            UploadSource::File(path) => tokio::fs::metadata(path).await
+                .map(|m| m.len())
+                .map_err(|e| format!("Failed to read local file: {}", e)),
+            UploadSource::Vault(path, vault) => vault.plaintext_len(path),
+        }
+    }
+
+    /// Up to `len` bytes starting at `offset`; fewer at the end
+    pub async fn read_chunk(&self, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        match self {
+            UploadSource::File(path) => {
+                let mut file = tokio::fs::File::open(path).await
+                    .map_err(|e| format!("Failed to read local file: {}", e))?;
+                file.seek(SeekFrom::Start(offset)).await
+                    .map_err(|e| format!("Failed to read local file: {}", e))?;
+                let mut chunk = Vec::with_capacity(len);
+                file.take(len as u64).read_to_end(&mut chunk).await
+                    .map_err(|e| format!("Failed to read local file: {}", e))?;
+                Ok(chunk)
+            }
+            UploadSource::Vault(path, vault) => {
+                let (path, vault) = (path.to_path_buf(), (*vault).clone());
+                tokio::task::spawn_blocking(move || vault.read_range(&path, offset, len))
+                    .await
+                    .map_err(|e| format!("Failed to read local file: {}", e))?
+            }
+        }
+    }
+}
+
+/// Storage the sync engine mirrors the diary to.
+/// Folders and files are addressed by ids returned from the store itself.
+#[async_trait]
+pub trait RemoteStore: Send + Sync {
+    /// Create the app folder with its entries and images subfolders if needed,
+    /// returning (app_folder_id, entries_folder_id, images_folder_id)
+    async fn ensure_folder_structure(&self) -> Result<(String, String, String), String>;
+
+    /// Ids of the app folder and its entries and images subfolders, None for those
+    /// that don't exist yet; creates nothing
+    async fn find_folder_structure(&self) -> Result<(Option<String>, Option<String>, Option<String>), String>;
+
+    async fn list_files(&self, folder_id: &str) -> Result<Vec<RemoteFile>, String>;
+
+    async fn find_file(&self, name: &str, folder_id: &str) -> Result<Option<RemoteFile>, String>;
+
+    async fn get_file_metadata(&self, file_id: &str) -> Result<RemoteFile, String>;
+
+    /// Create a file in the folder, or replace the content of `existing_file_id`
+    async fn upload_content(
+        &self,
+        content: &[u8],
+        file_name: &str,
+        folder_id: &str,
+        mime_type: &str,
+        existing_file_id: Option<&str>,
+    ) -> Result<RemoteFile, String>;
+
+    async fn upload_file(
+        &self,
+        local_path: &Path,
+        file_name: &str,
+        folder_id: &str,
+        existing_file_id: Option<&str>,
+    ) -> Result<RemoteFile, String> {
+        let content = tokio::fs::read(local_path).await
+            .map_err(|e| format!("Failed to read local file: {}", e))?;
+        let mime_type = mime_guess::from_path(local_path)
+            .first_or_octet_stream()
+            .to_string();
+        self.upload_content(&content, file_name, folder_id, &mime_type, existing_file_id).await
+    }
+
+    /// Open a session to upload `size` bytes in chunks across several requests (and runs),
+    /// returning its URI, or None if the backend only takes whole files
+    async fn start_resumable_upload(
+        &self,
+        _file_name: &str,
+        _folder_id: &str,
+        _mime_type: &str,
+        _size: u64,
+        _existing_file_id: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    /// Send the part of `source` the session hasn't received yet. Returns None if the
+    /// session expired and the upload has to start over.
+    async fn resume_upload(&self, _session_uri: &str, _source: &UploadSource<'_>) -> Result<Option<RemoteFile>, String> {
+        Err("This sync target can't resume uploads".to_string())
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, String>;
+
+    /// Give a file a new name in the same folder, keeping its content.
+    /// The returned file may have a new id on backends that address files by path.
+    async fn rename_file(&self, file_id: &str, new_name: &str) -> Result<RemoteFile, String>;
+
+    /// Token for the current state of the remote, or None if the backend has no change feed
+    async fn start_change_token(&self) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    /// Changes since `token`; only called on backends that returned a start token
+    async fn list_changes(&self, _token: &str) -> Result<RemoteChanges, String> {
+        Err("This sync target can't list changes".to_string())
+    }
+
+    /// Delete a file; deleting a file that is already gone succeeds
+    async fn delete_file(&self, file_id: &str) -> Result<(), String>;
+}
+
+/// Id given to folders a preview found missing; they list as empty
+const MISSING_FOLDER_PREFIX: &str = "missing:";
+
+/// Store for sync previews: reads go to the sync target, while folders that don't
+/// exist yet read as empty and every write fails, so a preview can't change anything
+pub struct ReadOnlyStore<'a> {
+    inner: &'a dyn RemoteStore,
+}
+
+impl<'a> ReadOnlyStore<'a> {
+    pub fn new(inner: &'a dyn RemoteStore) -> Self {
+        Self { inner }
+    }
+
+    fn is_missing(folder_id: &str) -> bool {
+        folder_id.starts_with(MISSING_FOLDER_PREFIX)
+    }
+
+    fn read_only<T>() -> Result<T, String> {
+        Err("A sync preview can't change the sync target".to_string())
+    }
+}
+
+#[async_trait]
+impl RemoteStore for ReadOnlyStore<'_> {
+    async fn ensure_folder_structure(&self) -> Result<(String, String, String), String> {
+        let (app, entries, images) = self.inner.find_folder_structure().await?;
+        let or_missing = |id: Option<String>, name: &str| id.unwrap_or_else(|| format!("{}{}", MISSING_FOLDER_PREFIX, name));
+        Ok((or_missing(app, "app"), or_missing(entries, "entries"), or_missing(images, "images")))
+    }
+
+    async fn find_folder_structure(&self) -> Result<(Option<String>, Option<String>, Option<String>), String> {
+        self.inner.find_folder_structure().await
+    }
+
+    async fn list_files(&self, folder_id: &str) -> Result<Vec<RemoteFile>, String> {
+        if Self::is_missing(folder_id) {
+            return Ok(Vec::new());
+        }
+        self.inner.list_files(folder_id).await
+    }
+
+    async fn find_file(&self, name: &str, folder_id: &str) -> Result<Option<RemoteFile>, String> {
+        if Self::is_missing(folder_id) {
+            return Ok(None);
+        }
+        self.inner.find_file(name, folder_id).await
+    }
+
+    async fn get_file_metadata(&self, file_id: &str) -> Result<RemoteFile, String> {
+        self.inner.get_file_metadata(file_id).await
+    }
+
+    async fn upload_content(
+        &self,
+        _content: &[u8],
+        _file_name: &str,
+        _folder_id: &str,
+        _mime_type: &str,
+        _existing_file_id: Option<&str>,
+    ) -> Result<RemoteFile, String> {
+        Self::read_only()
+    }
+
+    async fn start_resumable_upload(
+        &self,
+        _file_name: &str,
+        _folder_id: &str,
+        _mime_type: &str,
+        _size: u64,
+        _existing_file_id: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        Self::read_only()
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, String> {
+        self.inner.download_file(file_id).await
+    }
+
+    async fn rename_file(&self, _file_id: &str, _new_name: &str) -> Result<RemoteFile, String> {
+        Self::read_only()
+    }
+
+    async fn start_change_token(&self) -> Result<Option<String>, String> {
+        self.inner.start_change_token().await
+    }
+
+    async fn list_changes(&self, token: &str) -> Result<RemoteChanges, String> {
+        self.inner.list_changes(token).await
+    }
+
+    async fn delete_file(&self, _file_id: &str) -> Result<(), String> {
+        Self::read_only()
+    }
+}
+
+/// Open the backend selected in the sync settings.
+/// `encrypted` marks uploads as end-to-end encrypted on backends that keep file properties.
+pub async fn connect(app: &AppHandle, settings: &SyncSettings, encrypted: bool) -> Result<Box<dyn RemoteStore>, String> {
+    // Each notebook syncs into a folder of its own
+    let app_folder = config::read_config(app)?.active().remote_folder();
+    match settings.backend.as_str() {
+        BACKEND_LOCAL_FOLDER => {
+            let path = settings.local_folder_path.as_ref()
+                .ok_or("Choose a folder to sync with")?;
+            Ok(Box::new(LocalFolderStore::new(PathBuf::from(path), app_folder)))
+        }
+        BACKEND_WEBDAV => {
+            let url = settings.webdav_url.as_ref()
+                .ok_or("Enter the WebDAV server address")?;
+            let username = settings.webdav_username.clone().unwrap_or_default();
+            let password = webdav::load_password(app)
+                .ok_or("Enter the WebDAV password")?;
+            Ok(Box::new(WebDavStore::new(url, username, password, app_folder)?))
+        }
+        _ => {
+            let mut auth = GoogleAuth::load(app)?;
+            if !auth.is_authenticated() {
+                return Err("Not authenticated with Google".to_string());
+            }
+            let access_token = auth.get_valid_access_token().await?;
+
+            let encrypted = if encrypted { "true" } else { "false" };
+            let device = device::load(app)?;
+            let app_properties = HashMap::from([
+                (ENCRYPTED_PROPERTY.to_string(), encrypted.to_string()),
+                (DEVICE_ID_PROPERTY.to_string(), device.id),
+                (DEVICE_NAME_PROPERTY.to_string(), device.name),
+            ]);
+            Ok(Box::new(
+                DriveClient::new(access_token)
+                    .with_app_properties(app_properties)
+                    .with_app_folder(app_folder)
+                    .with_auth(auth),
+            ))
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use super::drive::{ENTRIES_FOLDER_NAME, IMAGES_FOLDER_NAME};
+use super::store::{RemoteFile, RemoteStore};
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use crate::{config, secrets};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::AppHandle;
+use url::Url;
+
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop><d:getlastmodified/><d:getcontentlength/><d:resourcetype/></d:prop>
+</d:propfind>"#;
+
+/// WebDAV server password (e.g. a Nextcloud app password), kept out of the sync settings
+#[derive(Debug, Serialize, Deserialize)]
+struct WebDavAuth {
+    password: String,
+}
+
+fn get_auth_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(config::get_state_dir(app)?.join("webdav_auth.json"))
+}
+
+pub fn load_password(app: &AppHandle) -> Option<String> {
+    let content = secrets::read(&get_auth_path(app).ok()?)?;
+    let auth: WebDavAuth = serde_json::from_slice(&content).ok()?;
+    Some(auth.password)
+}
+
+pub fn save_password(app: &AppHandle, password: &str) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(&WebDavAuth { password: password.to_string() })
+        .map_err(|e| e.to_string())?;
+    secrets::write(&get_auth_path(app)?, content.as_bytes())
+}
+
+pub fn clear_password(app: &AppHandle) -> Result<(), String> {
+    let path = get_auth_path(app)?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Error for a failed request, with the status and what the common ones mean
+async fn response_error(action: &str, response: Response) -> String {
+    let status = response.status();
+    let cause = match status {
+        StatusCode::UNAUTHORIZED => "the server rejected the username or password".to_string(),
+        StatusCode::NOT_FOUND => "not found on the server".to_string(),
+        StatusCode::INSUFFICIENT_STORAGE => "the server is out of storage space".to_string(),
+        _ => response.text().await.unwrap_or_default(),
+    };
+    format!("{} failed (HTTP {}): {}", action, status, cause)
+}
+
+/// Sync target on a WebDAV server such as Nextcloud. Ids are absolute URLs.
+pub struct WebDavStore {
+    http_client: Client,
+    base_url: Url,
+    username: String,
+    password: String,
+    /// Name of the app folder under `base_url`
+    app_folder: String,
+}
+
+impl WebDavStore {
+    pub fn new(base_url: &str, username: String, password: String, app_folder: String) -> Result<Self, String> {
+        // Treat the address as a folder so relative joins stay inside it
+        let mut base_url = Url::parse(base_url)
+            .map_err(|e| format!("Invalid WebDAV address: {}", e))?;
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        let http_client = Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+            .build()
+            .unwrap_or_else(|_| Client::new());
+
+        Ok(Self {
+            http_client,
+            base_url,
+            username,
+            password,
+            app_folder,
+        })
+    }
+
+    fn request(&self, method: Method, url: &Url) -> RequestBuilder {
+        self.http_client
+            .request(method, url.clone())
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    fn parse_id(&self, id: &str) -> Result<Url, String> {
+        let url = Url::parse(id).map_err(|e| format!("Invalid file id: {}", e))?;
+        if url.origin() != self.base_url.origin() {
+            return Err(format!("File is on another server: {}", id));
+        }
+        Ok(url)
+    }
+
+    fn folder_url(parent: &Url, name: &str) -> Result<Url, String> {
+        parent.join(&format!("{}/", urlencoding::encode(name)))
+            .map_err(|e| e.to_string())
+    }
+
+    fn file_url(folder: &Url, name: &str) -> Result<Url, String> {
+        folder.join(&urlencoding::encode(name))
+            .map_err(|e| e.to_string())
+    }
+
+    async fn make_collection(&self, url: &Url) -> Result<(), String> {
+        let response = self.request(Method::from_bytes(b"MKCOL").unwrap(), url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to create folder: {}", e))?;
+
+        // 405 Method Not Allowed means the folder already exists
+        match response.status() {
+            status if status.is_success() || status == StatusCode::METHOD_NOT_ALLOWED => Ok(()),
+            _ => Err(response_error(&format!("Creating folder {}", url), response).await),
+        }
+    }
+
+    /// PROPFIND the url, returning (file, is_folder) pairs, or None if it does not exist
+    async fn propfind(&self, url: &Url, depth: &str) -> Result<Option<Vec<(RemoteFile, bool)>>, String> {
+        let response = self.request(Method::from_bytes(b"PROPFIND").unwrap(), url)
+            .header("Depth", depth)
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to list files: {}", e))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(response_error("Listing files", response).await);
+        }
+
+        let body = response.text().await
+            .map_err(|e| format!("Failed to read response: {}", e))?;
+        self.parse_multistatus(&body).map(Some)
+    }
+
+    fn parse_multistatus(&self, body: &str) -> Result<Vec<(RemoteFile, bool)>, String> {
+        let doc = roxmltree::Document::parse(body)
+            .map_err(|e| format!("Invalid WebDAV response: {}", e))?;
+
+        let text_of = |node: roxmltree::Node, name: &str| -> Option<String> {
+            node.descendants()
+                .find(|n| n.has_tag_name(("DAV:", name)))
+                .and_then(|n| n.text())
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+        };
+
+        let mut files = Vec::new();
+        for response in doc.descendants().filter(|n| n.has_tag_name(("DAV:", "response"))) {
+            let Some(href) = text_of(response, "href") else { continue };
+            // Hrefs are usually absolute paths; join resolves them against the server
+            let url = self.base_url.join(&href)
+                .map_err(|e| format!("Invalid href in WebDAV response: {}", e))?;
+
+            let is_folder = response.descendants()
+                .any(|n| n.has_tag_name(("DAV:", "collection")));
+
+            let name = url.path_segments()
+                .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+                .map(|s| urlencoding::decode(s).map(|s| s.into_owned()).unwrap_or_else(|_| s.to_string()))
+                .unwrap_or_default();
+
+            let modified_time = text_of(response, "getlastmodified")
+                .and_then(|t| chrono::DateTime::parse_from_rfc2822(&t).ok())
+                .map(|t| t.with_timezone(&chrono::Utc).to_rfc3339());
+
+            files.push((
+                RemoteFile {
+                    id: url.to_string(),
+                    name,
+                    modified_time,
+                    size: text_of(response, "getcontentlength").and_then(|s| s.parse().ok()),
+                    encrypted: None,
+                    checksum: None,
+                    device_id: None,
+                    device_name: None,
+                },
+                is_folder,
+            ));
+        }
+
+        Ok(files)
+    }
+
+    async fn file_at(&self, url: &Url) -> Result<Option<RemoteFile>, String> {
+        let found = self.propfind(url, "0").await?;
+        Ok(found.and_then(|files| {
+            files.into_iter()
+                .find(|(_, is_folder)| !is_folder)
+                .map(|(file, _)| file)
+        }))
+    }
+}
+
+#[async_trait]
+impl RemoteStore for WebDavStore {
+    async fn ensure_folder_structure(&self) -> Result<(String, String, String), String> {
+        let app_folder = Self::folder_url(&self.base_url, &self.app_folder)?;
+        let entries_folder = Self::folder_url(&app_folder, ENTRIES_FOLDER_NAME)?;
+        let images_folder = Self::folder_url(&app_folder, IMAGES_FOLDER_NAME)?;
+
+        for folder in [&app_folder, &entries_folder, &images_folder] {
+            self.make_collection(folder).await?;
+        }
+
+        Ok((app_folder.to_string(), entries_folder.to_string(), images_folder.to_string()))
+    }
+
+    async fn find_folder_structure(&self) -> Result<(Option<String>, Option<String>, Option<String>), String> {
+        let app_folder = Self::folder_url(&self.base_url, &self.app_folder)?;
+        let entries_folder = Self::folder_url(&app_folder, ENTRIES_FOLDER_NAME)?;
+        let images_folder = Self::folder_url(&app_folder, IMAGES_FOLDER_NAME)?;
+
+        let mut found = Vec::new();
+        for folder in [&app_folder, &entries_folder, &images_folder] {
+            let exists = self.propfind(folder, "0").await?.is_some();
+            found.push(exists.then(|| folder.to_string()));
+        }
+        let mut found = found.into_iter();
+        Ok((found.next().flatten(), found.next().flatten(), found.next().flatten()))
+    }
+
+    async fn list_files(&self, folder_id: &str) -> Result<Vec<RemoteFile>, String> {
+        let folder = self.parse_id(folder_id)?;
+        let files = self.propfind(&folder, "1").await?
+            .ok_or_else(|| format!("Folder not found: {}", folder_id))?;
+
+        Ok(files.into_iter()
+            .filter(|(_, is_folder)| !is_folder)
+            .map(|(file, _)| file)
+            .collect())
+    }
+
+    async fn find_file(&self, name: &str, folder_id: &str) -> Result<Option<RemoteFile>, String> {
+        let url = Self::file_url(&self.parse_id(folder_id)?, name)?;
+        self.file_at(&url).await
+    }
+
+    async fn get_file_metadata(&self, file_id: &str) -> Result<RemoteFile, String> {
+        self.file_at(&self.parse_id(file_id)?).await?
+            .ok_or_else(|| format!("File not found: {}", file_id))
+    }
+
+    async fn upload_content(
+        &self,
+        content: &[u8],
+        file_name: &str,
+        folder_id: &str,
+        mime_type: &str,
+        existing_file_id: Option<&str>,
+    ) -> Result<RemoteFile, String> {
+        let url = match existing_file_id {
+            Some(file_id) => self.parse_id(file_id)?,
+            None => Self::file_url(&self.parse_id(folder_id)?, file_name)?,
+        };
+
+        let response = self.request(Method::PUT, &url)
+            .header("Content-Type", mime_type)
+            .body(content.to_vec())
+            .send()
+            .await
+            .map_err(|e| format!("Failed to upload file: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(response_error("Upload", response).await);
+        }
+
+        // PUT returns no metadata, read back the modification time
+        self.file_at(&url).await?
+            .ok_or_else(|| format!("Uploaded file not found: {}", url))
+    }
+
+    async fn download_file(&self, file_id: &str) -> Result<Vec<u8>, String> {
+        let response = self.request(Method::GET, &self.parse_id(file_id)?)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download file: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(response_error("Download", response).await);
+        }
+
+        response.bytes().await
+            .map(|b| b.to_vec())
+            .map_err(|e| format!("Failed to read response: {}", e))
+    }
+
+    async fn rename_file(&self, file_id: &str, new_name: &str) -> Result<RemoteFile, String> {
+        let url = self.parse_id(file_id)?;
+        // A relative name resolves against the file's own folder
+        let destination = Self::file_url(&url, new_name)?;
+
+        let response = self.request(Method::from_bytes(b"MOVE").unwrap(), &url)
+            .header("Destination", destination.as_str())
+            .header("Overwrite", "F")
+            .send()
+            .await
+            .map_err(|e| format!("Failed to rename file: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(response_error("Rename", response).await);
+        }
+
+        self.file_at(&destination).await?
+            .ok_or_else(|| format!("Renamed file not found: {}", destination))
+    }
+
+    async fn delete_file(&self, file_id: &str) -> Result<(), String> {
+        let response = self.request(Method::DELETE, &self.parse_id(file_id)?)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to delete file: {}", e))?;
+
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+            return Err(response_error("Delete", response).await);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/remote.php/dav/files/me/BingoDiary/entries/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/me/BingoDiary/entries/2024-01-31.txt</d:href>
+    <d:propstat><d:prop>
+      <d:getlastmodified>Wed, 31 Jan 2024 20:15:00 GMT</d:getlastmodified>
+      <d:getcontentlength>42</d:getcontentlength>
+      <d:resourcetype/>
+    </d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>https://cloud.example.com/remote.php/dav/files/me/BingoDiary/images/rainy%20day%20%E9%9B%A8.png</d:href>
+    <d:propstat><d:prop><d:resourcetype/></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+    fn store() -> WebDavStore {
+        WebDavStore::new(
+            "https://cloud.example.com/remote.php/dav/files/me",
+            "me".to_string(),
+            "secret".to_string(),
+            "BingoDiary".to_string(),
+        ).unwrap()
+    }
+
+    #[test]
+    fn parses_files_and_folders() {
+        let files = store().parse_multistatus(LISTING).unwrap();
+        assert_eq!(files.len(), 3);
+
+        let (folder, is_folder) = &files[0];
+        assert!(is_folder);
+        assert_eq!(folder.name, "entries");
+
+        let (entry, is_folder) = &files[1];
+        assert!(!is_folder);
+        assert_eq!(entry.id, "https://cloud.example.com/remote.php/dav/files/me/BingoDiary/entries/2024-01-31.txt");
+        assert_eq!(entry.name, "2024-01-31.txt");
+        assert_eq!(entry.size, Some(42));
+        assert_eq!(entry.modified_time.as_deref(), Some("2024-01-31T20:15:00+00:00"));
+        assert_eq!(entry.encrypted, None);
+    }
+
+    #[test]
+    fn decodes_percent_encoded_names() {
+        let files = store().parse_multistatus(LISTING).unwrap();
+        let (image, is_folder) = &files[2];
+        assert!(!is_folder);
+        assert_eq!(image.name, "rainy day 雨.png");
+        assert_eq!(image.size, None);
+        assert_eq!(image.modified_time, None);
+    }
+
+    #[test]
+    fn rejects_invalid_responses() {
+        assert!(store().parse_multistatus("<d:multistatus").is_err());
+        let empty = r#"<d:multistatus xmlns:d="DAV:"><d:response/></d:multistatus>"#;
+        assert!(store().parse_multistatus(empty).unwrap().is_empty());
+    }
+}
@@ -1,43 +1,60 @@
 use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
 use tauri::AppHandle;
 use serde::{Deserialize, Serialize};
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 
 use crate::config::get_diary_dir;
+use crate::storage;
+use crate::vault::Vault;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Tag {
     pub id: String,
     pub name: String,
     pub color: String,
     #[serde(rename = "createdAt")]
     pub created_at: String,
+    #[serde(rename = "updatedAt", default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+impl Tag {
+    /// Time of the last change, used to pick a side when merging
+    fn changed_at(&self) -> &str {
+        self.updated_at.as_deref().unwrap_or(&self.created_at)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct TagsData {
     pub tags: Vec<Tag>,
     #[serde(rename = "entryTags", default)]
     pub entry_tags: HashMap<String, Vec<String>>,
+    /// Date -> when its tags were last set, kept after the tags are cleared
+    #[serde(rename = "entryTagsUpdated", default)]
+    pub entry_tags_updated: HashMap<String, String>,
+    /// Tombstones: deleted tag id -> deletion time
+    #[serde(rename = "deletedTags", default)]
+    pub deleted_tags: HashMap<String, String>,
 }
 
-/// Get the path to tags.json in the diary directory
-pub async fn get_tags_path(app: &AppHandle) -> Result<PathBuf, String> {
-    let diary_dir = get_diary_dir(app).await?;
-    Ok(diary_dir.join("tags.json"))
-}
+/// Deleted tags are remembered at least this long, so devices that sync rarely still
+/// learn of the deletion before the tombstone is dropped
+pub const TOMBSTONE_RETENTION_DAYS: i64 = 30;
 
 /// Read tags data from file
 pub async fn read_tags_data(app: &AppHandle) -> Result<TagsData, String> {
-    let tags_path = get_tags_path(app).await?;
+    let diary_dir = get_diary_dir(app).await?;
+    let tags_path = diary_dir.join("tags.json");
 
     if tags_path.exists() {
-        let content = fs::read_to_string(&tags_path)
-            .map_err(|e| format!("Failed to read tags: {}", e))?;
-        serde_json::from_str(&content)
-            .map_err(|e| format!("Invalid tags format: {}", e))
+        let vault = Vault::open(app, &diary_dir);
+        storage::read_with_recovery(&tags_path, |data| {
+            let content = vault.decrypt(data)
+                .map_err(|e| format!("Failed to read tags: {}", e))?;
+            serde_json::from_slice(&content)
+                .map_err(|e| format!("Invalid tags format: {}", e))
+        })
     } else {
         Ok(TagsData::default())
     }
@@ -45,11 +62,13 @@ pub async fn read_tags_data(app: &AppHandle) -> Result<TagsData, String> {
 
 /// Write tags data to file
 pub async fn write_tags_data(app: &AppHandle, data: &TagsData) -> Result<(), String> {
-    let tags_path = get_tags_path(app).await?;
+    let diary_dir = get_diary_dir(app).await?;
+    let tags_path = diary_dir.join("tags.json");
     let json = serde_json::to_string_pretty(data)
         .map_err(|e| format!("Failed to serialize tags: {}", e))?;
 
-    fs::write(&tags_path, json)
+    let data = Vault::open(app, &diary_dir).encrypt(json.as_bytes())?;
+    storage::write_with_backup(&tags_path, &data)
         .map_err(|e| format!("Failed to write tags: {}", e))?;
 
     Ok(())
@@ -78,6 +97,7 @@ pub async fn create_tag(app: &AppHandle, name: String, color: String) -> Result<
         name,
         color,
         created_at: Utc::now().to_rfc3339(),
+        updated_at: None,
     };
 
     data.tags.push(tag.clone());
@@ -101,6 +121,7 @@ pub async fn update_tag(app: &AppHandle, id: String, name: String, color: String
 
     tag.name = name;
     tag.color = color;
+    tag.updated_at = Some(Utc::now().to_rfc3339());
 
     let updated_tag = tag.clone();
     write_tags_data(app, &data).await?;
@@ -119,6 +140,10 @@ pub async fn delete_tag(app: &AppHandle, id: String) -> Result<(), String> {
     for tag_ids in data.entry_tags.values_mut() {
         tag_ids.retain(|tid| tid != &id);
     }
+    data.entry_tags.retain(|_, tag_ids| !tag_ids.is_empty());
+
+    // Keep a tombstone so sync removes the tag on other devices too
+    data.deleted_tags.insert(id, Utc::now().to_rfc3339());
 
     write_tags_data(app, &data).await?;
 
@@ -142,6 +167,7 @@ pub async fn set_entry_tags(app: &AppHandle, date: String, tag_ids: Vec<String>)
         }
     }
 
+    data.entry_tags_updated.insert(date.clone(), Utc::now().to_rfc3339());
     if tag_ids.is_empty() {
         data.entry_tags.remove(&date);
     } else {
@@ -153,6 +179,91 @@ pub async fn set_entry_tags(app: &AppHandle, date: String, tag_ids: Vec<String>)
     Ok(())
 }
 
+/// Merge tags data changed on two devices.
+/// Tags are combined by id (newest edit wins), deleted tags stay deleted, and each
+/// date keeps the tags set most recently; dates set at the same time get both sides' tags.
+pub fn merge_tags_data(local: TagsData, remote: TagsData) -> TagsData {
+    let mut deleted_tags = local.deleted_tags;
+    for (id, deleted_at) in remote.deleted_tags {
+        let entry = deleted_tags.entry(id).or_default();
+        if deleted_at > *entry {
+            *entry = deleted_at;
+        }
+    }
+
+    let mut tags = local.tags;
+    for remote_tag in remote.tags {
+        match tags.iter_mut().find(|t| t.id == remote_tag.id) {
+            Some(tag) => {
+                if remote_tag.changed_at() > tag.changed_at() {
+                    *tag = remote_tag;
+                }
+            }
+            None => tags.push(remote_tag),
+        }
+    }
+    tags.retain(|t| !deleted_tags.contains_key(&t.id));
+
+    let dates: std::collections::HashSet<&String> = local.entry_tags.keys()
+        .chain(local.entry_tags_updated.keys())
+        .chain(remote.entry_tags.keys())
+        .chain(remote.entry_tags_updated.keys())
+        .collect();
+
+    let mut entry_tags = HashMap::new();
+    let mut entry_tags_updated = HashMap::new();
+
+    for date in dates {
+        let local_ids = local.entry_tags.get(date).cloned().unwrap_or_default();
+        let remote_ids = remote.entry_tags.get(date).cloned().unwrap_or_default();
+        // Dates from before timestamps were recorded compare as oldest
+        let local_updated = local.entry_tags_updated.get(date).cloned().unwrap_or_default();
+        let remote_updated = remote.entry_tags_updated.get(date).cloned().unwrap_or_default();
+
+        let (mut ids, updated) = if local_updated > remote_updated {
+            (local_ids, local_updated)
+        } else if remote_updated > local_updated {
+            (remote_ids, remote_updated)
+        } else {
+            let mut ids = local_ids;
+            for id in remote_ids {
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            (ids, local_updated)
+        };
+
+        ids.retain(|id| !deleted_tags.contains_key(id));
+        if !ids.is_empty() {
+            entry_tags.insert(date.clone(), ids);
+        }
+        if !updated.is_empty() {
+            entry_tags_updated.insert(date.clone(), updated);
+        }
+    }
+
+    TagsData {
+        tags,
+        entry_tags,
+        entry_tags_updated,
+        deleted_tags,
+    }
+}
+
+/// Forget tag deletions older than the retention period that every known device has
+/// synced since, given the oldest last sync among them. Returns whether any were dropped.
+pub fn expire_tombstones(data: &mut TagsData, oldest_sync: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    let cutoff = oldest_sync.min(now - Duration::days(TOMBSTONE_RETENTION_DAYS));
+    let before = data.deleted_tags.len();
+    data.deleted_tags.retain(|_, deleted_at| {
+        DateTime::parse_from_rfc3339(deleted_at)
+            .map(|deleted_at| deleted_at >= cutoff)
+            .unwrap_or(true)
+    });
+    data.deleted_tags.len() != before
+}
+
 /// Get tags for a diary entry
 pub async fn get_entry_tags(app: &AppHandle, date: String) -> Result<Vec<Tag>, String> {
     let data = read_tags_data(app).await?;
@@ -216,3 +327,84 @@ pub async fn get_tag_stats(app: &AppHandle) -> Result<Vec<TagStat>, String> {
 
     Ok(stats)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(id: &str, name: &str, changed_at: &str) -> Tag {
+        Tag {
+            id: id.to_string(),
+            name: name.to_string(),
+            color: "#000000".to_string(),
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            updated_at: Some(changed_at.to_string()),
+        }
+    }
+
+    fn entry(data: &mut TagsData, date: &str, ids: &[&str], updated: &str) {
+        data.entry_tags.insert(date.to_string(), ids.iter().map(|id| id.to_string()).collect());
+        data.entry_tags_updated.insert(date.to_string(), updated.to_string());
+    }
+
+    #[test]
+    fn newest_tag_edit_wins() {
+        let local = TagsData { tags: vec![tag("t1", "old", "2024-01-02T00:00:00+00:00")], ..Default::default() };
+        let remote = TagsData {
+            tags: vec![tag("t1", "new", "2024-01-03T00:00:00+00:00"), tag("t2", "other", "2024-01-01T00:00:00+00:00")],
+            ..Default::default()
+        };
+        let merged = merge_tags_data(local, remote);
+        let names: Vec<&str> = merged.tags.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["new", "other"]);
+    }
+
+    #[test]
+    fn deleted_tags_stay_deleted() {
+        let mut local = TagsData { tags: vec![tag("t1", "kept", "2024-01-01T00:00:00+00:00")], ..Default::default() };
+        entry(&mut local, "2024-01-05", &["t1", "t2"], "2024-01-05T00:00:00+00:00");
+        let mut remote = TagsData::default();
+        remote.deleted_tags.insert("t1".to_string(), "2024-01-06T00:00:00+00:00".to_string());
+
+        let merged = merge_tags_data(local, remote);
+        assert!(merged.tags.is_empty());
+        assert_eq!(merged.entry_tags["2024-01-05"], ["t2"]);
+        assert!(merged.deleted_tags.contains_key("t1"));
+    }
+
+    #[test]
+    fn entry_tags_set_last_win() {
+        let mut local = TagsData::default();
+        entry(&mut local, "2024-01-05", &["a"], "2024-01-06T00:00:00+00:00");
+        entry(&mut local, "2024-01-07", &["a"], "2024-01-07T00:00:00+00:00");
+        let mut remote = TagsData::default();
+        entry(&mut remote, "2024-01-05", &["b"], "2024-01-05T00:00:00+00:00");
+        entry(&mut remote, "2024-01-07", &["b"], "2024-01-07T00:00:00+00:00");
+        // Cleared on the remote side after the local edit
+        remote.entry_tags_updated.insert("2024-01-08".to_string(), "2024-01-09T00:00:00+00:00".to_string());
+        entry(&mut local, "2024-01-08", &["a"], "2024-01-08T00:00:00+00:00");
+
+        let merged = merge_tags_data(local, remote);
+        assert_eq!(merged.entry_tags["2024-01-05"], ["a"]);
+        // Set at the same time on both sides: both are kept
+        assert_eq!(merged.entry_tags["2024-01-07"], ["a", "b"]);
+        assert!(!merged.entry_tags.contains_key("2024-01-08"));
+        assert_eq!(merged.entry_tags_updated["2024-01-08"], "2024-01-09T00:00:00+00:00");
+    }
+
+    #[test]
+    fn tombstones_expire_once_old_and_synced_by_all() {
+        let now = DateTime::parse_from_rfc3339("2024-03-01T00:00:00+00:00").unwrap().with_timezone(&Utc);
+        let mut data = TagsData::default();
+        data.deleted_tags.insert("old".to_string(), "2024-01-01T00:00:00+00:00".to_string());
+        data.deleted_tags.insert("recent".to_string(), "2024-02-25T00:00:00+00:00".to_string());
+
+        // A device that hasn't synced since the deletion keeps the tombstone
+        let mut unsynced = data.clone();
+        let oldest_sync = DateTime::parse_from_rfc3339("2023-12-31T00:00:00+00:00").unwrap().with_timezone(&Utc);
+        assert!(!expire_tombstones(&mut unsynced, oldest_sync, now));
+
+        assert!(expire_tombstones(&mut data, now, now));
+        assert_eq!(data.deleted_tags.keys().collect::<Vec<_>>(), ["recent"]);
+    }
+}
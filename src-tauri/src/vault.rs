@@ -0,0 +1,623 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use serde::{Deserialize, Serialize};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chacha20::cipher::{KeyIvInit, StreamCipher as _, StreamCipherSeek};
+use chacha20::XChaCha20;
+use poly1305::universal_hash::UniversalHash;
+use poly1305::Poly1305;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::storage;
+
+/// Vault header stored in the diary directory. Its presence turns on encryption.
+pub const VAULT_FILE: &str = "vault.json";
+
+/// Prefix of every encrypted file, followed by a 24-byte nonce and the ciphertext
+const MAGIC: &[u8; 8] = b"BDVAULT1";
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+/// Bytes added by `seal`: magic, nonce and the Poly1305 tag
+pub const SEAL_OVERHEAD: usize = 8 + NONCE_LEN + 16;
+const HEADER_LEN: usize = 8 + NONCE_LEN;
+const TAG_LEN: usize = 16;
+
+/// Piece size of files encrypted or decrypted without reading them whole. A multiple
+/// of the Poly1305 block size, so that only the last piece gets padded.
+const STREAM_CHUNK: usize = 64 * 1024;
+
+// Argon2id parameters (OWASP recommended minimum)
+const KDF_MEMORY_KIB: u32 = 19456;
+const KDF_ITERATIONS: u32 = 2;
+const KDF_PARALLELISM: u32 = 1;
+
+pub type VaultKey = [u8; KEY_LEN];
+
+/// Password-wrapped data key. Also used for the sync encryption header on Drive.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultHeader {
+    version: u32,
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    /// Random data key, encrypted with the password-derived key
+    wrapped_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VaultStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+}
+
+/// Unlocked data key, kept in memory only
+#[derive(Default)]
+pub struct VaultState {
+    key: Mutex<Option<VaultKey>>,
+}
+
+/// Handle used to read and write diary files, encrypting them when the vault is enabled
+#[derive(Clone)]
+pub struct Vault {
+    enabled: bool,
+    key: Option<VaultKey>,
+}
+
+impl Vault {
+    pub fn open(app: &AppHandle, diary_dir: &Path) -> Self {
+        let key = app.try_state::<VaultState>()
+            .and_then(|state| *state.key.lock().unwrap());
+        Self {
+            enabled: is_enabled(diary_dir),
+            key,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Encryption is enabled but the key has not been unlocked yet
+    pub fn is_locked(&self) -> bool {
+        self.enabled && self.key.is_none()
+    }
+
+    /// Read a file, decrypting it if it is encrypted
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        let data = fs::read(path)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        self.decrypt(&data)
+    }
+
+    pub fn read_to_string(&self, path: &Path) -> Result<String, String> {
+        let data = self.read(path)?;
+        String::from_utf8(data)
+            .map_err(|e| format!("Invalid UTF-8 content: {}", e))
+    }
+
+    /// Write a file, encrypting it if the vault is enabled
+    pub fn write(&self, path: &Path, data: &[u8]) -> Result<(), String> {
+        let data = self.encrypt(data)?;
+        storage::write_atomic(path, &data)
+            .map_err(|e| format!("Failed to write file: {}", e))
+    }
+
+    /// Encrypt data for storage; passes data through when the vault is disabled
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if !self.enabled {
+            return Ok(data.to_vec());
+        }
+        let key = self.key.as_ref().ok_or("Diary is locked")?;
+        seal(key, data)
+    }
+
+    /// Decrypt stored data; plaintext data is returned unchanged
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if !is_encrypted(data) {
+            return Ok(data.to_vec());
+        }
+        let key = self.key.as_ref().ok_or("Diary is locked")?;
+        unseal(key, data)
+    }
+
+    /// Nonce of a sealed file, or None if it is stored as plaintext
+    fn sealed_nonce(file: &mut File) -> Result<Option<[u8; NONCE_LEN]>, String> {
+        let mut header = [0u8; HEADER_LEN];
+        let read = read_full(file, &mut header)?;
+        if !header[..read].starts_with(MAGIC) {
+            return Ok(None);
+        }
+        if read < HEADER_LEN {
+            return Err("Not an encrypted file".to_string());
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&header[MAGIC.len()..]);
+        Ok(Some(nonce))
+    }
+
+    /// Pass the plaintext of a file to `consume` a piece at a time. Sealed files are only
+    /// authenticated at the end, so whatever `consume` made of them is void on an error.
+    fn stream_plaintext(&self, path: &Path, mut consume: impl FnMut(&[u8]) -> Result<(), String>) -> Result<(), String> {
+        let mut file = File::open(path)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        let size = file.metadata()
+            .map_err(|e| format!("Failed to read file: {}", e))?
+            .len();
+        let mut chunk = vec![0u8; STREAM_CHUNK];
+
+        let Some(nonce) = Self::sealed_nonce(&mut file)? else {
+            file.seek(SeekFrom::Start(0))
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+            loop {
+                let read = read_full(&mut file, &mut chunk)?;
+                if read == 0 {
+                    return Ok(());
+                }
+                consume(&chunk[..read])?;
+            }
+        };
+
+        let key = self.key.as_ref().ok_or("Diary is locked")?;
+        let mut stream = StreamCipher::new(key, &nonce);
+        let mut remaining = size.checked_sub(SEAL_OVERHEAD as u64)
+            .ok_or("Not an encrypted file")?;
+        while remaining > 0 {
+            let len = remaining.min(STREAM_CHUNK as u64) as usize;
+            file.read_exact(&mut chunk[..len])
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+            stream.decrypt(&mut chunk[..len]);
+            consume(&chunk[..len])?;
+            remaining -= len as u64;
+        }
+
+        let mut tag = [0u8; TAG_LEN];
+        file.read_exact(&mut tag)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        if !stream.verify(&tag) {
+            return Err("Decryption failed (wrong key or corrupted file)".to_string());
+        }
+        Ok(())
+    }
+
+    /// Hash of a file's plaintext, like `calculate_content_hash` of `read`
+    /// but without holding the file in memory
+    pub fn hash_file(&self, path: &Path) -> Result<String, String> {
+        let mut hasher = Sha256::new();
+        self.stream_plaintext(path, |chunk| {
+            hasher.update(chunk);
+            Ok(())
+        })?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Write the plaintext of `path` to `target` sealed with `key`, a piece at a time.
+    /// Returns the hash of the plaintext.
+    pub fn reseal_file(&self, path: &Path, key: &VaultKey, target: &Path) -> Result<String, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut stream = StreamCipher::new(key, &nonce);
+        let mut hasher = Sha256::new();
+
+        let result = File::create(target)
+            .and_then(|mut out| {
+                out.write_all(MAGIC)?;
+                out.write_all(&nonce)?;
+                Ok(out)
+            })
+            .map_err(|e| format!("Failed to write {}: {}", target.display(), e))
+            .and_then(|mut out| {
+                let mut buffer = Vec::with_capacity(STREAM_CHUNK);
+                self.stream_plaintext(path, |chunk| {
+                    hasher.update(chunk);
+                    buffer.clear();
+                    buffer.extend_from_slice(chunk);
+                    stream.encrypt(&mut buffer);
+                    out.write_all(&buffer)
+                        .map_err(|e| format!("Failed to write {}: {}", target.display(), e))
+                })?;
+                out.write_all(&stream.tag())
+                    .and_then(|_| out.sync_all())
+                    .map_err(|e| format!("Failed to write {}: {}", target.display(), e))
+            });
+        if let Err(e) = result {
+            let _ = fs::remove_file(target);
+            return Err(e);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// Size of a file's plaintext
+    pub fn plaintext_len(&self, path: &Path) -> Result<u64, String> {
+        let mut file = File::open(path)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        let size = file.metadata()
+            .map_err(|e| format!("Failed to read file: {}", e))?
+            .len();
+        match Self::sealed_nonce(&mut file)? {
+            Some(_) => size.checked_sub(SEAL_OVERHEAD as u64).ok_or_else(|| "Not an encrypted file".to_string()),
+            None => Ok(size),
+        }
+    }
+
+    /// Up to `len` bytes of a file's plaintext starting at `offset`. Sealed files can't be
+    /// authenticated piecewise, so check the whole file with `hash_file` first.
+    pub fn read_range(&self, path: &Path, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        let plaintext_len = self.plaintext_len(path)?;
+        let mut file = File::open(path)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        let nonce = Self::sealed_nonce(&mut file)?;
+        let start = offset.min(plaintext_len);
+        let len = (plaintext_len - start).min(len as u64) as usize;
+        let header = if nonce.is_some() { HEADER_LEN as u64 } else { 0 };
+
+        let mut chunk = vec![0u8; len];
+        file.seek(SeekFrom::Start(header + start))
+            .and_then(|_| file.read_exact(&mut chunk))
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        if let Some(nonce) = nonce {
+            let key = self.key.as_ref().ok_or("Diary is locked")?;
+            StreamCipher::new(key, &nonce).apply_at(start, &mut chunk);
+        }
+        Ok(chunk)
+    }
+}
+
+/// Fill `buf` as far as the file goes, returning how many bytes were read
+fn read_full(file: &mut File, buf: &mut [u8]) -> Result<usize, String> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(format!("Failed to read file: {}", e)),
+        }
+    }
+    Ok(read)
+}
+
+/// The XChaCha20-Poly1305 of `seal` and `unseal`, applied a piece at a time.
+/// Every piece but the last must be a multiple of 16 bytes.
+struct StreamCipher {
+    cipher: XChaCha20,
+    mac: Poly1305,
+    len: u64,
+}
+
+impl StreamCipher {
+    /// ChaCha20 block whose keystream encrypts the first byte; block 0 keys Poly1305
+    const FIRST_BLOCK: u64 = 64;
+
+    fn new(key: &VaultKey, nonce: &[u8; NONCE_LEN]) -> Self {
+        let mut cipher = XChaCha20::new(Key::from_slice(key), XNonce::from_slice(nonce));
+        let mut mac_key = poly1305::Key::default();
+        cipher.apply_keystream(&mut mac_key);
+        cipher.seek(Self::FIRST_BLOCK);
+        Self {
+            cipher,
+            mac: Poly1305::new(&mac_key),
+            len: 0,
+        }
+    }
+
+    fn encrypt(&mut self, chunk: &mut [u8]) {
+        self.cipher.apply_keystream(chunk);
+        self.mac.update_padded(chunk);
+        self.len += chunk.len() as u64;
+    }
+
+    fn decrypt(&mut self, chunk: &mut [u8]) {
+        self.mac.update_padded(chunk);
+        self.cipher.apply_keystream(chunk);
+        self.len += chunk.len() as u64;
+    }
+
+    /// Decrypt (or encrypt) bytes at `offset` of the plaintext, without authentication
+    fn apply_at(mut self, offset: u64, chunk: &mut [u8]) {
+        self.cipher.seek(Self::FIRST_BLOCK + offset);
+        self.cipher.apply_keystream(chunk);
+    }
+
+    /// Tag over the ciphertext so far, with no associated data
+    fn tag(mut self) -> [u8; TAG_LEN] {
+        let mut lengths = poly1305::Block::default();
+        lengths[8..].copy_from_slice(&self.len.to_le_bytes());
+        self.mac.update(&[lengths]);
+        self.mac.finalize().into()
+    }
+
+    fn verify(mut self, tag: &[u8; TAG_LEN]) -> bool {
+        let mut lengths = poly1305::Block::default();
+        lengths[8..].copy_from_slice(&self.len.to_le_bytes());
+        self.mac.update(&[lengths]);
+        self.mac.verify(tag.into()).is_ok()
+    }
+}
+
+pub fn is_enabled(diary_dir: &Path) -> bool {
+    diary_dir.join(VAULT_FILE).exists()
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn get_status(app: &AppHandle, diary_dir: &Path) -> VaultStatus {
+    let vault = Vault::open(app, diary_dir);
+    VaultStatus {
+        enabled: vault.enabled,
+        unlocked: vault.key.is_some(),
+    }
+}
+
+/// Derive the password key and unwrap the data key into memory
+pub fn unlock(app: &AppHandle, diary_dir: &Path, password: &str) -> Result<(), String> {
+    let header = read_header(diary_dir)?;
+    let key = unwrap_key(&header, password)?;
+    set_key(app, Some(key));
+    Ok(())
+}
+
+/// Forget the in-memory data key
+pub fn lock(app: &AppHandle) {
+    set_key(app, None);
+}
+
+/// Re-wrap the unlocked data key with a new password
+pub fn change_password(app: &AppHandle, diary_dir: &Path, new_password: &str) -> Result<(), String> {
+    let key = Vault::open(app, diary_dir).key
+        .ok_or("Unlock the diary before changing the password")?;
+    write_header(diary_dir, &wrap_key(&key, new_password)?)
+}
+
+/// Encrypt (or decrypt) every entry, tags.json and image in the diary directory.
+/// Files already in the target state are skipped, so an interrupted run can be repeated.
+pub fn migrate(app: &AppHandle, diary_dir: &Path, password: &str, encrypt: bool) -> Result<usize, String> {
+    if encrypt {
+        if !is_enabled(diary_dir) {
+            write_header(diary_dir, &wrap_key(&generate_key(), password)?)?;
+        }
+        unlock(app, diary_dir, password)?;
+    } else {
+        if !is_enabled(diary_dir) {
+            return Ok(0);
+        }
+        unlock(app, diary_dir, password)?;
+    }
+
+    let key = Vault::open(app, diary_dir).key.ok_or("Diary is locked")?;
+    let mut migrated = 0;
+
+    for path in collect_vault_files(diary_dir)? {
+        let data = fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        let converted = if encrypt && !is_encrypted(&data) {
+            seal(&key, &data)?
+        } else if !encrypt && is_encrypted(&data) {
+            unseal(&key, &data)?
+        } else {
+            continue;
+        };
+
+        storage::write_atomic(&path, &converted)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        migrated += 1;
+    }
+
+    if !encrypt {
+        fs::remove_file(diary_dir.join(VAULT_FILE))
+            .map_err(|e| format!("Failed to remove vault header: {}", e))?;
+        let _ = fs::remove_file(storage::backup_path(&diary_dir.join(VAULT_FILE)));
+        lock(app);
+    }
+
+    Ok(migrated)
+}
+
+/// Entries, tags.json, images, conflict copies and entry history - the files covered by the vault
+fn collect_vault_files(diary_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(diary_dir).map_err(|e| format!("Failed to read directory: {}", e))? {
+        let path = entry.map_err(|e| format!("Failed to read entry: {}", e))?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let is_entry = crate::diary::is_entry_file(&path);
+        let is_tags = path.file_name().map(|n| n == "tags.json" || n == "tags.json.bak").unwrap_or(false);
+        if is_entry || is_tags {
+            files.push(path);
+        }
+    }
+
+    for subdir in ["images", crate::sync::engine::CONFLICTS_DIR] {
+        let dir = diary_dir.join(subdir);
+        if !dir.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir).map_err(|e| format!("Failed to read {} directory: {}", subdir, e))? {
+            let path = entry.map_err(|e| format!("Failed to read entry: {}", e))?.path();
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+
+    let history_dir = diary_dir.join(crate::history::HISTORY_DIR);
+    if history_dir.exists() {
+        for entry in walkdir::WalkDir::new(&history_dir).min_depth(2).max_depth(2) {
+            let entry = entry.map_err(|e| format!("Failed to read history directory: {}", e))?;
+            if entry.file_type().is_file() {
+                files.push(entry.into_path());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn set_key(app: &AppHandle, key: Option<VaultKey>) {
+    if let Some(state) = app.try_state::<VaultState>() {
+        *state.key.lock().unwrap() = key;
+    }
+}
+
+fn read_header(diary_dir: &Path) -> Result<VaultHeader, String> {
+    // Losing the header means losing the key to every entry, so it is backed up on each change
+    storage::read_with_recovery(&diary_dir.join(VAULT_FILE), |data| {
+        serde_json::from_slice(data)
+            .map_err(|e| format!("Invalid vault header: {}", e))
+    })
+}
+
+fn write_header(diary_dir: &Path, header: &VaultHeader) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(header)
+        .map_err(|e| format!("Failed to serialize vault header: {}", e))?;
+    let path = diary_dir.join(VAULT_FILE);
+    storage::write_atomic(&path, json.as_bytes())
+        .map_err(|e| format!("Failed to write vault header: {}", e))?;
+    // The backup gets the same header: a copy of the previous one would still open
+    // with the old password after it was changed
+    storage::write_atomic(&storage::backup_path(&path), json.as_bytes())
+        .map_err(|e| format!("Failed to write vault header backup: {}", e))
+}
+
+fn derive_key(password: &str, salt: &[u8], memory_kib: u32, iterations: u32, parallelism: u32) -> Result<VaultKey, String> {
+    let params = Params::new(memory_kib, iterations, parallelism, Some(KEY_LEN))
+        .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+    let mut key = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+pub fn generate_key() -> VaultKey {
+    let mut key = [0u8; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+pub fn wrap_key(key: &VaultKey, password: &str) -> Result<VaultHeader, String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let kek = derive_key(password, &salt, KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM)?;
+
+    Ok(VaultHeader {
+        version: 1,
+        salt: BASE64.encode(salt),
+        memory_kib: KDF_MEMORY_KIB,
+        iterations: KDF_ITERATIONS,
+        parallelism: KDF_PARALLELISM,
+        wrapped_key: BASE64.encode(seal(&kek, key)?),
+    })
+}
+
+pub fn unwrap_key(header: &VaultHeader, password: &str) -> Result<VaultKey, String> {
+    let salt = BASE64.decode(&header.salt)
+        .map_err(|e| format!("Invalid vault salt: {}", e))?;
+    let wrapped = BASE64.decode(&header.wrapped_key)
+        .map_err(|e| format!("Invalid vault key: {}", e))?;
+    let kek = derive_key(password, &salt, header.memory_kib, header.iterations, header.parallelism)?;
+
+    let key = unseal(&kek, &wrapped).map_err(|_| "Incorrect password".to_string())?;
+    key.try_into().map_err(|_| "Invalid vault key length".to_string())
+}
+
+/// Encrypt with XChaCha20-Poly1305: MAGIC || nonce || ciphertext
+pub fn seal(key: &VaultKey, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|_| "Encryption failed".to_string())?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+pub fn unseal(key: &VaultKey, data: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_encrypted(data) || data.len() < MAGIC.len() + NONCE_LEN {
+        return Err("Not an encrypted file".to_string());
+    }
+    let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+
+    XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Decryption failed (wrong key or corrupted file)".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault(key: VaultKey) -> Vault {
+        Vault { enabled: true, key: Some(key) }
+    }
+
+    // Spans several pieces, with a last one that isn't a multiple of the block size
+    fn content() -> Vec<u8> {
+        (0..STREAM_CHUNK * 2 + 1001).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn streamed_files_match_seal() {
+        let dir = tempfile::tempdir().unwrap();
+        let (key, sync_key) = (generate_key(), generate_key());
+        let content = content();
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&sealed_path, seal(&key, &content).unwrap()).unwrap();
+
+        let vault = vault(key);
+        assert_eq!(vault.hash_file(&sealed_path).unwrap(), hex::encode(Sha256::digest(&content)));
+        assert_eq!(vault.plaintext_len(&sealed_path).unwrap(), content.len() as u64);
+        assert_eq!(vault.read_range(&sealed_path, 70_000, 5000).unwrap(), &content[70_000..75_000]);
+
+        let resealed_path = dir.path().join("resealed");
+        vault.reseal_file(&sealed_path, &sync_key, &resealed_path).unwrap();
+        assert_eq!(unseal(&sync_key, &fs::read(&resealed_path).unwrap()).unwrap(), content);
+    }
+
+    #[test]
+    fn plaintext_files_pass_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = content();
+        let path = dir.path().join("plain");
+        fs::write(&path, &content).unwrap();
+
+        let vault = vault(generate_key());
+        assert_eq!(vault.plaintext_len(&path).unwrap(), content.len() as u64);
+        assert_eq!(vault.read_range(&path, 10, 20).unwrap(), &content[10..30]);
+        assert_eq!(vault.hash_file(&path).unwrap(), hex::encode(Sha256::digest(&content)));
+    }
+
+    #[test]
+    fn tampered_files_fail_to_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = generate_key();
+        let mut sealed = seal(&key, &content()).unwrap();
+        sealed[100] ^= 1;
+        let path = dir.path().join("sealed");
+        fs::write(&path, sealed).unwrap();
+
+        let target = dir.path().join("resealed");
+        assert!(vault(key).reseal_file(&path, &generate_key(), &target).is_err());
+        assert!(!target.exists());
+    }
+}
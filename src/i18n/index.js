@@ -22,6 +22,7 @@ const translations = {
     'settings.tabs.general': 'General',
     'settings.tabs.sync': 'Sync',
     'settings.tabs.shortcuts': 'Shortcuts',
+    'settings.tabs.notebooks': 'Notebooks',
 
     // General Settings
     'settings.language': 'Language',
@@ -137,6 +138,20 @@ const translations = {
     'event.newCategory': 'New category',
     'event.event': 'Event',
 
+    // Notebooks
+    'notebooks.title': 'Notebooks',
+    'notebooks.active': 'Active',
+    'notebooks.switch': 'Switch',
+    'notebooks.create': 'New Notebook',
+    'notebooks.namePlaceholder': 'Notebook name',
+    'notebooks.defaultDir': 'Default location',
+    'notebooks.pickDir': 'Choose a folder for the notebook',
+    'notebooks.remoteFolder': 'Remote folder',
+    'notebooks.remoteFolderPlaceholder': 'Remote folder (optional)',
+    'notebooks.remoteFolderHint': 'To sync a notebook created on another device, enter the remote folder shown there.',
+    'notebooks.deleteConfirm': 'Delete the notebook "{name}"?',
+    'notebooks.deleteEntries': 'Also delete its entries, images and history',
+
     // Sync
     'sync.googleCloudSetup': 'Google Cloud Setup',
     'sync.setupInfo': 'To enable sync, you need to create a Google Cloud project and get credentials.',
@@ -159,7 +174,8 @@ const translations = {
     'sync.clickToLogin': 'Click here to login Google',
     'sync.copyLink': 'Copy Link',
     'sync.codeHint': 'After logging in, browser will show "cannot access".<br>Copy the full URL from address bar and paste below:',
-    'sync.pasteCodePlaceholder': 'Paste link or auth code',
+    'sync.pasteCodePlaceholder': 'Paste the full link',
+    'sync.linkMissingState': 'Paste the full link from the address bar, not only the code',
     'sync.verifying': 'Verifying...',
     'sync.submit': 'Submit',
     'sync.manualSync': 'Sync',
@@ -175,7 +191,23 @@ const translations = {
     'sync.stageDiaryEntries': 'Diary Entries',
     'sync.stageTags': 'Tags',
     'sync.stageImages': 'Images',
+    'sync.scopeTitle': 'What to Sync',
+    'sync.scope': 'Sync',
+    'sync.scopeEntries': 'Entries only',
+    'sync.scopeEntriesTags': 'Entries and tags',
+    'sync.scopeAll': 'Entries, tags and images',
+    'sync.scopeImagesHint': 'Images are downloaded when an entry that shows them is opened.',
+    'sync.downloadWindow': 'Download entries from the last (days)',
+    'sync.downloadWindowAll': 'All',
+    'sync.downloadWindowHint': 'Older entries stay on the sync target. Leave empty to download everything.',
     'sync.advancedSettings': 'Advanced Settings',
+    'conflicts.title': 'Conflict Copies',
+    'conflicts.hint': 'Versions that could not be merged during sync or a move. Use one to replace the current version, or keep the current one to delete the copy.',
+    'conflicts.origin': 'From {origin}',
+    'conflicts.compare': 'Compare',
+    'conflicts.hideDiff': 'Hide',
+    'conflicts.useCopy': 'Use this version',
+    'conflicts.keepCurrent': 'Keep current',
     'sync.currentClientId': 'Current Client ID',
     'sync.changeClientId': 'Change Client ID',
     'sync.linkCopied': 'Link copied to clipboard',
@@ -223,6 +255,17 @@ const translations = {
     // Alerts
     'alert.settingsSaved': 'Settings saved!',
     'alert.storageChanged': 'Storage location changed. Please restart the app.',
+    'storage.chooseMode': 'What should happen to your diary files?',
+    'storage.move': 'Move',
+    'storage.moveHint': 'Move the diary files to the new folder and remove them here',
+    'storage.copy': 'Copy',
+    'storage.copyHint': 'Copy the diary files to the new folder and keep this one as it is',
+    'storage.point': 'Switch only',
+    'storage.pointHint': 'Use the new folder as it is, without copying anything',
+    'storage.progress': 'Copying {current} of {total}',
+    'storage.changed': 'Storage location changed.',
+    'storage.conflicts': '{count} files already existed in the new folder with different content; those versions were saved to the conflicts folder.',
+    'storage.failed': 'Failed to change path: {error}',
     'alert.passwordRequired': 'Please enter a password',
     'alert.passwordMismatch': 'Passwords do not match',
     'alert.passwordSet': 'Password set successfully!',
@@ -257,6 +300,7 @@ const translations = {
     'settings.tabs.general': '通用',
     'settings.tabs.sync': '同步',
     'settings.tabs.shortcuts': '快捷键',
+    'settings.tabs.notebooks': '日记本',
 
     // General Settings
     'settings.language': '语言',
@@ -372,6 +416,20 @@ const translations = {
     'event.newCategory': '新分类',
     'event.event': '事件',
 
+    // Notebooks
+    'notebooks.title': '日记本',
+    'notebooks.active': '当前',
+    'notebooks.switch': '切换',
+    'notebooks.create': '新建日记本',
+    'notebooks.namePlaceholder': '日记本名称',
+    'notebooks.defaultDir': '默认位置',
+    'notebooks.pickDir': '选择日记本文件夹',
+    'notebooks.remoteFolder': '远程文件夹',
+    'notebooks.remoteFolderPlaceholder': '远程文件夹（可选）',
+    'notebooks.remoteFolderHint': '要同步在其他设备上创建的日记本，请输入那里显示的远程文件夹。',
+    'notebooks.deleteConfirm': '删除日记本“{name}”？',
+    'notebooks.deleteEntries': '同时删除其日记、图片和历史记录',
+
     // Sync
     'sync.googleCloudSetup': 'Google Cloud 设置',
     'sync.setupInfo': '要启用同步，需要创建 Google Cloud 项目并获取凭据。',
@@ -394,7 +452,8 @@ const translations = {
     'sync.clickToLogin': '点击这里登录 Google',
     'sync.copyLink': '复制链接',
     'sync.codeHint': '登录 Google 后，浏览器会显示"无法访问此网站"。<br>请复制地址栏中的完整链接，粘贴到下方：',
-    'sync.pasteCodePlaceholder': '粘贴链接或授权码',
+    'sync.pasteCodePlaceholder': '粘贴完整链接',
+    'sync.linkMissingState': '请粘贴地址栏中的完整链接，而不仅是授权码',
     'sync.verifying': '验证中...',
     'sync.submit': '提交',
     'sync.manualSync': '同步',
@@ -410,7 +469,23 @@ const translations = {
     'sync.stageDiaryEntries': '日记条目',
     'sync.stageTags': '标签',
     'sync.stageImages': '图片',
+    'sync.scopeTitle': '同步内容',
+    'sync.scope': '同步',
+    'sync.scopeEntries': '仅日记',
+    'sync.scopeEntriesTags': '日记和标签',
+    'sync.scopeAll': '日记、标签和图片',
+    'sync.scopeImagesHint': '打开包含图片的日记时才会下载图片。',
+    'sync.downloadWindow': '下载最近多少天的日记',
+    'sync.downloadWindowAll': '全部',
+    'sync.downloadWindowHint': '更早的日记保留在同步目标上。留空则下载全部。',
     'sync.advancedSettings': '高级设置',
+    'conflicts.title': '冲突副本',
+    'conflicts.hint': '同步或移动时无法合并的版本。可以用它替换当前版本，或保留当前版本并删除副本。',
+    'conflicts.origin': '来自 {origin}',
+    'conflicts.compare': '对比',
+    'conflicts.hideDiff': '隐藏',
+    'conflicts.useCopy': '使用此版本',
+    'conflicts.keepCurrent': '保留当前版本',
     'sync.currentClientId': '当前 Client ID',
     'sync.changeClientId': '更改 Client ID',
     'sync.linkCopied': '链接已复制到剪贴板',
@@ -454,6 +529,17 @@ const translations = {
     // Alerts
     'alert.settingsSaved': '设置已保存！',
     'alert.storageChanged': '存储位置已更改。请重启应用。',
+    'storage.chooseMode': '如何处理日记文件？',
+    'storage.move': '移动',
+    'storage.moveHint': '将日记文件移动到新文件夹，并从此处删除',
+    'storage.copy': '复制',
+    'storage.copyHint': '将日记文件复制到新文件夹，保留此文件夹不变',
+    'storage.point': '仅切换',
+    'storage.pointHint': '直接使用新文件夹，不复制任何文件',
+    'storage.progress': '正在复制 {current} / {total}',
+    'storage.changed': '存储位置已更改。',
+    'storage.conflicts': '新文件夹中已有 {count} 个内容不同的文件；这些版本已保存到冲突文件夹。',
+    'storage.failed': '更改路径失败：{error}',
     'alert.passwordRequired': '请输入密码',
     'alert.passwordMismatch': '两次密码不一致',
     'alert.passwordSet': '密码设置成功！',